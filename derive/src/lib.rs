@@ -0,0 +1,294 @@
+//! Procedural macros for [panic-safe](https://docs.rs/panic-safe/), which are
+//! re-exported by it with the `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Expr, Fields, Ident, ItemFn, MetaNameValue, ReturnType,
+    Token, Type,
+};
+
+/// Derives `From<AllocError>` for an error enum, or for a struct with a single
+/// field.
+///
+/// The allocation error is converted into the variant marked with
+/// `#[alloc_error]`, or into the only variant with a single field of type
+/// `AllocError` if none is marked. The field of the variant is converted from the
+/// `AllocError` by `From`, so it can also be e.g. `Box<AllocError>` or
+/// `std::io::Error`.
+///
+/// ```
+/// use panic_safe::{AllocError, FromAllocError};
+/// # struct Index;
+/// # impl Index { fn build(_: &[u8]) -> Self { Index } }
+///
+/// #[derive(Debug, FromAllocError)]
+/// enum Error {
+///     Io(std::io::Error),
+///     OutOfMemory(AllocError),
+/// }
+///
+/// fn build(input: &[u8]) -> Result<Index, Error> {
+///     let index = panic_safe::catch_oom(|| Index::build(input))?;
+///     Ok(index)
+/// }
+/// # assert!(build(b"input").is_ok());
+/// ```
+#[proc_macro_derive(FromAllocError, attributes(alloc_error))]
+pub fn derive_from_alloc_error(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_alloc_error(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn from_alloc_error(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let (variant, fields) = match &input.data {
+        Data::Struct(data) => (None, &data.fields),
+        Data::Enum(data) => {
+            let marked: Vec<_> = data
+                .variants
+                .iter()
+                .filter(|v| v.attrs.iter().any(|a| a.path().is_ident("alloc_error")))
+                .collect();
+            let variant = match marked.as_slice() {
+                [variant] => *variant,
+                [] => {
+                    let candidates: Vec<_> = data
+                        .variants
+                        .iter()
+                        .filter(|v| v.fields.len() == 1 && v.fields.iter().all(|f| is_alloc_error(&f.ty)))
+                        .collect();
+                    match candidates.as_slice() {
+                        [variant] => *variant,
+                        _ => {
+                            return Err(Error::new(
+                                Span::call_site(),
+                                "mark the variant of the allocation error with `#[alloc_error]`",
+                            ))
+                        }
+                    }
+                }
+                [_, second, ..] => {
+                    return Err(Error::new_spanned(
+                        &second.ident,
+                        "only one variant can be marked with `#[alloc_error]`",
+                    ))
+                }
+            };
+            (Some(&variant.ident), &variant.fields)
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                Span::call_site(),
+                "`FromAllocError` cannot be derived for unions",
+            ))
+        }
+    };
+    if fields.len() != 1 {
+        let target = variant.map_or(&input.ident, |v| v);
+        return Err(Error::new_spanned(
+            target,
+            "the allocation error must be converted into a single field",
+        ));
+    }
+
+    let name = &input.ident;
+    let path = match variant {
+        Some(variant) => quote!(#name::#variant),
+        None => quote!(#name),
+    };
+    let value = quote!(::core::convert::From::from(e));
+    let body = match fields {
+        Fields::Named(fields) => {
+            let field = fields.named.first().and_then(|f| f.ident.as_ref());
+            quote!(#path { #field: #value })
+        }
+        _ => quote!(#path(#value)),
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::core::convert::From<::panic_safe::AllocError> for #name #ty_generics #where_clause {
+            #[inline]
+            fn from(e: ::panic_safe::AllocError) -> Self {
+                #body
+            }
+        }
+    })
+}
+
+/// Checks if `ty` names `AllocError`, e.g. `AllocError` or `panic_safe::AllocError`.
+fn is_alloc_error(ty: &Type) -> bool {
+    match ty {
+        Type::Path(ty) => ty
+            .path
+            .segments
+            .last()
+            .is_some_and(|s| s.ident == "AllocError" && s.arguments.is_none()),
+        _ => false,
+    }
+}
+
+/// Makes a function return `Result<T, AllocError>` instead of `T`, running its
+/// body by `catch_oom`.
+///
+/// The body runs as the closure of `catch_oom`, so `return` and `?` behave as
+/// in the original function, returning `T`. With `#[catch_oom_fn(assert_unwind_safe)]`
+/// the body runs by `catch_oom_assert` instead, e.g. for methods taking `&mut self`,
+/// where the caller asserts the state is consistent at every allocation. The
+/// attribute is not named `catch_oom`, which is the name of the block macro.
+///
+/// Async functions and functions returning `impl Trait` or `!` are not supported.
+///
+/// ```
+/// use panic_safe::catch_oom_fn;
+/// # use panic_safe::AllocError;
+/// # use std::io;
+/// # use std::path::Path;
+/// # struct Table;
+/// # impl Table { fn parse(_: &str) -> Self { Table } }
+/// # let path = Path::new("table.csv");
+///
+/// #[catch_oom_fn]
+/// fn load(path: &Path) -> io::Result<Table> {
+///     let text = std::fs::read_to_string(path)?;
+///     Ok(Table::parse(&text))
+/// }
+///
+/// let table: Result<io::Result<Table>, AllocError> = load(path);
+/// ```
+#[proc_macro_attribute]
+pub fn catch_oom_fn(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = proc_macro2::TokenStream::from(args);
+    let item = parse_macro_input!(input as ItemFn);
+    wrap_catch_oom(args, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn wrap_catch_oom(args: proc_macro2::TokenStream, mut item: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let catch = if args.is_empty() {
+        quote!(::panic_safe::catch_oom)
+    } else {
+        let arg: Ident = syn::parse2(args)?;
+        if arg != "assert_unwind_safe" {
+            return Err(Error::new_spanned(arg, "expected `assert_unwind_safe`"));
+        }
+        quote!(::panic_safe::catch_oom_assert)
+    };
+    if let Some(asyncness) = &item.sig.asyncness {
+        return Err(Error::new_spanned(asyncness, "async functions are not supported"));
+    }
+    let output = match &item.sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::ImplTrait(_) | Type::Never(_) => {
+                return Err(Error::new(
+                    ty.span(),
+                    "`impl Trait` and `!` cannot be returned as `Result`",
+                ))
+            }
+            ty => quote!(#ty),
+        },
+    };
+    let block = &item.block;
+    let block = parse_quote!({
+        #catch(move || -> #output #block)
+    });
+    *item.block = block;
+    item.sig.output = parse_quote!(-> ::core::result::Result<#output, ::panic_safe::AllocError>);
+    Ok(quote!(#item))
+}
+
+/// Runs the body of a function, usually `extern "C"`, by `catch_ffi`, returning
+/// a sentinel value on allocation error or panic, so no unwinding crosses the
+/// FFI boundary.
+///
+/// The values are given by `oom = <expr>` and `panic = <expr>`, or by
+/// `error = <expr>` for both, and default to `Default::default()`. They are
+/// evaluated after the failure, in the scope of the function, so they can write
+/// error codes through pointer arguments, which are copied into the body.
+///
+/// Async functions and functions returning `!` are not supported.
+///
+/// ```
+/// use panic_safe::ffi_guard;
+/// # use std::ffi::{c_char, c_int, CStr};
+/// # const ERR_FAILED: c_int = -1;
+/// # struct Index;
+/// # impl Index {
+/// #     fn build(_: &[u8]) -> Self { Index }
+/// #     fn open(_: &CStr) -> Self { Index }
+/// #     fn publish(self) {}
+/// # }
+///
+/// #[no_mangle]
+/// #[ffi_guard(oom = -2, panic = -1)]
+/// pub extern "C" fn index_build(input: *const u8, len: usize) -> c_int {
+///     let input = unsafe { std::slice::from_raw_parts(input, len) };
+///     Index::build(input).publish();
+///     0
+/// }
+///
+/// #[no_mangle]
+/// #[ffi_guard(error = { unsafe { *err = ERR_FAILED }; std::ptr::null_mut() })]
+/// pub extern "C" fn index_open(path: *const c_char, err: *mut c_int) -> *mut Index {
+///     Box::into_raw(Box::new(Index::open(unsafe { CStr::from_ptr(path) })))
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn ffi_guard(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args with Punctuated::<MetaNameValue, Token![,]>::parse_terminated);
+    let item = parse_macro_input!(input as ItemFn);
+    wrap_ffi_guard(args, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn wrap_ffi_guard(
+    args: Punctuated<MetaNameValue, Token![,]>,
+    mut item: ItemFn,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let (mut error, mut oom, mut panic) = (None, None, None);
+    for arg in args {
+        let slot = if arg.path.is_ident("error") {
+            &mut error
+        } else if arg.path.is_ident("oom") {
+            &mut oom
+        } else if arg.path.is_ident("panic") {
+            &mut panic
+        } else {
+            return Err(Error::new_spanned(arg.path, "expected `error`, `oom` or `panic`"));
+        };
+        if slot.replace(arg.value).is_some() {
+            return Err(Error::new(Span::call_site(), "duplicate sentinel value"));
+        }
+    }
+    if let Some(asyncness) = &item.sig.asyncness {
+        return Err(Error::new_spanned(asyncness, "async functions are not supported"));
+    }
+    let output = match &item.sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => match &**ty {
+            Type::Never(_) => return Err(Error::new(ty.span(), "`!` cannot be returned on failure")),
+            ty => quote!(#ty),
+        },
+    };
+    let default = || parse_quote!(::core::default::Default::default());
+    let oom: Expr = oom.or_else(|| error.clone()).unwrap_or_else(default);
+    let panic: Expr = panic.or(error).unwrap_or_else(default);
+
+    let block = &item.block;
+    let block = parse_quote!({
+        match ::panic_safe::catch_ffi(move || -> #output #block) {
+            ::core::result::Result::Ok(r) => r,
+            ::core::result::Result::Err(::panic_safe::CaughtError::Oom(_)) => #oom,
+            ::core::result::Result::Err(::panic_safe::CaughtError::Panic(_)) => #panic,
+        }
+    });
+    *item.block = block;
+    Ok(quote!(#item))
+}
@@ -0,0 +1,70 @@
+/*
+ * The C API of panic-safe, exported by a Rust library built with the `capi`
+ * feature.
+ */
+
+#ifndef PANIC_SAFE_H
+#define PANIC_SAFE_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The function returns normally. */
+#define PANIC_SAFE_OK 0
+/* An allocation error occurs. */
+#define PANIC_SAFE_OOM 1
+/* A panic other than allocation error occurs. */
+#define PANIC_SAFE_PANIC 2
+/* The arguments are invalid, e.g. a null function pointer. */
+#define PANIC_SAFE_INVALID (-1)
+
+/*
+ * The function called by panic_safe_call. It may unwind, so it is a Rust
+ * function of the "C-unwind" ABI, or a function compiled with unwind tables
+ * calling one.
+ */
+typedef void (*panic_safe_fn)(void *ctx);
+
+/*
+ * Installs the hooks, returning PANIC_SAFE_OK, or PANIC_SAFE_INVALID if panics
+ * abort in the Rust library, so nothing can be caught.
+ */
+int panic_safe_init(void);
+
+/*
+ * Calls f with ctx, catching the allocation error or the panic unwinding out of
+ * it. Returns PANIC_SAFE_OK if f returns, PANIC_SAFE_OOM or PANIC_SAFE_PANIC if
+ * it fails, whose error is kept as the last error of the calling thread until
+ * the next failure, or PANIC_SAFE_INVALID if f is null.
+ */
+int panic_safe_call(panic_safe_fn f, void *ctx);
+
+/*
+ * Returns the size of the allocation failed by the last error of the calling
+ * thread, or 0 if the last error is not an allocation error or there is none.
+ */
+size_t panic_safe_last_error_size(void);
+
+/*
+ * Returns the alignment of the allocation failed by the last error of the
+ * calling thread, or 0 if the last error is not an allocation error or there is
+ * none.
+ */
+size_t panic_safe_last_error_align(void);
+
+/*
+ * Writes the message of the last error of the calling thread into buf of len
+ * bytes as a NUL-terminated string, truncated if it does not fit, like snprintf.
+ * Returns the length of the whole message without the terminating NUL, or 0 if
+ * there is no error. Nothing is written if buf is null or len is 0.
+ */
+size_t panic_safe_last_error_message(char *buf, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PANIC_SAFE_H */
@@ -0,0 +1,166 @@
+//! Callbacks invoked before the process aborts on panic.
+
+use std::cell::Cell;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::panic::PanicHookInfo;
+use std::sync::{PoisonError, RwLock};
+
+type AbortHook = Box<dyn Fn(&PanicHookInfo<'_>) + 'static + Sync + Send>;
+
+/// The registered abort hooks, invoked in the order of registration.
+static ABORT_HOOKS: RwLock<Vec<AbortHook>> = RwLock::new(Vec::new());
+
+/// The fatal reporter, invoked before the abort hooks.
+static FATAL_REPORTER: RwLock<Option<fn(&PanicHookInfo<'_>)>> = RwLock::new(None);
+
+thread_local! {
+    static THREAD_ABORTING: Cell<bool> = const { Cell::new(false) };
+    static THREAD_CRITICAL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Registers an abort hook, replacing all the hooks registered before.
+///
+/// See [`add_abort_hook`] for the contract of the hook.
+pub fn set_abort_hook<F>(hook: F)
+where
+    F: Fn(&PanicHookInfo<'_>) + 'static + Sync + Send,
+{
+    let mut hooks = ABORT_HOOKS.write().unwrap_or_else(PoisonError::into_inner);
+    hooks.clear();
+    hooks.push(Box::new(hook));
+}
+
+/// Registers an abort hook in addition to the hooks registered before.
+///
+/// Abort hooks are invoked with the panic information right before the process
+/// is aborted on a panic other than allocation error, or on any panic guarded by
+/// an [`AbortGuard`], e.g. to flush buffers and
+/// sync files. They are invoked in the order of registration on the panicking
+/// thread, while other threads keep running.
+///
+/// An abort hook should finish in bounded time and should not allocate, as the
+/// process may be short of memory. It must not register abort hooks, which
+/// deadlocks. If an abort hook panics, the process is aborted at once without
+/// invoking the remaining hooks.
+pub fn add_abort_hook<F>(hook: F)
+where
+    F: Fn(&PanicHookInfo<'_>) + 'static + Sync + Send,
+{
+    ABORT_HOOKS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Box::new(hook));
+}
+
+/// Sets the fatal reporter, returning the previous one.
+///
+/// The fatal reporter is invoked synchronously on the panicking thread right
+/// before the process is aborted on a panic other than allocation error, before
+/// the abort hooks, e.g. to send the event to an error tracking service. It is
+/// not invoked for allocation errors, which are returned as `AllocError`, unless
+/// guarded by an [`AbortGuard`].
+///
+/// As a function pointer, the reporter is stored and invoked without allocating.
+/// The reporter itself should avoid allocating, as the process may be short of
+/// memory, e.g. by writing into buffers prepared ahead. If the reporter panics,
+/// the process is aborted at once.
+pub fn set_fatal_reporter(reporter: Option<fn(&PanicHookInfo<'_>)>) -> Option<fn(&PanicHookInfo<'_>)> {
+    std::mem::replace(
+        &mut *FATAL_REPORTER.write().unwrap_or_else(PoisonError::into_inner),
+        reporter,
+    )
+}
+
+/// Invokes the fatal reporter and the abort hooks, then aborts the process.
+pub(crate) fn abort(info: &PanicHookInfo<'_>) -> ! {
+    if !THREAD_ABORTING.with(|a| a.replace(true)) {
+        let reporter = *FATAL_REPORTER.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(reporter) = reporter {
+            reporter(info);
+        }
+        for hook in ABORT_HOOKS.read().unwrap_or_else(PoisonError::into_inner).iter() {
+            hook(info);
+        }
+    }
+    std::process::abort()
+}
+
+/// Checks if current thread is in a critical section guarded by an [`AbortGuard`].
+#[inline]
+pub(crate) fn in_critical_section() -> bool {
+    THREAD_CRITICAL_DEPTH.with(Cell::get) > 0
+}
+
+/// A guard aborting the process on any panic, including allocation errors, while
+/// it is armed.
+///
+/// This guards critical sections, e.g. updating a structure in shared memory,
+/// where continuing after a panic would leave the state corrupted. Unlike
+/// [`CatchMode::AbortOnPanic`](crate::CatchMode::AbortOnPanic), allocation errors
+/// abort as well, even in catching scopes. The panic is reported and the
+/// process aborted by our panic hook, after invoking the fatal reporter and the
+/// abort hooks. If the hook is not installed or bypassed, e.g. by `resume_unwind`,
+/// the process is aborted when the guard is dropped while unwinding.
+///
+/// The guard marks current thread, so it cannot be sent to other threads.
+///
+/// ```
+/// use panic_safe::AbortGuard;
+///
+/// # struct Header { len: usize }
+/// # struct Shared { header: Header, entries: Vec<u32> }
+/// # let mut shared = Shared { header: Header { len: 0 }, entries: Vec::new() };
+/// # let entries = [1, 2, 3];
+/// # let new_len = entries.len();
+/// let guard = AbortGuard::new();
+/// shared.header.len = new_len;
+/// shared.entries.extend_from_slice(&entries);
+/// guard.disarm();
+/// ```
+#[derive(Debug)]
+#[must_use = "the critical section ends once the guard is dropped"]
+pub struct AbortGuard {
+    /// Whether current thread is unwinding when the guard is created, e.g. in a
+    /// destructor, in which case dropping it is not unwinding out of it.
+    unwinding: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl AbortGuard {
+    /// Creates an armed `AbortGuard`, entering a critical section of current thread
+    /// until disarmed or dropped.
+    #[inline]
+    pub fn new() -> Self {
+        THREAD_CRITICAL_DEPTH.with(|d| d.set(d.get() + 1));
+        AbortGuard {
+            unwinding: std::thread::panicking(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Disarms the guard, leaving the critical section.
+    #[inline]
+    pub fn disarm(self) {
+        THREAD_CRITICAL_DEPTH.with(|d| d.set(d.get() - 1));
+        std::mem::forget(self);
+    }
+}
+
+impl Default for AbortGuard {
+    #[inline]
+    fn default() -> Self {
+        AbortGuard::new()
+    }
+}
+
+impl Drop for AbortGuard {
+    #[inline]
+    fn drop(&mut self) {
+        THREAD_CRITICAL_DEPTH.with(|d| d.set(d.get() - 1));
+        if std::thread::panicking() && !self.unwinding {
+            let _ = std::io::stderr().write_all(b"unwinding out of a critical section, aborting\n");
+            std::process::abort();
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Capturing backtraces where allocations fail.
+
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Size of the memory reserved for capturing a backtrace.
+const RESERVE_SIZE: usize = 64 * 1024;
+
+/// Memory reserved for capturing a backtrace, which is released right before the
+/// capture, as the allocator is failing at that time.
+static RESERVE: Mutex<Option<Box<[u8]>>> = Mutex::new(None);
+static RESERVED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static THREAD_CAPTURING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Reserves memory for capturing a backtrace if it has been released.
+#[inline]
+pub(crate) fn reserve() {
+    if !RESERVED.load(Ordering::Acquire) {
+        let mut reserve = RESERVE.lock().unwrap_or_else(PoisonError::into_inner);
+        if reserve.is_none() {
+            // Failing here would capture a backtrace, which takes the lock held.
+            let mut block = Vec::new();
+            if block.try_reserve_exact(RESERVE_SIZE).is_err() {
+                return;
+            }
+            block.resize(RESERVE_SIZE, 0);
+            *reserve = Some(block.into_boxed_slice());
+        }
+        RESERVED.store(true, Ordering::Release);
+    }
+}
+
+/// Checks if current thread is capturing a backtrace.
+#[inline]
+pub(crate) fn capturing() -> bool {
+    THREAD_CAPTURING.with(Cell::get)
+}
+
+/// Captures a backtrace using the reserved memory.
+///
+/// Returns `None` if called again while capturing a backtrace in current thread.
+pub(crate) fn capture() -> Option<Arc<Backtrace>> {
+    if THREAD_CAPTURING.with(|capturing| capturing.replace(true)) {
+        return None;
+    }
+
+    RESERVED.store(false, Ordering::Release);
+    drop(RESERVE.lock().unwrap_or_else(PoisonError::into_inner).take());
+    let backtrace = Arc::new(Backtrace::force_capture());
+
+    THREAD_CAPTURING.with(|capturing| capturing.set(false));
+    Some(backtrace)
+}
@@ -0,0 +1,187 @@
+//! Memory budgets shared by threads.
+
+use std::cell::Cell;
+use std::panic::{Location, UnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+#[derive(Debug)]
+struct Budget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+thread_local! {
+    static THREAD_BUDGET: Cell<*const Budget> = const { Cell::new(ptr::null()) };
+}
+
+/// A memory budget shared by threads, bounding the total bytes allocated by them.
+///
+/// Allocations in threads attached to the budget debit it, and fail if it is
+/// exhausted, so they are reported as `AllocError` in catching scopes. The budget
+/// is credited when the memory is deallocated by a thread attached to it, memory
+/// deallocated by other threads is not credited back. Clones of a budget share
+/// the same counter, so a budget can bound e.g. all threads of a query.
+///
+/// The bytes are counted by [`TrackingAlloc`](crate::TrackingAlloc), which must be
+/// registered as the global allocator, otherwise the budget has no effect.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    budget: Arc<Budget>,
+}
+
+impl MemoryBudget {
+    /// Creates a new `MemoryBudget` of `limit` bytes.
+    #[must_use]
+    #[inline]
+    pub fn new(limit: usize) -> Self {
+        MemoryBudget {
+            budget: Arc::new(Budget {
+                limit,
+                used: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Returns the limit of the budget in bytes.
+    #[must_use]
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.budget.limit
+    }
+
+    /// Returns the bytes debited from the budget.
+    #[must_use]
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.budget.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the bytes remaining in the budget.
+    #[must_use]
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.limit().saturating_sub(self.used())
+    }
+
+    /// Invokes a closure with current thread attached to the budget.
+    ///
+    /// The previously attached budget is restored when the closure returns or panics.
+    #[inline]
+    pub fn enter<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        struct Restore(*const Budget);
+
+        impl Drop for Restore {
+            #[inline]
+            fn drop(&mut self) {
+                THREAD_BUDGET.with(|budget| budget.set(self.0));
+            }
+        }
+
+        let _restore = Restore(THREAD_BUDGET.with(|budget| budget.replace(Arc::as_ptr(&self.budget))));
+        f()
+    }
+
+    /// Invokes a closure under [`catch_oom`](crate::catch_oom) with current thread
+    /// attached to the budget, so exhausting the budget is returned as `AllocError`.
+    #[track_caller]
+    #[inline]
+    pub fn catch_oom<F: FnOnce() -> R + UnwindSafe, R>(&self, f: F) -> Result<R, AllocError> {
+        let caller = Location::caller();
+        self.enter(|| catch_oom_at(caller, f))
+    }
+}
+
+/// The budget attached to a thread, carried to the threads running its closures.
+#[derive(Clone, Copy)]
+pub(crate) struct Attached(*const Budget);
+
+// SAFETY: the budget is shared by threads by design, and is kept alive while the
+// thread it is carried to runs, see `Attached::attach`.
+unsafe impl Send for Attached {}
+
+impl Attached {
+    /// Returns the budget attached to current thread, if any.
+    #[inline]
+    pub(crate) fn current() -> Self {
+        Attached(THREAD_BUDGET.with(Cell::get))
+    }
+
+    /// Attaches current thread to the budget.
+    ///
+    /// # Safety
+    ///
+    /// Current thread must exit before the budget is detached from the thread it
+    /// is taken from, e.g. as a scoped thread of it.
+    #[inline]
+    pub(crate) unsafe fn attach(self) {
+        THREAD_BUDGET.with(|budget| budget.set(self.0));
+    }
+}
+
+/// Debits `size` bytes from the budget attached to current thread, returns `false`
+/// if the budget is exhausted.
+#[inline]
+pub(crate) fn debit(size: usize) -> bool {
+    let budget = THREAD_BUDGET.with(Cell::get);
+    if budget.is_null() {
+        return true;
+    }
+    // SAFETY: the budget is kept alive by the `MemoryBudget` while attached.
+    let budget = unsafe { &*budget };
+    let mut used = budget.used.load(Ordering::Relaxed);
+    loop {
+        let Some(new) = used.checked_add(size).filter(|&new| new <= budget.limit) else {
+            return false;
+        };
+        match budget
+            .used
+            .compare_exchange_weak(used, new, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => return true,
+            Err(current) => used = current,
+        }
+    }
+}
+
+/// Debits `size` bytes from the budget attached to current thread even if they
+/// exceed the limit, for the allocations let exceed the limits while unwinding,
+/// which are credited when deallocated.
+#[inline]
+pub(crate) fn force_debit(size: usize) {
+    let budget = THREAD_BUDGET.with(Cell::get);
+    if !budget.is_null() {
+        // SAFETY: the budget is kept alive by the `MemoryBudget` while attached.
+        let budget = unsafe { &*budget };
+        let mut used = budget.used.load(Ordering::Relaxed);
+        while let Err(current) =
+            budget
+                .used
+                .compare_exchange_weak(used, used.saturating_add(size), Ordering::Relaxed, Ordering::Relaxed)
+        {
+            used = current;
+        }
+    }
+}
+
+/// Credits `size` bytes to the budget attached to current thread.
+#[inline]
+pub(crate) fn credit(size: usize) {
+    let budget = THREAD_BUDGET.with(Cell::get);
+    if !budget.is_null() {
+        // SAFETY: the budget is kept alive by the `MemoryBudget` while attached.
+        let budget = unsafe { &*budget };
+        let mut used = budget.used.load(Ordering::Relaxed);
+        while let Err(current) =
+            budget
+                .used
+                .compare_exchange_weak(used, used.saturating_sub(size), Ordering::Relaxed, Ordering::Relaxed)
+        {
+            used = current;
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! The C API, exported with the `capi` feature.
+//!
+//! The functions are exported unmangled by the library or executable linking
+//! the crate, e.g. a Rust plugin built as `cdylib`, and are declared for C and
+//! C++ in `include/panic_safe.h`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void};
+use std::fmt::{self, Write};
+
+use crate::{catch_ffi, CaughtError};
+
+/// The closure returns normally.
+pub const PANIC_SAFE_OK: c_int = 0;
+/// An allocation error occurs.
+pub const PANIC_SAFE_OOM: c_int = 1;
+/// A panic other than allocation error occurs.
+pub const PANIC_SAFE_PANIC: c_int = 2;
+/// The arguments are invalid, e.g. a null function pointer.
+pub const PANIC_SAFE_INVALID: c_int = -1;
+
+thread_local! {
+    /// The error of the last failed `panic_safe_call` in current thread.
+    static THREAD_LAST_ERROR: RefCell<Option<CaughtError>> = const { RefCell::new(None) };
+}
+
+/// The function called by `panic_safe_call`.
+///
+/// It may unwind, so it is a Rust function of the `"C-unwind"` ABI, or a C or C++
+/// function compiled with unwind tables calling one.
+pub type PanicSafeFn = unsafe extern "C-unwind" fn(ctx: *mut c_void);
+
+/// Installs the hooks, returning [`PANIC_SAFE_OK`], or [`PANIC_SAFE_INVALID`] if
+/// panics abort in this build, so nothing can be caught.
+#[no_mangle]
+pub extern "C" fn panic_safe_init() -> c_int {
+    match crate::try_init() {
+        Ok(()) => PANIC_SAFE_OK,
+        Err(_) => PANIC_SAFE_INVALID,
+    }
+}
+
+/// Calls `f` with `ctx`, catching the allocation error or the panic unwinding out
+/// of it.
+///
+/// Returns [`PANIC_SAFE_OK`] if `f` returns, [`PANIC_SAFE_OOM`] or
+/// [`PANIC_SAFE_PANIC`] if it fails, whose error is kept as the last error of
+/// current thread until the next failure, or [`PANIC_SAFE_INVALID`] if `f` is
+/// null.
+///
+/// # Safety
+///
+/// `f` must be safe to call with `ctx`.
+#[no_mangle]
+pub unsafe extern "C" fn panic_safe_call(f: Option<PanicSafeFn>, ctx: *mut c_void) -> c_int {
+    let Some(f) = f else {
+        return PANIC_SAFE_INVALID;
+    };
+    // SAFETY: guaranteed by the caller.
+    let (code, error) = match catch_ffi(|| unsafe { f(ctx) }) {
+        Ok(()) => return PANIC_SAFE_OK,
+        Err(e @ CaughtError::Oom(_)) => (PANIC_SAFE_OOM, e),
+        Err(e @ CaughtError::Panic(_)) => (PANIC_SAFE_PANIC, e),
+    };
+    // The previous error is dropped outside of the borrow, as dropping a panic
+    // payload runs arbitrary code.
+    let previous = THREAD_LAST_ERROR.with(|last| last.borrow_mut().replace(error));
+    drop(previous);
+    code
+}
+
+/// Returns the size of the allocation failed by the last error of current thread,
+/// or 0 if the last error is not an allocation error or there is none.
+#[no_mangle]
+pub extern "C" fn panic_safe_last_error_size() -> usize {
+    THREAD_LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(CaughtError::Oom(e)) => e.size(),
+        _ => 0,
+    })
+}
+
+/// Returns the alignment of the allocation failed by the last error of current
+/// thread, or 0 if the last error is not an allocation error or there is none.
+#[no_mangle]
+pub extern "C" fn panic_safe_last_error_align() -> usize {
+    THREAD_LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(CaughtError::Oom(e)) => e.align(),
+        _ => 0,
+    })
+}
+
+/// A writer into a C buffer, which counts the length of the whole text and
+/// discards the overflowing text.
+struct CBuffer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    total: usize,
+}
+
+impl Write for CBuffer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        self.total += s.len();
+        Ok(())
+    }
+}
+
+/// Writes the message of the last error of current thread into `buf` of `len`
+/// bytes as a NUL-terminated string, truncated if it does not fit, like
+/// `snprintf`.
+///
+/// Returns the length of the whole message without the terminating NUL, or 0 if
+/// there is no error. Nothing is written if `buf` is null or `len` is 0.
+///
+/// # Safety
+///
+/// `buf` must be null or valid for writes of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn panic_safe_last_error_message(buf: *mut c_char, len: usize) -> usize {
+    let buf: &mut [u8] = if buf.is_null() || len == 0 {
+        &mut []
+    } else {
+        // SAFETY: guaranteed by the caller.
+        unsafe { std::slice::from_raw_parts_mut(buf.cast::<u8>(), len) }
+    };
+    // The last byte is reserved for the NUL.
+    let capacity = buf.len().saturating_sub(1);
+    let mut writer = CBuffer {
+        buf: &mut buf[..capacity],
+        len: 0,
+        total: 0,
+    };
+    THREAD_LAST_ERROR.with(|last| {
+        if let Some(e) = &*last.borrow() {
+            let _ = write!(writer, "{}", e);
+        }
+    });
+    let (written, total) = (writer.len, writer.total);
+    if let Some(nul) = buf.get_mut(written) {
+        *nul = 0;
+    }
+    total
+}
@@ -0,0 +1,497 @@
+//! The catching functions.
+
+use std::panic::{AssertUnwindSafe, Location, PanicHookInfo, UnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::hook::{init, HandlingOom, PanicHandler, ThreadPanic};
+use crate::slot::ThreadAllocError;
+use crate::throw::Thrown;
+use crate::{AllocError, CatchError, CaughtError, PanicError};
+
+/// Specifies how panics other than allocation error are handled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CatchMode {
+    /// Aborts the process.
+    #[default]
+    AbortOnPanic,
+    /// Propagates the panic to the caller by resuming unwinding.
+    ResumeUnwind,
+    /// Returns the panic as an error.
+    ReturnError,
+}
+
+impl CatchMode {
+    #[inline]
+    const fn into_u8(self) -> u8 {
+        match self {
+            CatchMode::AbortOnPanic => 0,
+            CatchMode::ResumeUnwind => 1,
+            CatchMode::ReturnError => 2,
+        }
+    }
+
+    #[inline]
+    const fn from_u8(mode: u8) -> Self {
+        match mode {
+            1 => CatchMode::ResumeUnwind,
+            2 => CatchMode::ReturnError,
+            _ => CatchMode::AbortOnPanic,
+        }
+    }
+}
+
+static CATCH_MODE: AtomicU8 = AtomicU8::new(CatchMode::AbortOnPanic.into_u8());
+
+/// Sets the global catch mode used by [`catch_oom`].
+///
+/// The default mode is [`CatchMode::AbortOnPanic`]. The mode applies to the
+/// threads in catching scopes only, so panics of other threads unwind and are
+/// reported as usual, unless [`set_abort_outside_scopes`] is enabled.
+#[inline]
+pub fn set_catch_mode(mode: CatchMode) {
+    CATCH_MODE.store(mode.into_u8(), Ordering::Release);
+}
+
+static ABORT_OUTSIDE_SCOPES: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether panics outside any catching scope abort the process in the global
+/// [`CatchMode::AbortOnPanic`], once the panic hook has been installed.
+///
+/// This makes every thread of the process abort on panic, including the threads
+/// which never use the catching functions. It is off by default, so installing
+/// the panic hook only changes the behavior of the threads in catching scopes.
+#[inline]
+pub fn set_abort_outside_scopes(abort: bool) {
+    ABORT_OUTSIDE_SCOPES.store(abort, Ordering::Relaxed);
+}
+
+/// Returns whether panics outside any catching scope abort the process, see
+/// [`set_abort_outside_scopes`].
+#[must_use]
+#[inline]
+pub fn abort_outside_scopes() -> bool {
+    ABORT_OUTSIDE_SCOPES.load(Ordering::Relaxed)
+}
+
+/// Returns the global catch mode.
+#[must_use]
+#[inline]
+pub fn catch_mode() -> CatchMode {
+    CatchMode::from_u8(CATCH_MODE.load(Ordering::Acquire))
+}
+
+/// Sets the catch mode of current thread, and restores the previous mode on drop.
+///
+/// The panic handler of current thread is set as well, so the handler of an
+/// outer scope does not apply to the inner scope, and the scope is marked as
+/// entered while unwinding if so, e.g. in a destructor.
+struct ModeGuard(Option<CatchMode>, Option<*const PanicHandler>, bool);
+
+impl ModeGuard {
+    #[inline]
+    fn new(mode: CatchMode, handler: Option<&(dyn Fn(&PanicHookInfo<'_>) -> CatchMode + '_)>) -> Self {
+        // SAFETY: only the lifetime is erased, and the handler is unset on drop,
+        // before it goes out of scope.
+        let handler = handler.map(|h| unsafe { std::mem::transmute::<_, *const PanicHandler>(h as *const _) });
+        ModeGuard(
+            ThreadPanic::set_mode(Some(mode)),
+            ThreadPanic::set_handler(handler),
+            ThreadPanic::set_scope_in_unwinding(std::thread::panicking()),
+        )
+    }
+}
+
+impl Drop for ModeGuard {
+    #[inline]
+    fn drop(&mut self) {
+        ThreadPanic::set_mode(self.0);
+        ThreadPanic::set_handler(self.1);
+        ThreadPanic::set_scope_in_unwinding(self.2);
+    }
+}
+
+/// Invokes a closure in the given catch mode.
+///
+/// On panic, returns the allocation error if one occurs, together with the panic.
+/// The allocation error is taken from the thread error slot, or from the payload
+/// if the panic is propagated from another thread, and records `caller` as its
+/// location. The values thrown by [`throw`](crate::throw) are propagated. The
+/// allocation error is recorded as the last error of current thread, and the
+/// low-memory listeners and the observers are notified of it.
+#[inline]
+fn catch_unwind<F: FnOnce() -> R + UnwindSafe, R>(
+    mode: CatchMode,
+    handler: Option<&(dyn Fn(&PanicHookInfo<'_>) -> CatchMode + '_)>,
+    caller: &'static Location<'static>,
+    f: F,
+) -> Result<R, (Option<AllocError>, PanicError)> {
+    let _frame = ThreadAllocError::enter_frame();
+    ThreadPanic::take_hook_seen();
+    {
+        let _handling = HandlingOom::enter();
+        crate::emergency::reserve();
+        #[cfg(feature = "backtrace")]
+        crate::backtrace::reserve();
+    }
+    let _guard = ModeGuard::new(mode, handler);
+    let _usage = crate::tracking::ScopeUsage::enter();
+    let _tracing = crate::large_alloc::TracingScope::enter(caller);
+    let result = std::panic::catch_unwind(f);
+    match result {
+        Ok(r) => Ok(r),
+        Err(payload) if payload.is::<Thrown>() => std::panic::resume_unwind(payload),
+        Err(payload) => {
+            if !ThreadPanic::take_hook_seen() && !payload.is::<AllocError>() {
+                // The panic bypassed our hook, which may have been replaced.
+                let _ = crate::hook::verify_hook();
+            }
+            let location = ThreadPanic::take_location();
+            let alloc_error = ThreadAllocError::take()
+                .or_else(|| payload.downcast_ref::<AllocError>().cloned())
+                .map(|e| e.caught_at(caller));
+            if let Some(e) = &alloc_error {
+                crate::last_error::record(e);
+                let freed = crate::listener::notify_low_memory(e.layout());
+                crate::observer::notify_observers(e, freed);
+            }
+            Err((alloc_error, PanicError::new(location, payload)))
+        }
+    }
+}
+
+/// Invokes a closure, capturing the out-of-memory panic if one occurs.
+///
+/// This function will return `Ok` with the closure's result if the closure
+/// does not panic, and will return `AllocError` if allocation error occurs.
+/// Other panics are handled by the global [`catch_mode`]: the process will abort
+/// by default, and the panic will be propagated to the caller if the mode is
+/// [`CatchMode::ResumeUnwind`] or [`CatchMode::ReturnError`], as `AllocError`
+/// cannot carry it. Use [`catch_oom_with_mode`] to get the panic as an error.
+/// If a panic is not aborted in the default mode, e.g. as the panic hook is
+/// replaced, it is returned as `AllocError` of
+/// [`AllocErrorKind::UnexpectedPanic`](crate::AllocErrorKind::UnexpectedPanic).
+///
+/// The `capacity overflow` panics of the standard collections, which panic
+/// before reaching the allocator, are caught as `AllocError` of
+/// [`AllocErrorKind::CapacityOverflow`](crate::AllocErrorKind::CapacityOverflow).
+///
+/// The location of the call is recorded in the returned `AllocError`.
+#[track_caller]
+#[inline]
+pub fn catch_oom<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, AllocError> {
+    catch_oom_at(Location::caller(), f)
+}
+
+/// Invokes a closure returning `Result` like [`catch_oom`], flattening the error
+/// of the closure and the allocation error into a `CatchError`.
+///
+/// This avoids the nested `Result<Result<T, E>, AllocError>` of [`catch_oom`] for
+/// the closures which already return errors.
+///
+/// ```
+/// use panic_safe::{catch_oom_result, CatchError};
+///
+/// # let (path, n) = ("input.txt", 2);
+/// match catch_oom_result(|| std::fs::read_to_string(path).map(|s| s.repeat(n))) {
+///     Ok(text) => println!("{}", text),
+///     Err(CatchError::Alloc(e)) => eprintln!("out of memory: {}", e),
+///     Err(CatchError::User(e)) => eprintln!("I/O error: {}", e),
+/// }
+/// ```
+#[track_caller]
+#[inline]
+pub fn catch_oom_result<F, T, E>(f: F) -> Result<T, CatchError<E>>
+where
+    F: FnOnce() -> Result<T, E> + UnwindSafe,
+{
+    match catch_oom_at(Location::caller(), f) {
+        Ok(result) => result.map_err(CatchError::User),
+        Err(e) => Err(CatchError::Alloc(e)),
+    }
+}
+
+/// Invokes a closure like [`catch_oom`], without requiring it to be `UnwindSafe`.
+///
+/// This is [`catch_oom`] with the closure wrapped in `AssertUnwindSafe`, for the
+/// common closures capturing `&mut` state. By calling it, the caller asserts that
+/// the state is consistent at every allocation of the closure, or is not used
+/// after an error is returned, as an allocation error interrupts the closure in
+/// the middle of changing the state.
+///
+/// ```
+/// use panic_safe::catch_oom_assert;
+///
+/// # struct Index(Vec<String>);
+/// # impl Index {
+/// #     fn new() -> Self { Index(Vec::new()) }
+/// #     fn insert_all(&mut self, documents: &[&str]) { self.0.extend(documents.iter().map(|d| d.to_string())) }
+/// # }
+/// # fn main() -> Result<(), panic_safe::AllocError> {
+/// # let documents = ["a", "b"];
+/// let mut index = Index::new();
+/// catch_oom_assert(|| index.insert_all(&documents))?;
+/// # Ok(())
+/// # }
+/// ```
+#[track_caller]
+#[inline]
+pub fn catch_oom_assert<F: FnOnce() -> R, R>(f: F) -> Result<R, AllocError> {
+    catch_oom_at(Location::caller(), AssertUnwindSafe(f))
+}
+
+/// Invokes a closure like [`catch_oom`], recording `caller` as the location of the
+/// returned `AllocError`.
+///
+/// This is used where the closure runs apart from the call of the public function,
+/// e.g. on another thread, in which case `#[track_caller]` cannot see the caller.
+#[inline]
+pub(crate) fn catch_oom_at<F: FnOnce() -> R + UnwindSafe, R>(
+    caller: &'static Location<'static>,
+    f: F,
+) -> Result<R, AllocError> {
+    init();
+
+    catch_oom_in(catch_mode(), caller, f)
+}
+
+/// Invokes a closure like [`catch_oom`] in the given catch mode, once the hooks
+/// are installed.
+#[inline]
+fn catch_oom_in<F: FnOnce() -> R + UnwindSafe, R>(
+    mode: CatchMode,
+    caller: &'static Location<'static>,
+    f: F,
+) -> Result<R, AllocError> {
+    match catch_unwind(mode, None, caller, f) {
+        Ok(r) => Ok(r),
+        Err((Some(e), _)) => Err(e),
+        Err((None, panic)) => match mode {
+            // The panic hook of the crate would have aborted the process.
+            CatchMode::AbortOnPanic => {
+                let e = AllocError::unexpected_panic(panic).caught_at(caller);
+                crate::last_error::record(&e);
+                Err(e)
+            }
+            CatchMode::ResumeUnwind | CatchMode::ReturnError => std::panic::resume_unwind(panic.into_payload()),
+        },
+    }
+}
+
+/// Invokes a `FnMut` closure like [`catch_oom`], so the same closure can be
+/// invoked repeatedly, e.g. by an event loop, without being moved into each call.
+///
+/// The state captured by the closure is left as an interrupted call changed it,
+/// and is seen by the next call, so it must be consistent at every allocation,
+/// as required of any `UnwindSafe` closure. Use [`Catcher`] to invoke closures
+/// without checking the hooks on each call.
+///
+/// ```no_run
+/// use panic_safe::catch_oom_mut;
+///
+/// # struct Server;
+/// # impl Server { fn poll_events(&self) -> usize { 0 } }
+/// # let server = Server;
+/// let mut poll = || server.poll_events();
+/// loop {
+///     if let Err(e) = catch_oom_mut(&mut poll) {
+///         eprintln!("dropped events: {}", e);
+///     }
+/// }
+/// ```
+#[track_caller]
+#[inline]
+pub fn catch_oom_mut<F: FnMut() -> R + UnwindSafe, R>(f: &mut F) -> Result<R, AllocError> {
+    catch_oom_at(Location::caller(), AssertUnwindSafe(f))
+}
+
+/// A catching function prepared once, which invokes closures like [`catch_oom`]
+/// without checking the hooks and loading the global catch mode on each call.
+///
+/// The catch mode is the global [`catch_mode`] when the `Catcher` is created, and
+/// the location of its creation is recorded in the returned `AllocError`s. The
+/// hooks are installed by [`Catcher::new`], so nothing is caught after they are
+/// uninstalled by [`uninstall`](crate::uninstall).
+///
+/// ```no_run
+/// use panic_safe::Catcher;
+///
+/// # struct Server;
+/// # impl Server { fn poll_events(&self) -> usize { 0 } }
+/// # let server = Server;
+/// let catcher = Catcher::new();
+/// let mut poll = || server.poll_events();
+/// loop {
+///     if let Err(e) = catcher.catch_oom_mut(&mut poll) {
+///         eprintln!("dropped events: {}", e);
+///     }
+/// }
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Catcher {
+    mode: CatchMode,
+    caller: &'static Location<'static>,
+}
+
+impl Catcher {
+    /// Creates a new `Catcher` in the global catch mode, installing the hooks.
+    #[track_caller]
+    #[must_use]
+    #[inline]
+    pub fn new() -> Self {
+        init();
+        Catcher {
+            mode: catch_mode(),
+            caller: Location::caller(),
+        }
+    }
+
+    /// Returns the catch mode of the `Catcher`.
+    #[must_use]
+    #[inline]
+    pub fn mode(&self) -> CatchMode {
+        self.mode
+    }
+
+    /// Invokes a closure like [`catch_oom`].
+    #[inline]
+    pub fn catch_oom<F: FnOnce() -> R + UnwindSafe, R>(&self, f: F) -> Result<R, AllocError> {
+        catch_oom_in(self.mode, self.caller, f)
+    }
+
+    /// Invokes a `FnMut` closure like [`catch_oom_mut`].
+    #[inline]
+    pub fn catch_oom_mut<F: FnMut() -> R + UnwindSafe, R>(&self, f: &mut F) -> Result<R, AllocError> {
+        catch_oom_in(self.mode, self.caller, AssertUnwindSafe(f))
+    }
+}
+
+impl Default for Catcher {
+    #[track_caller]
+    #[inline]
+    fn default() -> Self {
+        Catcher::new()
+    }
+}
+
+/// Invokes a closure like [`catch_oom`], attaching `name` as the context of the
+/// returned `AllocError`.
+///
+/// The context names the operation in the error message, e.g.
+/// `build-index: failed to allocate memory by ...`. If the error already has a
+/// context, e.g. attached by a nested `catch_oom_named`, the inner one is kept.
+#[track_caller]
+#[inline]
+pub fn catch_oom_named<F: FnOnce() -> R + UnwindSafe, R>(name: &'static str, f: F) -> Result<R, AllocError> {
+    catch_oom(f).map_err(|e| match e.context() {
+        Some(_) => e,
+        None => e.with_context(name),
+    })
+}
+
+/// Invokes a closure in the given catch mode, capturing the out-of-memory panic
+/// if one occurs.
+///
+/// This function will return `Ok` with the closure's result if the closure
+/// does not panic, and will return `CaughtError::Oom` if allocation error occurs.
+/// Other panics will abort the process, be propagated to the caller, or be returned
+/// as `CaughtError::Panic`, according to `mode`.
+#[track_caller]
+#[inline]
+pub fn catch_oom_with_mode<F: FnOnce() -> R + UnwindSafe, R>(mode: CatchMode, f: F) -> Result<R, CaughtError> {
+    catch_oom_with_mode_at(mode, Location::caller(), f)
+}
+
+/// Invokes a closure like [`catch_oom_with_mode`], recording `caller` as the
+/// location of the returned `AllocError`.
+#[inline]
+pub(crate) fn catch_oom_with_mode_at<F: FnOnce() -> R + UnwindSafe, R>(
+    mode: CatchMode,
+    caller: &'static Location<'static>,
+    f: F,
+) -> Result<R, CaughtError> {
+    init();
+
+    match catch_unwind(mode, None, caller, f) {
+        Ok(r) => Ok(r),
+        Err((Some(e), _)) => Err(CaughtError::Oom(e)),
+        Err((None, panic)) => match mode {
+            CatchMode::ResumeUnwind => std::panic::resume_unwind(panic.into_payload()),
+            CatchMode::AbortOnPanic | CatchMode::ReturnError => Err(CaughtError::Panic(panic)),
+        },
+    }
+}
+
+/// Invokes a closure like [`catch_oom_with_mode`], deciding how a panic other than
+/// allocation error is handled by `handler`.
+///
+/// The handler is invoked by the panic hook on the panicking thread, before
+/// unwinding, with the panic information. It can log the panic, or flush state
+/// which must survive the panic, and returns the mode handling the panic: the
+/// process will abort after the handler returns [`CatchMode::AbortOnPanic`], the
+/// panic will be propagated to the caller for [`CatchMode::ResumeUnwind`], and
+/// returned as `CaughtError::Panic` for [`CatchMode::ReturnError`].
+///
+/// The handler does not apply to the catching scopes nested in the closure. If
+/// the handler panics, the process aborts.
+///
+/// ```
+/// use panic_safe::{catch_oom_with_hook, CatchMode};
+///
+/// # struct State;
+/// # impl State { fn flush(&self) {} }
+/// # fn process(request: &str) -> usize { request.len() }
+/// # let (shared_state, request) = (State, String::from("request"));
+/// let result = catch_oom_with_hook(
+///     |info| {
+///         shared_state.flush();
+///         eprintln!("{}", info);
+///         CatchMode::ReturnError
+///     },
+///     || process(&request),
+/// );
+/// ```
+#[track_caller]
+#[inline]
+pub fn catch_oom_with_hook<F, R, H>(handler: H, f: F) -> Result<R, CaughtError>
+where
+    F: FnOnce() -> R + UnwindSafe,
+    H: Fn(&PanicHookInfo<'_>) -> CatchMode,
+{
+    init();
+
+    ThreadPanic::take_handled_mode();
+    match catch_unwind(CatchMode::ReturnError, Some(&handler), Location::caller(), f) {
+        Ok(r) => Ok(r),
+        Err((Some(e), _)) => Err(CaughtError::Oom(e)),
+        Err((None, panic)) => match ThreadPanic::take_handled_mode() {
+            Some(CatchMode::ResumeUnwind) => std::panic::resume_unwind(panic.into_payload()),
+            _ => Err(CaughtError::Panic(panic)),
+        },
+    }
+}
+
+/// Invokes a closure, capturing the cause of an unwinding panic if one occurs.
+///
+/// This function will return `Ok` with the closure's result if the closure
+/// does not panic, and will return `PanicError` with the panic message, payload
+/// and location if the closure panics. Unlike [`catch_oom`], the process will not
+/// abort on panics other than allocation error.
+#[track_caller]
+#[inline]
+pub fn catch_panic<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, PanicError> {
+    init();
+
+    catch_unwind(CatchMode::ReturnError, None, Location::caller(), f).map_err(|(_, panic)| panic)
+}
+
+/// Invokes a closure, capturing the allocation error or the panic if one occurs.
+///
+/// This function will return `Ok` with the closure's result if the closure
+/// does not panic, will return `CaughtError::Oom` if allocation error occurs,
+/// and will return `CaughtError::Panic` if other panics occur. The process will
+/// not abort in either case.
+#[track_caller]
+#[inline]
+pub fn catch_any<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, CaughtError> {
+    catch_oom_with_mode(CatchMode::ReturnError, f)
+}
@@ -0,0 +1,219 @@
+//! Hierarchical memory contexts.
+
+use std::alloc::{self, Allocator, Layout};
+use std::cell::RefCell;
+use std::panic::{AssertUnwindSafe, Location};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+/// Size of the chunks allocated by a memory context.
+const CHUNK_SIZE: usize = 8 * 1024;
+/// Alignment of the chunks allocated by a memory context.
+const CHUNK_ALIGN: usize = 16;
+
+/// The accounting of a memory context, shared with its children.
+#[derive(Debug)]
+struct Node {
+    limit: usize,
+    used: AtomicUsize,
+    parent: Option<Arc<Node>>,
+}
+
+impl Node {
+    /// Charges `size` bytes to the node and its ancestors, returns `false` if any
+    /// limit is exceeded, in which case nothing is charged.
+    fn charge(&self, size: usize) -> bool {
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let Some(new) = used.checked_add(size).filter(|&new| new <= self.limit) else {
+                return false;
+            };
+            match self
+                .used
+                .compare_exchange_weak(used, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => used = current,
+            }
+        }
+        match &self.parent {
+            Some(parent) if !parent.charge(size) => {
+                self.used.fetch_sub(size, Ordering::Relaxed);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Uncharges `size` bytes from the node and its ancestors.
+    fn uncharge(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::Relaxed);
+        if let Some(parent) = &self.parent {
+            parent.uncharge(size);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Arena {
+    chunks: Vec<(NonNull<u8>, Layout)>,
+    next: usize,
+    end: usize,
+}
+
+/// A memory context, i.e. an arena allocator whose memory is freed all at once,
+/// organized in a tree like the memory contexts of PostgreSQL.
+///
+/// Memory is allocated from a context through the allocator API, e.g. by
+/// `Vec::new_in(&context)`, in chunks taken from the global allocator.
+/// Deallocating does nothing, the memory is freed by [`reset`](Self::reset) or by
+/// dropping the context, which costs a deallocation per chunk regardless of the
+/// number of allocations and leaves no fragmentation behind. As the allocations
+/// borrow the context, they must be dropped before it is reset.
+///
+/// The memory of a child context is charged to its parent as well, so a limit of
+/// the parent bounds the whole subtree. An allocation exceeding the limit of the
+/// context or of any ancestor fails, so it is reported as `AllocError` by
+/// [`catch_oom`](Self::catch_oom).
+#[derive(Debug)]
+pub struct MemoryContext {
+    name: &'static str,
+    node: Arc<Node>,
+    arena: RefCell<Arena>,
+}
+
+impl MemoryContext {
+    /// Creates a new top-level `MemoryContext`, limited to `limit` bytes if given.
+    #[must_use]
+    #[inline]
+    pub fn new(name: &'static str, limit: Option<usize>) -> Self {
+        MemoryContext::with_parent(name, limit, None)
+    }
+
+    /// Creates a child context of the context, limited to `limit` bytes if given.
+    #[must_use]
+    #[inline]
+    pub fn child(&self, name: &'static str, limit: Option<usize>) -> Self {
+        MemoryContext::with_parent(name, limit, Some(self.node.clone()))
+    }
+
+    #[inline]
+    fn with_parent(name: &'static str, limit: Option<usize>, parent: Option<Arc<Node>>) -> Self {
+        MemoryContext {
+            name,
+            node: Arc::new(Node {
+                limit: limit.unwrap_or(usize::MAX),
+                used: AtomicUsize::new(0),
+                parent,
+            }),
+            arena: RefCell::default(),
+        }
+    }
+
+    /// Returns the name of the context.
+    #[must_use]
+    #[inline]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the limit of the context in bytes, if any.
+    #[must_use]
+    #[inline]
+    pub fn limit(&self) -> Option<usize> {
+        (self.node.limit != usize::MAX).then_some(self.node.limit)
+    }
+
+    /// Returns the bytes of the chunks allocated by the context and its children.
+    #[must_use]
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.node.used.load(Ordering::Relaxed)
+    }
+
+    /// Frees all the memory allocated from the context.
+    ///
+    /// The memory of the child contexts is freed by resetting or dropping them.
+    pub fn reset(&mut self) {
+        let arena = std::mem::take(self.arena.get_mut());
+        for (chunk, layout) in arena.chunks {
+            // SAFETY: the chunk is allocated by the global allocator with the layout.
+            unsafe { alloc::dealloc(chunk.as_ptr(), layout) };
+            self.node.uncharge(layout.size());
+        }
+    }
+
+    /// Invokes a closure allocating from the context under
+    /// [`catch_oom`](crate::catch_oom), resetting the context if allocation error
+    /// occurs.
+    ///
+    /// Whatever the closure allocated from the context is freed at once on failure,
+    /// and the context is ready for the next use.
+    #[track_caller]
+    pub fn catch_oom<F: FnOnce(&MemoryContext) -> R, R>(&mut self, f: F) -> Result<R, AllocError> {
+        let caller = Location::caller();
+        // The context is reset on failure, so its state cannot be observed broken.
+        let result = catch_oom_at(caller, AssertUnwindSafe(|| f(self)));
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    fn allocate_chunk(&self, arena: &mut Arena, layout: Layout) -> Option<()> {
+        let size = CHUNK_SIZE.max(layout.size().checked_add(layout.align())?);
+        let chunk_layout = Layout::from_size_align(size, CHUNK_ALIGN).ok()?;
+        if !self.node.charge(size) {
+            return None;
+        }
+        // SAFETY: the layout has a non-zero size.
+        let Some(chunk) = NonNull::new(unsafe { alloc::alloc(chunk_layout) }) else {
+            self.node.uncharge(size);
+            return None;
+        };
+        arena.chunks.push((chunk, chunk_layout));
+        arena.next = chunk.as_ptr() as usize;
+        arena.end = arena.next + size;
+        Some(())
+    }
+
+    fn bump(arena: &mut Arena, layout: Layout) -> Option<NonNull<u8>> {
+        let start = arena.next.checked_next_multiple_of(layout.align())?;
+        let end = start.checked_add(layout.size())?;
+        if arena.chunks.is_empty() || end > arena.end {
+            return None;
+        }
+        arena.next = end;
+        let (chunk, _) = arena.chunks.last()?;
+        // SAFETY: the range is within the last chunk.
+        NonNull::new(unsafe { chunk.as_ptr().add(start - chunk.as_ptr() as usize) })
+    }
+}
+
+unsafe impl Allocator for MemoryContext {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, std::alloc::AllocError> {
+        let mut arena = self.arena.borrow_mut();
+        let ptr = match MemoryContext::bump(&mut arena, layout) {
+            Some(ptr) => ptr,
+            None => {
+                self.allocate_chunk(&mut arena, layout).ok_or(std::alloc::AllocError)?;
+                MemoryContext::bump(&mut arena, layout).ok_or(std::alloc::AllocError)?
+            }
+        };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+}
+
+impl Drop for MemoryContext {
+    #[inline]
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
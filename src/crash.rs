@@ -0,0 +1,103 @@
+//! Crash reports written before the process aborts on panic.
+
+use std::backtrace::Backtrace;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::panic::PanicHookInfo;
+use std::path::Path;
+use std::sync::{Mutex, Once, PoisonError, TryLockError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::abort::add_abort_hook;
+use crate::panic::payload_as_str;
+use crate::stats::oom_stats;
+use crate::PanicKind;
+
+/// The pre-opened crash report file.
+static CRASH_REPORT: Mutex<Option<File>> = Mutex::new(None);
+static REGISTER: Once = Once::new();
+
+/// Writes a crash report to the file at `path` when the process aborts on a panic
+/// other than allocation error.
+///
+/// The file is opened, and created if missing, by this function, so writing the
+/// report does not depend on opening files in a dying process. Reports are
+/// appended to the file, so the report of a previous run is kept. Calling this
+/// function again switches to the new file.
+///
+/// A report contains the time, the panicking thread, the panic message, kind and
+/// location, the threads of the process (on Linux), the allocation error
+/// statistics, and a backtrace. The report is written by an abort hook, see
+/// [`add_abort_hook`](crate::add_abort_hook). Listing the threads and capturing
+/// the backtrace allocate, which is best effort.
+///
+/// # Errors
+///
+/// Returns the error if the file cannot be opened.
+pub fn set_crash_report<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *CRASH_REPORT.lock().unwrap_or_else(PoisonError::into_inner) = Some(file);
+    REGISTER.call_once(|| add_abort_hook(write_report));
+    Ok(())
+}
+
+fn write_report(info: &PanicHookInfo<'_>) {
+    // Do not wait for the lock, which may be held by the panicking thread itself.
+    let mut file = match CRASH_REPORT.try_lock() {
+        Ok(file) => file,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => return,
+    };
+    if let Some(file) = file.as_mut() {
+        let _ = write_to(file, info);
+        let _ = file.sync_all();
+    }
+}
+
+fn write_to(w: &mut File, info: &PanicHookInfo<'_>) -> io::Result<()> {
+    writeln!(w, "=== crash report ===")?;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    writeln!(w, "time: {}.{:09}", time.as_secs(), time.subsec_nanos())?;
+
+    let thread = std::thread::current();
+    writeln!(
+        w,
+        "thread: {} ({:?})",
+        thread.name().unwrap_or("<unnamed>"),
+        thread.id()
+    )?;
+    let message = payload_as_str(info.payload());
+    writeln!(w, "message: {}", message.unwrap_or("Box<dyn Any>"))?;
+    writeln!(w, "kind: {}", PanicKind::of_hook_info(info))?;
+    match info.location() {
+        Some(location) => writeln!(w, "location: {}", location)?,
+        None => writeln!(w, "location: <unknown>")?,
+    }
+
+    write_threads(w)?;
+
+    let (count, last) = oom_stats();
+    write!(w, "allocation errors: {}", count)?;
+    if let Some((size, align)) = last {
+        write!(w, ", last {{size: {}, align: {}}}", size, align)?;
+    }
+    writeln!(w)?;
+
+    writeln!(w, "backtrace:\n{}", Backtrace::force_capture())?;
+    writeln!(w)
+}
+
+#[cfg(target_os = "linux")]
+fn write_threads(w: &mut File) -> io::Result<()> {
+    writeln!(w, "threads:")?;
+    for task in std::fs::read_dir("/proc/self/task")?.flatten() {
+        let comm = std::fs::read_to_string(task.path().join("comm")).unwrap_or_default();
+        writeln!(w, "  {} {}", task.file_name().to_string_lossy(), comm.trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_threads(_: &mut File) -> io::Result<()> {
+    Ok(())
+}
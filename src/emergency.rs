@@ -0,0 +1,65 @@
+//! The emergency memory reserve released on allocation error.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+static RESERVE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Memory reserved for the unwinding after an allocation error, which is released
+/// by the allocation error hook.
+static RESERVE: Mutex<Option<Box<[u8]>>> = Mutex::new(None);
+static RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Sets the size of the emergency memory reserve, and reserves it.
+///
+/// The reserve is allocated ahead and released when an allocation error occurs,
+/// so that constructing the error and running the destructors during unwinding
+/// have memory to work with. Once released, the reserve is allocated again when
+/// entering the next catching scope. The reserve is not allocated if the memory
+/// is short at that time, and allocating it is retried later.
+///
+/// The reserve is disabled by default, or if `bytes` is 0.
+pub fn set_emergency_reserve(bytes: usize) {
+    RESERVE_SIZE.store(bytes, Ordering::Relaxed);
+    drop(RESERVE.lock().unwrap_or_else(PoisonError::into_inner).take());
+    RESERVED.store(false, Ordering::Release);
+    reserve();
+}
+
+/// Returns the size of the emergency memory reserve.
+#[must_use]
+#[inline]
+pub fn emergency_reserve() -> usize {
+    RESERVE_SIZE.load(Ordering::Relaxed)
+}
+
+/// Reserves the emergency memory if it has been released.
+#[inline]
+pub(crate) fn reserve() {
+    if !RESERVED.load(Ordering::Acquire) {
+        let size = RESERVE_SIZE.load(Ordering::Relaxed);
+        let mut reserve = RESERVE.lock().unwrap_or_else(PoisonError::into_inner);
+        if reserve.is_none() && size > 0 {
+            let mut block = Vec::new();
+            if block.try_reserve_exact(size).is_err() {
+                return;
+            }
+            // Fill the block, so the pages are committed under overcommit.
+            block.resize(size, 0xa5);
+            *reserve = Some(block.into_boxed_slice());
+        }
+        RESERVED.store(true, Ordering::Release);
+    }
+}
+
+/// Releases the emergency memory.
+///
+/// The lock is not waited for, as the allocation error may occur while holding it.
+#[inline]
+pub(crate) fn release() {
+    if RESERVED.swap(false, Ordering::AcqRel) {
+        if let Ok(mut reserve) = RESERVE.try_lock() {
+            drop(reserve.take());
+        }
+    }
+}
@@ -0,0 +1,554 @@
+//! The allocation error type.
+
+use core::alloc::Layout;
+use core::cmp::Ordering;
+use core::error::Error;
+#[cfg(not(feature = "stable"))]
+use core::error::Request;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::num::NonZeroUsize;
+use core::panic::Location;
+#[cfg(feature = "backtrace")]
+use std::backtrace::Backtrace;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[cfg(feature = "std")]
+use crate::PanicError;
+
+/// The kind of an allocation failure.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum AllocErrorKind {
+    /// The allocator fails to allocate memory by the required layout.
+    Exhausted,
+    /// The computed capacity exceeds the maximum, so the layout cannot be computed.
+    ///
+    /// The `capacity overflow` panics of the standard collections, e.g. by
+    /// `Vec::with_capacity(usize::MAX)`, are caught as this kind.
+    CapacityOverflow,
+    /// The layout can never be allocated, e.g. a zero-sized layout passed to the
+    /// allocator, or a layout which cannot be constructed by `Layout`.
+    InvalidLayout,
+    /// A panic other than allocation error reaches a catching function which
+    /// cannot return it, e.g. [`catch_oom`](crate::catch_oom) in
+    /// [`CatchMode::AbortOnPanic`](crate::CatchMode::AbortOnPanic), as the panic
+    /// hook of the crate is replaced, or the panic is resumed by `resume_unwind`,
+    /// so the process is not aborted. The panic is taken by
+    /// [`AllocError::take_panic`].
+    UnexpectedPanic,
+}
+
+impl AllocErrorKind {
+    /// Checks if the allocation is impossible, i.e. of
+    /// [`AllocErrorKind::CapacityOverflow`] or [`AllocErrorKind::InvalidLayout`].
+    ///
+    /// Impossible allocations indicate bugs, like computing the size from
+    /// untrusted input, rather than exhausted memory, so retrying them or
+    /// releasing memory does not help.
+    #[must_use]
+    #[inline]
+    pub const fn is_impossible(self) -> bool {
+        matches!(self, AllocErrorKind::CapacityOverflow | AllocErrorKind::InvalidLayout)
+    }
+}
+
+/// The error type for allocation failure.
+#[derive(Clone)]
+pub struct AllocError {
+    layout: Layout,
+    kind: AllocErrorKind,
+    context: Option<&'static str>,
+    location: Option<&'static Location<'static>>,
+    suppressed: Option<NonZeroUsize>,
+    #[cfg(feature = "backtrace")]
+    backtrace: Option<Arc<Backtrace>>,
+    #[cfg(feature = "std")]
+    panic: Option<Arc<Mutex<Option<PanicError>>>>,
+}
+
+impl AllocError {
+    /// Creates a new `AllocError` of [`AllocErrorKind::Exhausted`].
+    #[must_use]
+    #[inline]
+    pub const fn new(layout: Layout) -> Self {
+        AllocError::with_kind(layout, AllocErrorKind::Exhausted)
+    }
+
+    /// Creates a new `AllocError` of [`AllocErrorKind::CapacityOverflow`].
+    ///
+    /// As the required layout cannot be computed, the layout of the error is the
+    /// zero-sized layout of `()`.
+    #[must_use]
+    #[inline]
+    pub const fn capacity_overflow() -> Self {
+        AllocError::with_kind(Layout::new::<()>(), AllocErrorKind::CapacityOverflow)
+    }
+
+    /// Creates a new `AllocError` of [`AllocErrorKind::InvalidLayout`] by the
+    /// layout passed to the allocator.
+    #[must_use]
+    #[inline]
+    pub const fn invalid_layout(layout: Layout) -> Self {
+        AllocError::with_kind(layout, AllocErrorKind::InvalidLayout)
+    }
+
+    #[inline]
+    pub(crate) const fn with_kind(layout: Layout, kind: AllocErrorKind) -> Self {
+        AllocError {
+            layout,
+            kind,
+            context: None,
+            location: None,
+            suppressed: None,
+            #[cfg(feature = "backtrace")]
+            backtrace: None,
+            #[cfg(feature = "std")]
+            panic: None,
+        }
+    }
+
+    /// Creates a new `AllocError` of [`AllocErrorKind::UnexpectedPanic`] carrying
+    /// the panic.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn unexpected_panic(panic: PanicError) -> Self {
+        let mut e = AllocError::with_kind(Layout::new::<()>(), AllocErrorKind::UnexpectedPanic);
+        e.panic = Some(Arc::new(Mutex::new(Some(panic))));
+        e
+    }
+
+    /// Takes the panic carried by an `AllocError` of
+    /// [`AllocErrorKind::UnexpectedPanic`], e.g. to resume it by
+    /// `resume_unwind(panic.into_payload())`.
+    ///
+    /// The panic is shared by the clones of the `AllocError`, and is taken only
+    /// once. Returns `None` for other kinds, or if the panic is taken.
+    #[cfg(feature = "std")]
+    #[must_use]
+    #[inline]
+    pub fn take_panic(&self) -> Option<PanicError> {
+        self.panic
+            .as_ref()
+            .and_then(|panic| panic.lock().unwrap_or_else(PoisonError::into_inner).take())
+    }
+
+    /// Creates a new `AllocError` from the failure of an [`Allocator`] by the
+    /// layout passed to it.
+    ///
+    /// The error of the allocator API carries no layout, so it is converted by
+    /// this function rather than by `From` or `TryFrom`. The other way round, an
+    /// `AllocError` is converted into it by `TryFrom`.
+    ///
+    /// [`Allocator`]: core::alloc::Allocator
+    #[cfg(not(feature = "stable"))]
+    #[must_use]
+    #[inline]
+    pub const fn from_allocator(_: core::alloc::AllocError, layout: Layout) -> Self {
+        AllocError::new(layout)
+    }
+
+    /// Returns the memory layout of the `AllocError`.
+    #[must_use]
+    #[inline]
+    pub const fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns the size of the memory layout of the `AllocError`.
+    #[must_use]
+    #[inline]
+    pub const fn size(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// Returns the alignment of the memory layout of the `AllocError`.
+    #[must_use]
+    #[inline]
+    pub const fn align(&self) -> usize {
+        self.layout.align()
+    }
+
+    /// Returns the kind of the `AllocError`.
+    #[must_use]
+    #[inline]
+    pub const fn kind(&self) -> AllocErrorKind {
+        self.kind
+    }
+
+    /// Returns the context attached to the `AllocError`, if any.
+    #[must_use]
+    #[inline]
+    pub const fn context(&self) -> Option<&'static str> {
+        self.context
+    }
+
+    /// Attaches a context naming the operation that failed, replacing the
+    /// previous one. The context is printed before the message by `Display`.
+    #[must_use]
+    #[inline]
+    pub const fn with_context(mut self, context: &'static str) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Returns the location of the catching call which captured the `AllocError`,
+    /// if any.
+    ///
+    /// The location is recorded by the innermost catching function, e.g.
+    /// [`catch_oom`](crate::catch_oom), which is marked with `#[track_caller]`.
+    #[must_use]
+    #[inline]
+    pub const fn location(&self) -> Option<&'static Location<'static>> {
+        self.location
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn caught_at(mut self, location: &'static Location<'static>) -> Self {
+        self.location.get_or_insert(location);
+        self
+    }
+
+    /// Returns the size of an allocation which failed while unwinding from the
+    /// `AllocError`, e.g. in a destructor, if any.
+    ///
+    /// Panicking again while unwinding aborts the process, so such a failure is
+    /// suppressed rather than raised. Only the allocator wrappers can retry the
+    /// allocation: [`CatchAlloc`](crate::CatchAlloc) retries it after the emergency
+    /// reserve is released, and [`TrackingAlloc`](crate::TrackingAlloc) lets it
+    /// exceed its limits. A failure of other allocators reaches the allocation
+    /// error hook, which cannot retry it, so the process aborts there, and the
+    /// suppressed failure is only observable when a wrapper serves the allocation.
+    /// Only the first suppressed failure is recorded.
+    #[must_use]
+    #[inline]
+    pub const fn suppressed(&self) -> Option<usize> {
+        match self.suppressed {
+            Some(size) => Some(size.get()),
+            None => None,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn suppress(&mut self, layout: Layout) {
+        if self.suppressed.is_none() {
+            self.suppressed = NonZeroUsize::new(layout.size());
+        }
+    }
+
+    /// Returns the backtrace captured where the allocation failed, if available.
+    #[cfg(feature = "backtrace")]
+    #[must_use]
+    #[inline]
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_deref()
+    }
+
+    #[cfg(feature = "backtrace")]
+    #[inline]
+    pub(crate) fn with_backtrace(mut self, backtrace: Option<Arc<Backtrace>>) -> Self {
+        self.backtrace = backtrace;
+        self
+    }
+}
+
+impl AllocError {
+    #[inline]
+    fn key(&self) -> (usize, usize, AllocErrorKind) {
+        (self.layout.size(), self.layout.align(), self.kind)
+    }
+}
+
+/// Allocation errors are compared by the size, then the alignment of the layout,
+/// then the kind. The context, the location and the captured backtrace are ignored.
+impl PartialEq for AllocError {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for AllocError {}
+
+impl PartialOrd for AllocError {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AllocError {
+    #[inline]
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl Hash for AllocError {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl fmt::Debug for AllocError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AllocError")
+            .field("size", &self.layout.size())
+            .field("align", &self.layout.align())
+            .field("kind", &self.kind)
+            .field("context", &self.context)
+            .field("location", &self.location)
+            .field("suppressed", &self.suppressed)
+            .finish()
+    }
+}
+
+/// Formats a size in bytes by binary units, e.g. `1.5 GiB`.
+pub(crate) struct HumanSize(pub(crate) usize);
+
+impl fmt::Display for HumanSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+        if self.0 < 1024 {
+            return write!(f, "{} bytes", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// The alternate form `{:#}` prints the size in binary units, e.g.
+/// `failed to allocate 1.5 GiB`.
+impl fmt::Display for AllocError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(context) = self.context {
+            write!(f, "{}: ", context)?;
+        }
+        if self.kind == AllocErrorKind::CapacityOverflow {
+            f.write_str("failed to allocate memory because the computed capacity exceeded the maximum")?;
+        } else if self.kind == AllocErrorKind::UnexpectedPanic {
+            f.write_str("unexpected panic in catching scope")?;
+        } else if self.kind == AllocErrorKind::InvalidLayout {
+            write!(
+                f,
+                "failed to allocate memory by invalid layout {{size: {}, align: {}}}",
+                self.layout.size(),
+                self.layout.align()
+            )?;
+        } else if f.alternate() {
+            write!(f, "failed to allocate {}", HumanSize(self.layout.size()))?;
+        } else {
+            write!(
+                f,
+                "failed to allocate memory by required layout {{size: {}, align: {}}}",
+                self.layout.size(),
+                self.layout.align()
+            )?;
+        }
+        if let Some(location) = self.location {
+            write!(f, ", caught at {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AllocError {
+    #[cfg(not(feature = "stable"))]
+    fn provide<'a>(&'a self, request: &mut Request<'a>) {
+        request.provide_value::<Layout>(self.layout);
+        if let Some(location) = self.location {
+            request.provide_ref::<Location<'static>>(location);
+        }
+        #[cfg(feature = "backtrace")]
+        if let Some(backtrace) = self.backtrace() {
+            request.provide_ref::<Backtrace>(backtrace);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<AllocError> for std::io::Error {
+    /// Converts an `AllocError` into an I/O error of [`ErrorKind::OutOfMemory`],
+    /// which keeps the `AllocError` as the inner error.
+    ///
+    /// [`ErrorKind::OutOfMemory`]: std::io::ErrorKind::OutOfMemory
+    #[inline]
+    fn from(e: AllocError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::OutOfMemory, e)
+    }
+}
+
+#[cfg(not(feature = "stable"))]
+impl TryFrom<AllocError> for core::alloc::AllocError {
+    type Error = AllocError;
+
+    /// Converts an `AllocError` into the error of the allocator API, discarding
+    /// the layout.
+    ///
+    /// An `AllocError` of [`AllocErrorKind::UnexpectedPanic`] is not a failure of
+    /// allocation, so it is returned back, keeping the panic.
+    #[inline]
+    fn try_from(e: AllocError) -> Result<Self, Self::Error> {
+        match e.kind {
+            AllocErrorKind::UnexpectedPanic => Err(e),
+            _ => Ok(core::alloc::AllocError),
+        }
+    }
+}
+
+impl From<core::alloc::LayoutError> for AllocError {
+    /// Converts a `LayoutError` into an `AllocError` of
+    /// [`AllocErrorKind::InvalidLayout`], whose layout is the zero-sized layout of
+    /// `()` as the required layout cannot be constructed.
+    #[inline]
+    fn from(_: core::alloc::LayoutError) -> Self {
+        AllocError::invalid_layout(Layout::new::<()>())
+    }
+}
+
+#[cfg(all(feature = "std", not(feature = "stable")))]
+impl From<std::collections::TryReserveError> for AllocError {
+    /// Converts a `TryReserveError` into an `AllocError`, keeping the layout passed to
+    /// the allocator, or of [`AllocErrorKind::CapacityOverflow`] if the capacity
+    /// overflows.
+    #[inline]
+    fn from(e: std::collections::TryReserveError) -> Self {
+        use std::collections::TryReserveErrorKind;
+
+        match e.kind() {
+            TryReserveErrorKind::AllocError { layout, .. } => AllocError::new(layout),
+            TryReserveErrorKind::CapacityOverflow => AllocError::capacity_overflow(),
+        }
+    }
+}
+
+/// The error type for [`catch_oom_result`](crate::catch_oom_result), which is
+/// either an allocation error or the error returned by the closure.
+///
+/// `?` converts an `AllocError` into it, and it converts into `anyhow::Error` or
+/// `Box<dyn Error + Send + Sync>` if `E` is an error. A blanket `From<E>` would
+/// conflict with `From<AllocError>` for `CatchError<AllocError>`, so the errors
+/// of the closure are converted by `?` inside the closure, which returns `E`,
+/// or by `map_err(CatchError::User)`. If `E` can be converted from `AllocError`,
+/// [`CatchError::into_user`] converts the whole error into it.
+///
+/// The error is transparent: it displays as the inner error, and its source is
+/// the source of the inner error, so error reporters do not print the inner error
+/// twice.
+///
+/// ```no_run
+/// use panic_safe::{catch_oom, catch_oom_result, CatchError};
+/// # use std::io;
+/// # use std::path::Path;
+/// # struct Table;
+/// # impl Table { fn parse(_: &str) -> Self { Table } }
+///
+/// fn load(path: &Path) -> Result<Table, CatchError<io::Error>> {
+///     let text = catch_oom_result(|| std::fs::read_to_string(path))?;
+///     let table = catch_oom(|| Table::parse(&text))?;
+///     Ok(table)
+/// }
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+///     let table = load(Path::new("table.csv"))?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub enum CatchError<E> {
+    /// Allocation error occurs.
+    Alloc(AllocError),
+    /// The closure returns an error.
+    User(E),
+}
+
+impl<E> CatchError<E> {
+    /// Returns the allocation error, if any.
+    #[must_use]
+    #[inline]
+    pub fn alloc_error(&self) -> Option<&AllocError> {
+        match self {
+            CatchError::Alloc(e) => Some(e),
+            CatchError::User(_) => None,
+        }
+    }
+
+    /// Returns the error returned by the closure, if any.
+    #[must_use]
+    #[inline]
+    pub fn user_error(&self) -> Option<&E> {
+        match self {
+            CatchError::Alloc(_) => None,
+            CatchError::User(e) => Some(e),
+        }
+    }
+
+    /// Maps the error returned by the closure by `op`, keeping the allocation error.
+    #[inline]
+    pub fn map_user<F, O: FnOnce(E) -> F>(self, op: O) -> CatchError<F> {
+        match self {
+            CatchError::Alloc(e) => CatchError::Alloc(e),
+            CatchError::User(e) => CatchError::User(op(e)),
+        }
+    }
+
+    /// Converts into the error type of the closure, converting the allocation
+    /// error by `From`, e.g. into an enum with a variant for it.
+    #[inline]
+    pub fn into_user(self) -> E
+    where
+        E: From<AllocError>,
+    {
+        match self {
+            CatchError::Alloc(e) => E::from(e),
+            CatchError::User(e) => e,
+        }
+    }
+}
+
+impl<E> From<AllocError> for CatchError<E> {
+    #[inline]
+    fn from(e: AllocError) -> Self {
+        CatchError::Alloc(e)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for CatchError<E> {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CatchError::Alloc(e) => fmt::Display::fmt(e, f),
+            CatchError::User(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for CatchError<E> {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CatchError::Alloc(e) => e.source(),
+            CatchError::User(e) => e.source(),
+        }
+    }
+
+    #[cfg(not(feature = "stable"))]
+    fn provide<'a>(&'a self, request: &mut Request<'a>) {
+        match self {
+            CatchError::Alloc(e) => e.provide(request),
+            CatchError::User(e) => e.provide(request),
+        }
+    }
+}
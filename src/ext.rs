@@ -0,0 +1,70 @@
+//! The extension trait invoking closures by the catching functions.
+
+use std::panic::UnwindSafe;
+
+use crate::{AllocError, CatchMode, CaughtError, PanicError};
+
+/// Invokes closures by the catching functions in method syntax, e.g.
+/// `(|| build(input)).catch_oom()`.
+///
+/// This composes with iterator adapters and builders, where the closures are
+/// passed around as values.
+///
+/// ```
+/// use panic_safe::CatchOomExt;
+///
+/// # fn parse(input: &str) -> usize { input.len() }
+/// # let inputs = ["a", "bc"];
+/// let results: Vec<_> = inputs
+///     .iter()
+///     .map(|input| (move || parse(input)).catch_oom_named("parse"))
+///     .collect();
+/// ```
+pub trait CatchOomExt<R>: FnOnce() -> R + UnwindSafe + Sized {
+    /// Invokes the closure by [`catch_oom`](crate::catch_oom).
+    #[track_caller]
+    fn catch_oom(self) -> Result<R, AllocError>;
+
+    /// Invokes the closure by [`catch_oom_named`](crate::catch_oom_named).
+    #[track_caller]
+    fn catch_oom_named(self, name: &'static str) -> Result<R, AllocError>;
+
+    /// Invokes the closure by [`catch_oom_with_mode`](crate::catch_oom_with_mode).
+    #[track_caller]
+    fn catch_oom_with_mode(self, mode: CatchMode) -> Result<R, CaughtError>;
+
+    /// Invokes the closure by [`catch_any`](crate::catch_any).
+    #[track_caller]
+    fn catch_any(self) -> Result<R, CaughtError>;
+
+    /// Invokes the closure by [`catch_panic`](crate::catch_panic).
+    #[track_caller]
+    fn catch_panic(self) -> Result<R, PanicError>;
+}
+
+impl<F: FnOnce() -> R + UnwindSafe, R> CatchOomExt<R> for F {
+    #[inline]
+    fn catch_oom(self) -> Result<R, AllocError> {
+        crate::catch_oom(self)
+    }
+
+    #[inline]
+    fn catch_oom_named(self, name: &'static str) -> Result<R, AllocError> {
+        crate::catch_oom_named(name, self)
+    }
+
+    #[inline]
+    fn catch_oom_with_mode(self, mode: CatchMode) -> Result<R, CaughtError> {
+        crate::catch_oom_with_mode(mode, self)
+    }
+
+    #[inline]
+    fn catch_any(self) -> Result<R, CaughtError> {
+        crate::catch_any(self)
+    }
+
+    #[inline]
+    fn catch_panic(self) -> Result<R, PanicError> {
+        crate::catch_panic(self)
+    }
+}
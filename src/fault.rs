@@ -0,0 +1,348 @@
+//! The fault-injection allocator failing chosen allocations.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{Location, RefUnwindSafe, UnwindSafe};
+
+use crate::catch::catch_oom_at;
+use crate::hook::ThreadPanic;
+use crate::AllocError;
+
+type Predicate = fn(Layout) -> bool;
+
+thread_local! {
+    // None needs a destructor, so accessing them never allocates.
+    static THREAD_COUNTDOWN: Cell<usize> = const { Cell::new(0) };
+    static THREAD_PREDICATE: Cell<Option<Predicate>> = const { Cell::new(None) };
+    static THREAD_ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static THREAD_LAST_FAILURE: Cell<Option<FailureTrace>> = const { Cell::new(None) };
+    // The gaps between the failures following the armed countdown.
+    static THREAD_SCHEDULE: Cell<(*const usize, usize)> = const { Cell::new((std::ptr::null(), 0)) };
+}
+
+/// A global allocator wrapper which fails chosen allocations, for testing the
+/// handling of allocation errors.
+///
+/// The failures are configured per thread, so tests in different threads do not
+/// interfere, by [`fail_at`](Self::fail_at) and [`fail_when`](Self::fail_when). A
+/// failed allocation returns null as if the memory were exhausted, so it is
+/// reported as `AllocError` in catching scopes. The wrapper composes with the
+/// other wrappers of this crate, e.g. `FaultInjector<TrackingAlloc>`, and should
+/// be wrapped by [`CatchAlloc`](crate::CatchAlloc) with the `stable` feature.
+///
+/// ```
+/// use panic_safe::{catch_oom, FaultInjector};
+/// use std::alloc::System;
+///
+/// # #[cfg(not(feature = "stable"))]
+/// #[global_allocator]
+/// static GLOBAL: FaultInjector<System> = FaultInjector::new(System);
+/// # #[cfg(feature = "stable")]
+/// # #[global_allocator]
+/// # static GLOBAL: panic_safe::CatchAlloc<FaultInjector<System>> = panic_safe::CatchAlloc::new(FaultInjector::new(System));
+///
+/// FaultInjector::fail_at(3);
+/// assert!(catch_oom(|| (0..10).map(|i| vec![i; 10]).collect::<Vec<_>>()).is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct FaultInjector<A = System>(A);
+
+impl<A> FaultInjector<A> {
+    /// Creates a new `FaultInjector` wrapping the given allocator.
+    #[must_use]
+    #[inline]
+    pub const fn new(alloc: A) -> Self {
+        FaultInjector(alloc)
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.0
+    }
+}
+
+impl FaultInjector {
+    /// Makes the `n`th allocation of current thread from now fail, counting from 1.
+    ///
+    /// Only that allocation fails, the following allocations succeed. `n` of 0
+    /// disarms the countdown. Reallocations count as allocations. The hooks are
+    /// installed beforehand, so the allocations of installing them in the first
+    /// catching scope are not counted.
+    #[inline]
+    pub fn fail_at(n: usize) {
+        crate::init();
+        THREAD_COUNTDOWN.with(|c| c.set(n));
+        THREAD_ALLOCATIONS.with(|a| a.set(0));
+    }
+
+    /// Makes every allocation of current thread whose layout satisfies the
+    /// predicate fail, e.g. `FaultInjector::fail_when(|layout| layout.size() > 4096)`.
+    ///
+    /// The hooks are installed beforehand, like by [`fail_at`](Self::fail_at).
+    #[inline]
+    pub fn fail_when(predicate: fn(Layout) -> bool) {
+        crate::init();
+        THREAD_PREDICATE.with(|p| p.set(Some(predicate)));
+        THREAD_ALLOCATIONS.with(|a| a.set(0));
+    }
+
+    /// Disarms all failures of current thread.
+    #[inline]
+    pub fn disarm() {
+        THREAD_COUNTDOWN.with(|c| c.set(0));
+        THREAD_PREDICATE.with(|p| p.set(None));
+    }
+
+    /// Returns the number of allocations of current thread since the last
+    /// [`fail_at`](Self::fail_at) or [`fail_when`](Self::fail_when), including the
+    /// failed ones.
+    #[must_use]
+    #[inline]
+    pub fn allocations() -> usize {
+        THREAD_ALLOCATIONS.with(Cell::get)
+    }
+
+    /// Returns the trace of the last injected failure of current thread, which
+    /// can be replayed by [`replay_failure`].
+    #[must_use]
+    #[inline]
+    pub fn last_failure() -> Option<FailureTrace> {
+        THREAD_LAST_FAILURE.with(Cell::get)
+    }
+}
+
+/// The point of an injected allocation failure, for replaying it later.
+///
+/// The trace is the position of the failed allocation counted from the last
+/// [`FaultInjector::fail_at`] or [`FaultInjector::fail_when`], and the layout of
+/// it. It is serializable with the
+/// `serde` feature, and is printed as `allocation 3 of 40 bytes aligned to 8`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FailureTrace {
+    index: usize,
+    layout: Layout,
+}
+
+impl FailureTrace {
+    /// Creates a trace of the `index`th allocation failing with `layout`.
+    #[must_use]
+    #[inline]
+    pub const fn new(index: usize, layout: Layout) -> Self {
+        FailureTrace { index, layout }
+    }
+
+    /// Returns the position of the failed allocation, counting from 1.
+    #[must_use]
+    #[inline]
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Returns the layout of the failed allocation.
+    #[must_use]
+    #[inline]
+    pub const fn layout(&self) -> Layout {
+        self.layout
+    }
+}
+
+impl fmt::Display for FailureTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "allocation {} of {} bytes aligned to {}",
+            self.index,
+            self.layout.size(),
+            self.layout.align()
+        )
+    }
+}
+
+/// Invokes a closure like [`catch_oom`](crate::catch_oom), failing the same
+/// allocation as the run `trace` is recorded from.
+///
+/// The closure must allocate the same as that run up to the failed allocation,
+/// which is checked by its layout: this function panics if the replayed failure
+/// has another layout. If the closure completes without reaching it, `Ok` is
+/// returned.
+///
+/// ```
+/// use panic_safe::{catch_oom, replay_failure, FaultInjector};
+/// # #[cfg(not(feature = "stable"))]
+/// # #[global_allocator]
+/// # static GLOBAL: FaultInjector = FaultInjector::new(std::alloc::System);
+/// # #[cfg(feature = "stable")]
+/// # #[global_allocator]
+/// # static GLOBAL: panic_safe::CatchAlloc<FaultInjector> = panic_safe::CatchAlloc::new(FaultInjector::new(std::alloc::System));
+/// # fn run() -> Vec<u8> { vec![0; 8192] }
+///
+/// FaultInjector::fail_when(|layout| layout.size() > 4096);
+/// assert!(catch_oom(run).is_err());
+/// let trace = FaultInjector::last_failure().unwrap();
+/// FaultInjector::disarm();
+///
+/// // Later, e.g. with the trace restored from a CI log.
+/// assert!(replay_failure(&trace, run).is_err());
+/// ```
+#[track_caller]
+pub fn replay_failure<F: FnOnce() -> R + UnwindSafe, R>(trace: &FailureTrace, f: F) -> Result<R, AllocError> {
+    let caller = Location::caller();
+    FaultInjector::fail_at(trace.index);
+    THREAD_LAST_FAILURE.with(|l| l.set(None));
+    let result = catch_oom_at(caller, f);
+    FaultInjector::disarm();
+    if let Some(failure) = FaultInjector::last_failure() {
+        assert_eq!(
+            failure.layout, trace.layout,
+            "replayed allocation {} has another layout",
+            trace.index
+        );
+    }
+    result
+}
+
+/// Runs a closure repeatedly, failing its 1st allocation, then its 2nd, and so on,
+/// until it completes without reaching the failing allocation.
+///
+/// Every injected failure must be caught as `AllocError`, otherwise this function
+/// panics. A panic other than the out-of-memory panic is handled by the global
+/// [`catch_mode`](crate::catch_mode), so it aborts the process by default. The
+/// closure should leave no state behind between the runs, as each run must
+/// allocate the same as the previous ones up to its failing allocation.
+///
+/// Returns the number of allocations of the closure, i.e. the number of checked
+/// runs. [`FaultInjector`] must be registered as the global allocator, otherwise
+/// the closure runs once and 0 is returned.
+///
+/// ```
+/// use panic_safe::exhaustive_oom_check;
+/// # #[cfg(not(feature = "stable"))]
+/// # #[global_allocator]
+/// # static GLOBAL: panic_safe::FaultInjector = panic_safe::FaultInjector::new(std::alloc::System);
+/// # #[cfg(feature = "stable")]
+/// # #[global_allocator]
+/// # static GLOBAL: panic_safe::CatchAlloc<panic_safe::FaultInjector> =
+/// #     panic_safe::CatchAlloc::new(panic_safe::FaultInjector::new(std::alloc::System));
+///
+/// let runs = exhaustive_oom_check(|| {
+///     let mut map = std::collections::HashMap::new();
+///     map.insert(1, vec![0u8; 16]);
+/// });
+/// assert!(runs > 0);
+/// ```
+#[track_caller]
+pub fn exhaustive_oom_check<F: Fn() -> R + RefUnwindSafe, R>(f: F) -> usize {
+    let caller = Location::caller();
+    let mut n = 1;
+    loop {
+        FaultInjector::fail_at(n);
+        let result = catch_oom_at(caller, &f);
+        // The countdown reaches 0 once the `n`th allocation has failed.
+        let injected = THREAD_COUNTDOWN.with(Cell::get) == 0;
+        FaultInjector::disarm();
+        match result {
+            Ok(_) if injected => panic!("allocation {n} failed but the closure succeeded"),
+            Ok(_) => return n - 1,
+            Err(_) if injected => n += 1,
+            Err(e) => panic!("allocation failed without injection: {e}"),
+        }
+    }
+}
+
+/// Invokes a closure with the allocations of current thread failing at the given
+/// gaps: the `gaps[0]`th allocation fails, then the `gaps[1]`th allocation after
+/// it, and so on. All failures are disarmed afterwards.
+#[cfg(feature = "fuzz")]
+pub(crate) fn with_schedule<F: FnOnce() -> R, R>(gaps: &[usize], f: F) -> R {
+    struct Disarm;
+
+    impl Drop for Disarm {
+        #[inline]
+        fn drop(&mut self) {
+            THREAD_SCHEDULE.with(|s| s.set((std::ptr::null(), 0)));
+            FaultInjector::disarm();
+        }
+    }
+
+    let _disarm = Disarm;
+    if let Some((&first, rest)) = gaps.split_first() {
+        FaultInjector::fail_at(first);
+        THREAD_SCHEDULE.with(|s| s.set((rest.as_ptr(), rest.len())));
+    }
+    f()
+}
+
+impl<A> FaultInjector<A> {
+    /// Checks if the allocation of `layout` should fail.
+    ///
+    /// Allocations raising the out-of-memory panic and unwinding never fail, so
+    /// the injected failure is handled as a real one would be in the best case.
+    #[inline]
+    fn inject(layout: Layout) -> bool {
+        if ThreadPanic::handling_oom() || std::thread::panicking() {
+            return false;
+        }
+        THREAD_ALLOCATIONS.with(|a| a.set(a.get().wrapping_add(1)));
+        let countdown = THREAD_COUNTDOWN.with(|c| {
+            let n = c.get();
+            if n > 0 {
+                c.set(n - 1);
+            }
+            n
+        });
+        if countdown == 1 {
+            // Arm the next failure of the schedule if any.
+            let (next, len) = THREAD_SCHEDULE.with(Cell::get);
+            if len > 0 {
+                // SAFETY: the schedule is borrowed by `with_schedule`, which removes it when returning.
+                let gap = unsafe { *next };
+                THREAD_SCHEDULE.with(|s| s.set((unsafe { next.add(1) }, len - 1)));
+                THREAD_COUNTDOWN.with(|c| c.set(gap));
+            }
+        }
+        let fail = countdown == 1
+            || THREAD_PREDICATE
+                .with(Cell::get)
+                .is_some_and(|predicate| predicate(layout));
+        if fail {
+            let index = THREAD_ALLOCATIONS.with(Cell::get);
+            THREAD_LAST_FAILURE.with(|l| l.set(Some(FailureTrace::new(index, layout))));
+        }
+        fail
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for FaultInjector<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if Self::inject(layout) {
+            return std::ptr::null_mut();
+        }
+        self.0.alloc(layout)
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if Self::inject(layout) {
+            return std::ptr::null_mut();
+        }
+        self.0.alloc_zeroed(layout)
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if Self::inject(new_layout) {
+            return std::ptr::null_mut();
+        }
+        self.0.realloc(ptr, layout, new_size)
+    }
+}
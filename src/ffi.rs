@@ -0,0 +1,43 @@
+//! The catching function for FFI boundaries.
+
+use std::panic::{self, AssertUnwindSafe, Location};
+
+use crate::catch::catch_oom_with_mode_at;
+use crate::{CatchMode, CaughtError, PanicError};
+
+/// Invokes a closure at an FFI boundary, e.g. in an `extern "C"` function,
+/// capturing the allocation error or the panic if one occurs, so no unwinding
+/// escapes it.
+///
+/// This is [`catch_any`](crate::catch_any) without the `UnwindSafe` bound, also
+/// returning the unwinding which is not a panic of the closure as
+/// `CaughtError::Panic`, e.g. a value thrown by [`throw`](crate::throw) without
+/// a `catch`, or a payload resumed by `resume_unwind`. The closure usually
+/// captures raw pointers of the caller, whose state is the caller's concern.
+/// The `ffi_guard` attribute of the `derive` feature wraps the body of a
+/// function by it.
+///
+/// ```
+/// use panic_safe::{catch_ffi, CaughtError};
+/// # use std::ffi::c_int;
+/// # struct Index;
+/// # impl Index { fn build(_: &[u8]) -> Self { Index } }
+///
+/// #[no_mangle]
+/// pub extern "C" fn index_build(input: *const u8, len: usize) -> c_int {
+///     match catch_ffi(|| Index::build(unsafe { std::slice::from_raw_parts(input, len) })) {
+///         Ok(_) => 0,
+///         Err(CaughtError::Oom(_)) => -2,
+///         Err(CaughtError::Panic(_)) => -1,
+///     }
+/// }
+/// ```
+#[track_caller]
+#[inline]
+pub fn catch_ffi<F: FnOnce() -> R, R>(f: F) -> Result<R, CaughtError> {
+    let caller = Location::caller();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        catch_oom_with_mode_at(CatchMode::ReturnError, caller, AssertUnwindSafe(f))
+    }));
+    result.unwrap_or_else(|payload| Err(CaughtError::Panic(PanicError::new(None, payload))))
+}
@@ -0,0 +1,76 @@
+//! Catching allocation errors in asynchronous code.
+
+use std::future::Future;
+use std::panic::{AssertUnwindSafe, Location, UnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+/// Future for the [`catch_oom_future`] function.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+#[derive(Debug)]
+pub struct CatchOom<F> {
+    future: F,
+    caller: &'static Location<'static>,
+}
+
+/// Wraps a future, capturing the out-of-memory panic if one occurs while polling it.
+///
+/// The returned future resolves to `Ok` with the output of the inner future if it
+/// does not panic, and resolves to `AllocError` if allocation error occurs in any
+/// poll. Other panics are handled as [`catch_oom`](crate::catch_oom) does.
+///
+/// The inner future should not be polled again after resolving to `AllocError`.
+/// The location of this call is recorded in the `AllocError`.
+///
+/// In a web service, wrapping the handler of each request maps an allocation
+/// error to a response, e.g. `503 Service Unavailable`, instead of taking down
+/// the whole service. A middleware does the same by wrapping the future of the
+/// inner service:
+///
+/// ```ignore
+/// async fn report(Json(query): Json<Query>) -> Response {
+///     match panic_safe::catch_oom_future(AssertUnwindSafe(build_report(query))).await {
+///         Ok(report) => Json(report).into_response(),
+///         Err(_) => (StatusCode::SERVICE_UNAVAILABLE, [(header::RETRY_AFTER, "30")]).into_response(),
+///     }
+/// }
+/// ```
+///
+/// Likewise, a gRPC method maps it to `RESOURCE_EXHAUSTED`, with the failed
+/// layout in the message given by the `AllocError`:
+///
+/// ```ignore
+/// async fn search(&self, request: Request<SearchRequest>) -> Result<Response<SearchReply>, Status> {
+///     panic_safe::catch_oom_future(AssertUnwindSafe(self.index.search(request.into_inner())))
+///         .await
+///         .map_err(|e| Status::resource_exhausted(e.to_string()))?
+///         .map(Response::new)
+/// }
+/// ```
+#[track_caller]
+#[inline]
+pub fn catch_oom_future<F: Future + UnwindSafe>(future: F) -> CatchOom<F> {
+    CatchOom {
+        future,
+        caller: Location::caller(),
+    }
+}
+
+impl<F: Future + UnwindSafe> Future for CatchOom<F> {
+    type Output = Result<F::Output, AllocError>;
+
+    #[inline]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let caller = self.caller;
+        // SAFETY: `future` is pinned as a part of `self` and never moved.
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.future) };
+        match catch_oom_at(caller, AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(e) => Poll::Ready(Err(e)),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Allocation failure schedules for fuzzing.
+
+use std::panic::{Location, UnwindSafe};
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+/// The allocations to fail in a run, derived from the input of a fuzzer.
+///
+/// The input is read as a sequence of LEB128 encoded integers, each being the gap
+/// to the next failing allocation minus 1, so `[0, 2]` fails the 1st allocation
+/// and the 4th allocation. A fuzzer thus explores the allocation errors at arbitrary
+/// points of the closure, including the ones after the nested catching scopes
+/// handled a failure. [`FaultInjector`](crate::FaultInjector) must be registered as
+/// the global allocator.
+///
+/// ```
+/// # mod libfuzzer_sys {
+/// #     macro_rules! fuzz_target {
+/// #         (|$data:ident: &[u8]| $body:block) => { fn main() { let $data: &[u8] = &[0, 2]; $body } };
+/// #     }
+/// #     pub(crate) use fuzz_target;
+/// # }
+/// # mod my_crate { pub fn build_index() -> Vec<Vec<u32>> { (0..4).map(|i| vec![i; 4]).collect() } }
+/// use libfuzzer_sys::fuzz_target;
+/// use panic_safe::{FailureSchedule, FaultInjector};
+///
+/// #[global_allocator]
+/// static GLOBAL: FaultInjector = FaultInjector::new(std::alloc::System);
+///
+/// fuzz_target!(|data: &[u8]| {
+///     let _ = FailureSchedule::from_bytes(data).run(|| my_crate::build_index());
+/// });
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FailureSchedule {
+    gaps: Vec<usize>,
+}
+
+impl FailureSchedule {
+    /// Creates a schedule from the input of a fuzzer.
+    ///
+    /// A truncated integer at the end of the input is ignored.
+    #[must_use]
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut gaps = Vec::new();
+        let mut value = 0usize;
+        let mut shift = 0u32;
+        for &byte in data {
+            if shift < usize::BITS {
+                value |= usize::from(byte & 0x7f) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                gaps.push(value.saturating_add(1));
+                value = 0;
+                shift = 0;
+            }
+        }
+        FailureSchedule { gaps }
+    }
+
+    /// Returns the positions of the failing allocations, counting from 1.
+    pub fn failures(&self) -> impl Iterator<Item = usize> + '_ {
+        self.gaps.iter().scan(0usize, |index, &gap| {
+            *index = index.saturating_add(gap);
+            Some(*index)
+        })
+    }
+
+    /// Invokes a closure like [`catch_oom`](crate::catch_oom), failing the
+    /// allocations of current thread by the schedule.
+    ///
+    /// The failures are disarmed when this function returns.
+    #[track_caller]
+    pub fn run<F: FnOnce() -> R + UnwindSafe, R>(&self, f: F) -> Result<R, AllocError> {
+        let caller = Location::caller();
+        crate::fault::with_schedule(&self.gaps, || catch_oom_at(caller, f))
+    }
+}
@@ -0,0 +1,89 @@
+//! Global allocator wrappers.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+
+use crate::hook::{oom_hook, suppress_oom, ThreadPanic};
+
+/// A global allocator wrapper which raises the out-of-memory panic by itself.
+///
+/// When an allocation fails in a catching scope, the wrapper records the failing
+/// layout and panics, so the failure is caught by [`catch_oom`](crate::catch_oom)
+/// and friends without the unstable allocation error hook. Outside catching scopes
+/// the failure is reported by returning null as usual.
+///
+/// This is how allocation errors are caught with the `stable` feature, where the
+/// wrapper must be registered as the global allocator:
+///
+/// ```
+/// use panic_safe::CatchAlloc;
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static GLOBAL: CatchAlloc<System> = CatchAlloc::new(System);
+/// ```
+///
+/// An allocation failing in a catching scope while unwinding, e.g. in a destructor,
+/// cannot be raised, as panicking again aborts the process. It is retried after
+/// releasing the [emergency reserve](crate::set_emergency_reserve) instead, and
+/// recorded by [`AllocError::suppressed`](crate::AllocError::suppressed).
+///
+/// Note that unwinding out of a global allocator is not guaranteed to be supported
+/// by Rust, although it works with the unwinding implementations of the current
+/// standard library.
+#[derive(Debug, Default)]
+pub struct CatchAlloc<A = System>(A);
+
+impl<A> CatchAlloc<A> {
+    /// Creates a new `CatchAlloc` wrapping the given allocator.
+    #[must_use]
+    #[inline]
+    pub const fn new(alloc: A) -> Self {
+        CatchAlloc(alloc)
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.0
+    }
+}
+
+impl<A> CatchAlloc<A> {
+    #[inline]
+    fn check(ptr: *mut u8, layout: Layout, retry: impl FnOnce() -> *mut u8) -> *mut u8 {
+        if ptr.is_null() && ThreadPanic::mode().is_some() {
+            if ThreadPanic::cannot_raise() {
+                suppress_oom(layout);
+                return retry();
+            }
+            oom_hook(layout);
+        }
+        ptr
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CatchAlloc<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::check(self.0.alloc(layout), layout, || self.0.alloc(layout))
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::check(self.0.alloc_zeroed(layout), layout, || self.0.alloc_zeroed(layout))
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        Self::check(self.0.realloc(ptr, layout, new_size), new_layout, || {
+            self.0.realloc(ptr, layout, new_size)
+        })
+    }
+}
@@ -0,0 +1,95 @@
+//! The cleanup guards run when a catching scope unwinds.
+
+use crate::slot::ThreadAllocError;
+
+/// The guard returned by [`on_oom`], which runs the closure if dropped while
+/// unwinding from an allocation error.
+#[must_use = "the closure runs when the guard is dropped"]
+pub struct OnOom<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> OnOom<F> {
+    /// Drops the guard without running the closure, e.g. once the changes to roll
+    /// back are committed.
+    #[inline]
+    pub fn dismiss(mut self) {
+        self.0 = None;
+    }
+}
+
+impl<F: FnOnce()> Drop for OnOom<F> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(f) = self.0.take() {
+            if std::thread::panicking() && ThreadAllocError::layout().is_some() {
+                f();
+            }
+        }
+    }
+}
+
+/// Returns a guard which runs `f` if the enclosing catching scope ends with an
+/// allocation error, i.e. when the guard is dropped while unwinding from one.
+///
+/// This rolls back partial changes made by the closure of
+/// [`catch_oom`](crate::catch_oom), e.g. to a collection outside of it, which
+/// would be left inconsistent by the interrupted closure. The closure does not
+/// run if the guard is dropped normally or by another panic, or if
+/// [`OnOom::dismiss`] is called.
+///
+/// The closure runs while unwinding, so it must not panic, which aborts the
+/// process. An allocation failing in it is suppressed as described by
+/// [`AllocError::suppressed`](crate::AllocError::suppressed).
+///
+/// ```
+/// use panic_safe::{catch_oom, on_oom};
+/// use std::cell::RefCell;
+/// use std::panic::AssertUnwindSafe;
+///
+/// # fn parse(input: &str) -> Vec<u8> { input.bytes().collect() }
+/// # let input = String::from("input");
+/// let entries = RefCell::new(Vec::new());
+/// let result = catch_oom(AssertUnwindSafe(|| {
+///     let len = entries.borrow().len();
+///     let _rollback = on_oom(|| entries.borrow_mut().truncate(len));
+///     entries.borrow_mut().extend(parse(&input));
+/// }));
+/// ```
+#[inline]
+pub fn on_oom<F: FnOnce()>(f: F) -> OnOom<F> {
+    OnOom(Some(f))
+}
+
+/// The guard returned by [`defer`], which runs the closure when dropped.
+#[must_use = "the closure runs when the guard is dropped"]
+pub struct Defer<F: FnOnce()>(Option<F>);
+
+impl<F: FnOnce()> Drop for Defer<F> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(f) = self.0.take() {
+            f();
+        }
+    }
+}
+
+/// Returns a guard which runs `f` when dropped, whether the enclosing scope ends
+/// normally, with an allocation error or with another panic.
+///
+/// Like the closure of [`on_oom`], the closure must not panic when running
+/// while unwinding.
+///
+/// ```
+/// use panic_safe::{catch_oom, defer};
+///
+/// # struct Table;
+/// # impl Table { fn unlock(&self) {} fn rebuild(&self) -> usize { 0 } }
+/// # let table = Table;
+/// let result = catch_oom(|| {
+///     let _unlock = defer(|| table.unlock());
+///     table.rebuild()
+/// });
+/// ```
+#[inline]
+pub fn defer<F: FnOnce()>(f: F) -> Defer<F> {
+    Defer(Some(f))
+}
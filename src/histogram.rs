@@ -0,0 +1,162 @@
+//! The size-class histogram of the live allocations.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+use crate::error::HumanSize;
+use crate::AllocError;
+
+/// The number of size classes, one for each power of two up to `2^64`.
+const CLASSES: usize = usize::BITS as usize + 1;
+
+static COUNTS: [AtomicUsize; CLASSES] = [const { AtomicUsize::new(0) }; CLASSES];
+static BYTES: [AtomicUsize; CLASSES] = [const { AtomicUsize::new(0) }; CLASSES];
+static OOM_REPORTER: RwLock<Option<OomReporter>> = RwLock::new(None);
+
+type OomReporter = fn(&AllocError, &HeapHistogram);
+
+/// Returns the size class of `size`, i.e. the smallest `k` with `size <= 2^k`.
+#[inline]
+fn class(size: usize) -> usize {
+    (usize::BITS - size.saturating_sub(1).leading_zeros()) as usize
+}
+
+#[inline]
+pub(crate) fn allocated(size: usize) {
+    let class = class(size);
+    COUNTS[class].fetch_add(1, Ordering::Relaxed);
+    BYTES[class].fetch_add(size, Ordering::Relaxed);
+}
+
+#[inline]
+pub(crate) fn deallocated(size: usize) {
+    let class = class(size);
+    COUNTS[class].fetch_sub(1, Ordering::Relaxed);
+    BYTES[class].fetch_sub(size, Ordering::Relaxed);
+}
+
+/// A snapshot of the live allocations of the process by size class.
+///
+/// The size classes are the powers of two, so the histogram tells whether the
+/// memory is held by a few huge allocations or by many small ones. It is taken
+/// by [`heap_histogram`], and does not allocate, so it can be taken when an
+/// allocation error is caught, e.g. in a listener registered by
+/// [`register_low_memory_listener`](crate::register_low_memory_listener), or
+/// where the allocation fails by [`set_heap_histogram_reporter`]. The alternate
+/// form `{:#}` prints the empty classes too.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HeapHistogram {
+    counts: [usize; CLASSES],
+    bytes: [usize; CLASSES],
+}
+
+impl HeapHistogram {
+    /// Returns the non-empty size classes in ascending order, as tuples of the
+    /// maximum size of the class, the number of the live allocations and the
+    /// bytes of them.
+    pub fn classes(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        (0..CLASSES)
+            .filter(|&class| self.counts[class] > 0)
+            .map(|class| (max_size(class), self.counts[class], self.bytes[class]))
+    }
+
+    /// Returns the number of the live allocations.
+    #[must_use]
+    pub fn count(&self) -> usize {
+        self.counts.iter().fold(0, |sum, &count| sum.wrapping_add(count))
+    }
+
+    /// Returns the bytes of the live allocations.
+    #[must_use]
+    pub fn bytes(&self) -> usize {
+        self.bytes.iter().fold(0, |sum, &bytes| sum.wrapping_add(bytes))
+    }
+}
+
+#[inline]
+fn max_size(class: usize) -> usize {
+    1usize.checked_shl(class as u32).unwrap_or(usize::MAX)
+}
+
+impl fmt::Display for HeapHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for class in 0..CLASSES {
+            let count = self.counts[class];
+            if count > 0 || f.alternate() {
+                writeln!(
+                    f,
+                    "<= {}: {} allocations, {}",
+                    HumanSize(max_size(class)),
+                    count,
+                    HumanSize(self.bytes[class])
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Takes the size-class histogram of the live allocations of the process.
+///
+/// The allocations are counted by [`TrackingAlloc`](crate::TrackingAlloc), which
+/// must be registered as the global allocator, otherwise the histogram is empty.
+///
+/// ```
+/// use panic_safe::{heap_histogram, register_low_memory_listener};
+///
+/// register_low_memory_listener(|layout| {
+///     eprintln!("failed to allocate {} bytes, live allocations:\n{}", layout.size(), heap_histogram());
+///     0
+/// });
+/// ```
+#[must_use]
+pub fn heap_histogram() -> HeapHistogram {
+    let mut histogram = HeapHistogram {
+        counts: [0; CLASSES],
+        bytes: [0; CLASSES],
+    };
+    for class in 0..CLASSES {
+        histogram.counts[class] = COUNTS[class].load(Ordering::Relaxed);
+        histogram.bytes[class] = BYTES[class].load(Ordering::Relaxed);
+    }
+    histogram
+}
+
+/// Sets the reporter invoked with the histogram of the live allocations where an
+/// allocation fails, returning the previous one.
+///
+/// The reporter is invoked synchronously on the failing thread when the
+/// allocation error is raised, after the [emergency reserve](crate::set_emergency_reserve)
+/// is released and before unwinding, so the histogram tells whether the failure
+/// is one huge request or the memory is held by many small allocations. The
+/// error is not caught yet, so it has no location.
+///
+/// As a function pointer, the reporter is stored and invoked without allocating,
+/// and the histogram is taken on the stack. The reporter itself should avoid
+/// allocating, as the process is short of memory, and must not panic.
+///
+/// ```
+/// use panic_safe::{catch_oom, set_heap_histogram_reporter, AllocError, HeapHistogram};
+///
+/// fn report(e: &AllocError, histogram: &HeapHistogram) {
+///     eprintln!("{}, live allocations:\n{}", e, histogram);
+/// }
+///
+/// set_heap_histogram_reporter(Some(report));
+/// let _ = catch_oom(|| vec![0u8; 1 << 20]);
+/// ```
+pub fn set_heap_histogram_reporter(reporter: Option<OomReporter>) -> Option<OomReporter> {
+    std::mem::replace(
+        &mut *OOM_REPORTER.write().unwrap_or_else(PoisonError::into_inner),
+        reporter,
+    )
+}
+
+/// Invokes the reporter, if any, with the histogram taken where `e` is raised.
+pub(crate) fn report_oom(e: &AllocError) {
+    let reporter = *OOM_REPORTER.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(reporter) = reporter {
+        reporter(e, &heap_histogram());
+    }
+}
@@ -0,0 +1,621 @@
+//! The panic hook and the allocation error hook.
+
+use std::alloc::Layout;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::panic::PanicHookInfo;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError, RwLock};
+
+use crate::catch::{abort_outside_scopes, catch_mode};
+use crate::slot::ThreadAllocError;
+use crate::{AllocError, CatchMode, PanicLocation};
+
+thread_local! {
+    static THREAD_CATCH_MODE: Cell<Option<CatchMode>> = const { Cell::new(None) };
+    static THREAD_PANIC_LOCATION: Cell<Option<PanicLocation>> = const { Cell::new(None) };
+    static THREAD_PAYLOAD_TRANSPORT: Cell<bool> = const { Cell::new(false) };
+    static THREAD_HANDLING_OOM: Cell<bool> = const { Cell::new(false) };
+    static THREAD_HANDLING_LAYOUT: Cell<Option<Layout>> = const { Cell::new(None) };
+    static THREAD_RAISING_REENTERED: Cell<bool> = const { Cell::new(false) };
+    static THREAD_SCOPE_IN_UNWINDING: Cell<bool> = const { Cell::new(false) };
+    static THREAD_HOOK_SEEN: Cell<bool> = const { Cell::new(false) };
+    static THREAD_IN_HOOK: Cell<bool> = const { Cell::new(false) };
+    static THREAD_PANIC_HANDLER: Cell<Option<*const PanicHandler>> = const { Cell::new(None) };
+    static THREAD_HANDLED_MODE: Cell<Option<CatchMode>> = const { Cell::new(None) };
+}
+
+/// The handler deciding how a panic other than allocation error is handled, see
+/// [`catch_oom_with_hook`](crate::catch_oom_with_hook).
+pub(crate) type PanicHandler = dyn Fn(&PanicHookInfo<'_>) -> CatchMode;
+
+/// Panic state of current thread, used to decide how a panic is handled and to
+/// capture the panic location.
+pub(crate) struct ThreadPanic;
+
+impl ThreadPanic {
+    /// Sets the catch mode of current thread, returns the previous mode.
+    #[inline]
+    pub(crate) fn set_mode(mode: Option<CatchMode>) -> Option<CatchMode> {
+        THREAD_CATCH_MODE.with(|m| m.replace(mode))
+    }
+
+    /// Returns the catch mode of current thread, `None` if not in a catching scope.
+    #[inline]
+    pub(crate) fn mode() -> Option<CatchMode> {
+        THREAD_CATCH_MODE.with(|m| m.get())
+    }
+
+    /// Makes the out-of-memory panic of current thread carry the allocation error
+    /// in the payload rather than the thread error slot.
+    ///
+    /// This is used by threads whose panics are propagated to other threads.
+    #[inline]
+    pub(crate) fn set_payload_transport(enabled: bool) {
+        THREAD_PAYLOAD_TRANSPORT.with(|t| t.set(enabled));
+    }
+
+    /// Checks if the out-of-memory panic of current thread carries the allocation error.
+    #[inline]
+    pub(crate) fn payload_transport() -> bool {
+        THREAD_PAYLOAD_TRANSPORT.with(|t| t.get())
+    }
+
+    /// Checks if current thread is allocating for the handling of allocation
+    /// errors, i.e. raising the out-of-memory panic or refilling the reserves.
+    #[inline]
+    pub(crate) fn handling_oom() -> bool {
+        THREAD_HANDLING_OOM.with(Cell::get)
+    }
+
+    /// Marks whether the catching scope of current thread is entered while
+    /// unwinding, returns the previous mark.
+    #[inline]
+    pub(crate) fn set_scope_in_unwinding(in_unwinding: bool) -> bool {
+        THREAD_SCOPE_IN_UNWINDING.with(|s| s.replace(in_unwinding))
+    }
+
+    /// Checks if an allocation error of current thread cannot be raised, as the
+    /// thread is unwinding, e.g. running a destructor, where panicking again aborts
+    /// the process.
+    ///
+    /// An allocation error can be raised while raising another one, or in a
+    /// catching scope entered while unwinding, which catches it.
+    #[inline]
+    pub(crate) fn cannot_raise() -> bool {
+        std::thread::panicking() && !ThreadPanic::handling_oom() && !THREAD_SCOPE_IN_UNWINDING.with(Cell::get)
+    }
+
+    /// Records the location of the panic in current thread.
+    ///
+    /// The location is not recorded if allocating it fails, as the failure cannot
+    /// be raised in the panic hook.
+    #[inline]
+    pub(crate) fn record(info: &PanicHookInfo<'_>) {
+        let location = info.location().and_then(PanicLocation::try_from_location);
+        THREAD_PANIC_LOCATION.with(|l| l.set(location));
+    }
+
+    /// Takes the panic location from current thread.
+    #[inline]
+    pub(crate) fn take_location() -> Option<PanicLocation> {
+        THREAD_PANIC_LOCATION.with(|l| l.take())
+    }
+
+    /// Sets the panic handler of current thread, returns the previous handler.
+    ///
+    /// The handler must outlive the scope it is set for.
+    #[inline]
+    pub(crate) fn set_handler(handler: Option<*const PanicHandler>) -> Option<*const PanicHandler> {
+        THREAD_PANIC_HANDLER.with(|h| h.replace(handler))
+    }
+
+    /// Takes the catch mode decided by the panic handler of current thread.
+    #[inline]
+    pub(crate) fn take_handled_mode() -> Option<CatchMode> {
+        THREAD_HANDLED_MODE.with(Cell::take)
+    }
+
+    /// Takes whether our panic hook has been invoked on current thread since the
+    /// last call.
+    #[inline]
+    pub(crate) fn take_hook_seen() -> bool {
+        THREAD_HOOK_SEEN.with(|s| s.replace(false))
+    }
+}
+
+/// The payload of the out-of-memory panic, whose allocation error is recorded in
+/// the thread error slot.
+///
+/// It is zero-sized, so boxing it does not allocate, unlike the formatted message
+/// of `panic!`.
+pub(crate) struct OomPanic;
+
+/// Marks current thread as handling allocation errors until dropped.
+///
+/// The layout of the allocation error being handled, if any, is recorded as well.
+pub(crate) struct HandlingOom(bool, Option<Layout>);
+
+impl HandlingOom {
+    #[inline]
+    pub(crate) fn enter() -> Self {
+        HandlingOom::enter_with(None)
+    }
+
+    #[inline]
+    fn enter_with(layout: Option<Layout>) -> Self {
+        HandlingOom(
+            THREAD_HANDLING_OOM.with(|h| h.replace(true)),
+            THREAD_HANDLING_LAYOUT.with(|l| l.replace(layout)),
+        )
+    }
+}
+
+impl Drop for HandlingOom {
+    #[inline]
+    fn drop(&mut self) {
+        THREAD_HANDLING_OOM.with(|h| h.set(self.0));
+        THREAD_HANDLING_LAYOUT.with(|l| l.set(self.1));
+    }
+}
+
+/// Clears the mark of raising the minimal allocation error on drop.
+struct RaisingReentered;
+
+impl Drop for RaisingReentered {
+    #[inline]
+    fn drop(&mut self) {
+        THREAD_RAISING_REENTERED.with(|r| r.set(false));
+    }
+}
+
+/// The context of the allocation error raised if allocation fails again while
+/// handling an allocation error.
+const REENTERED_CONTEXT: &str = "allocation failed again while handling the allocation error";
+
+/// Handles the failure of an allocation of `layout` which cannot be raised, see
+/// [`ThreadPanic::cannot_raise`].
+///
+/// The emergency reserve is released so the allocation may be retried, and the
+/// failure is recorded as suppressed by the allocation error being unwound, if any.
+pub(crate) fn suppress_oom(layout: Layout) {
+    crate::emergency::release();
+    crate::stats::record_oom(layout);
+    ThreadAllocError::suppress(layout);
+}
+
+pub(crate) fn oom_hook(layout: Layout) {
+    if ThreadPanic::cannot_raise() {
+        // The hook cannot retry the allocation like `CatchAlloc` and
+        // `TrackingAlloc` do, and the process aborts once it returns, so abort
+        // here with a message naming the cause instead.
+        suppress_oom(layout);
+        let mut stderr = std::io::stderr().lock();
+        let _ = crate::message::write_oom_message(&mut stderr, layout);
+        let _ = stderr.write_all(b"memory allocation failed while unwinding, aborting\n");
+        drop(stderr);
+        std::process::abort();
+    }
+    if ThreadPanic::handling_oom() {
+        // Allocation fails while handling an allocation error, e.g. capturing the
+        // backtrace or boxing the payload. Raise the error being handled with a
+        // minimal error, which neither allocates nor captures anything, rather
+        // than recursing into the same failure.
+        #[cfg(feature = "backtrace")]
+        if crate::backtrace::capturing() {
+            // Unwinding from the capture, which holds the lock of the unwinder,
+            // deadlocks, so the process has to abort.
+            let _ = std::io::stderr().write_all(b"memory allocation failed while capturing a backtrace\n");
+            std::process::abort();
+        }
+        if THREAD_RAISING_REENTERED.with(|r| r.replace(true)) {
+            // Even the minimal error cannot be raised, as starting the unwinding
+            // allocates as well.
+            let _ = std::io::stderr().write_all(b"memory allocation failed while raising an allocation error\n");
+            std::process::abort();
+        }
+        let _raising = RaisingReentered;
+        let layout = THREAD_HANDLING_LAYOUT.with(Cell::get).unwrap_or(layout);
+        let e = AllocError::new(layout).with_context(REENTERED_CONTEXT);
+        if ThreadPanic::payload_transport() {
+            std::panic::panic_any(e);
+        }
+        ThreadAllocError::inject(e);
+        std::panic::panic_any(OomPanic);
+    }
+    let _handling = HandlingOom::enter_with(Some(layout));
+    crate::emergency::release();
+    crate::stats::record_oom(layout);
+    // A zero-sized allocation can only fail by an alignment no allocation can meet.
+    let e = if layout.size() == 0 {
+        AllocError::invalid_layout(layout)
+    } else {
+        AllocError::new(layout)
+    };
+    #[cfg(feature = "backtrace")]
+    let e = e.with_backtrace(crate::backtrace::capture());
+    crate::histogram::report_oom(&e);
+    if ThreadPanic::payload_transport() {
+        // The panic may be propagated to another thread, so the error is carried
+        // by the payload rather than the thread error slot, which allocates.
+        std::panic::panic_any(e);
+    }
+    ThreadAllocError::inject(e);
+    std::panic::panic_any(OomPanic);
+}
+
+/// Prints the out-of-memory panic to stderr like the default panic hook, without
+/// allocating a message.
+fn report_oom(info: &PanicHookInfo<'_>, layout: Layout) {
+    let thread = std::thread::current();
+    let name = thread.name().unwrap_or("<unnamed>");
+    let mut stderr = std::io::stderr().lock();
+    let _ = match info.location() {
+        Some(location) => writeln!(stderr, "\nthread '{}' panicked at {}:", name, location),
+        None => writeln!(stderr, "\nthread '{}' panicked:", name),
+    };
+    let _ = crate::message::write_oom_message(&mut stderr, layout);
+}
+
+type Hook = Box<dyn Fn(&PanicHookInfo<'_>) + 'static + Sync + Send>;
+
+/// The panic hook installed before ours, which is invoked by our panic hook.
+static PREVIOUS_HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// The panic hook which replaced ours, which is invoked by our panic hook
+/// instead of the previous one once ours is reinstalled by [`verify_hook`].
+static REPLACING_HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// The address of our panic hook, which identifies it.
+static HOOK_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+
+/// Creates our panic hook, recording its address.
+fn new_hook() -> Hook {
+    // The hook is not zero-sized, so its box is allocated at a unique address.
+    let marker = 0u8;
+    let hook: Hook = Box::new(move |info: &PanicHookInfo<'_>| {
+        std::hint::black_box(marker);
+        panic_hook(info);
+    });
+    HOOK_ADDRESS.store(&*hook as *const _ as *const () as usize, Ordering::Relaxed);
+    hook
+}
+
+/// Invokes the panic hook in `hook`, returning `false` if there is none.
+fn call_hook(hook: &RwLock<Option<Hook>>, info: &PanicHookInfo<'_>) -> bool {
+    match hook.read().unwrap_or_else(PoisonError::into_inner).as_ref() {
+        Some(hook) => {
+            hook(info);
+            true
+        }
+        None => false,
+    }
+}
+
+static QUIET_OOM: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether the out-of-memory panics which are caught as `AllocError` are
+/// printed to stderr.
+///
+/// Out-of-memory panics are printed by our panic hook instead of the panic hook
+/// installed before, which would allocate to format the message. In quiet mode,
+/// the out-of-memory panics inside catching scopes, or carried to the joining
+/// thread, are not reported. Other panics, and out-of-memory panics outside
+/// catching scopes, are always reported. Quiet mode is off by default.
+#[inline]
+pub fn set_quiet_oom(quiet: bool) {
+    QUIET_OOM.store(quiet, Ordering::Relaxed);
+}
+
+/// Returns whether the quiet mode is on, see [`set_quiet_oom`].
+#[must_use]
+#[inline]
+pub fn quiet_oom() -> bool {
+    QUIET_OOM.load(Ordering::Relaxed)
+}
+
+/// Checks if the panic is the capacity overflow of the standard collections,
+/// which panic before allocating if the capacity exceeds `isize::MAX` bytes.
+fn is_capacity_overflow(info: &PanicHookInfo<'_>) -> bool {
+    let Some(message) = crate::payload_as_str(info.payload()) else {
+        return false;
+    };
+    let in_alloc = info
+        .location()
+        .is_some_and(|l| l.file().contains("alloc/src/") || l.file().contains("alloc\\src\\"));
+    message == "capacity overflow" && in_alloc
+}
+
+fn panic_hook(info: &PanicHookInfo<'_>) {
+    THREAD_HOOK_SEEN.with(|s| s.set(true));
+    if THREAD_IN_HOOK.with(|h| h.replace(true)) {
+        // Invoked again by the replacing hook, which chains to the replaced one.
+        let _ = call_hook(&PREVIOUS_HOOK, info);
+        return;
+    }
+    handle_panic(info);
+    THREAD_IN_HOOK.with(|h| h.set(false));
+}
+
+fn handle_panic(info: &PanicHookInfo<'_>) {
+    let payload = info.payload();
+    let layout = match payload.downcast_ref::<AllocError>() {
+        Some(e) => Some(e.layout()),
+        None if payload.is::<OomPanic>() => Some(ThreadAllocError::layout().unwrap_or(Layout::new::<()>())),
+        None => None,
+    };
+    if crate::abort::in_critical_section() {
+        match layout {
+            Some(layout) => report_oom(info, layout),
+            None => {
+                let _ = call_hook(&REPLACING_HOOK, info) || call_hook(&PREVIOUS_HOOK, info);
+            }
+        }
+        crate::abort::abort(info);
+    }
+    if let Some(layout) = layout {
+        let caught = payload.is::<AllocError>() || ThreadPanic::mode().is_some();
+        if !(caught && quiet_oom()) {
+            report_oom(info, layout);
+        }
+        return;
+    }
+
+    // The error in the slot is stale, e.g. left by an out-of-memory panic caught
+    // by `std::panic::catch_unwind` in the scope.
+    ThreadAllocError::clear();
+    let capacity_overflow = is_capacity_overflow(info);
+    if capacity_overflow {
+        let e = AllocError::capacity_overflow();
+        #[cfg(feature = "backtrace")]
+        let e = e.with_backtrace(crate::backtrace::capture());
+        ThreadAllocError::inject(e);
+    }
+
+    if !(capacity_overflow && ThreadPanic::mode().is_some() && quiet_oom()) {
+        // The replacing hook is expected to chain to the previous one if it wants.
+        let _ = call_hook(&REPLACING_HOOK, info) || call_hook(&PREVIOUS_HOOK, info);
+    }
+    if capacity_overflow {
+        // Caught as an allocation error like the out-of-memory panic.
+        return;
+    }
+    let mode = match THREAD_PANIC_HANDLER.with(Cell::get) {
+        Some(handler) => {
+            // SAFETY: the handler outlives the scope it is set for, in which the
+            // panic occurs.
+            let mode = unsafe { (*handler)(info) };
+            THREAD_HANDLED_MODE.with(|m| m.set(Some(mode)));
+            Some(mode)
+        }
+        None => ThreadPanic::mode(),
+    };
+    match mode {
+        Some(CatchMode::ReturnError) => ThreadPanic::record(info),
+        Some(CatchMode::ResumeUnwind) => {}
+        Some(CatchMode::AbortOnPanic) => crate::abort::abort(info),
+        None => {
+            if abort_outside_scopes() && catch_mode() == CatchMode::AbortOnPanic {
+                crate::abort::abort(info);
+            }
+        }
+    }
+}
+
+/// The allocation error hook installed before ours, which is restored by [`uninstall`].
+#[cfg(not(feature = "stable"))]
+static PREVIOUS_ALLOC_ERROR_HOOK: Mutex<Option<fn(Layout)>> = Mutex::new(None);
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static INSTALL_LOCK: Mutex<()> = Mutex::new(());
+
+/// Installs the hooks, returns `false` if they have been installed.
+///
+/// Everything the catching path needs is prepared here, so catching does not
+/// allocate afterwards, except for refilling the memory reserves released by an
+/// allocation error.
+#[cold]
+fn install() -> bool {
+    let _lock = INSTALL_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    if INSTALLED.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    let hook = new_hook();
+    let previous = std::panic::take_hook();
+    *PREVIOUS_HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(previous);
+    std::panic::set_hook(hook);
+
+    #[cfg(not(feature = "stable"))]
+    {
+        let previous = std::alloc::take_alloc_error_hook();
+        *PREVIOUS_ALLOC_ERROR_HOOK.lock().unwrap_or_else(PoisonError::into_inner) = Some(previous);
+        std::alloc::set_alloc_error_hook(oom_hook);
+    }
+
+    crate::emergency::reserve();
+    #[cfg(feature = "backtrace")]
+    crate::backtrace::reserve();
+
+    // Initialize the thread locals of current thread, whose destructors may be
+    // registered by allocating on first access.
+    ThreadAllocError::clear();
+    ThreadPanic::set_mode(ThreadPanic::mode());
+
+    INSTALLED.store(true, Ordering::Release);
+    true
+}
+
+/// Installs the panic hook and the allocation error hook.
+///
+/// The hooks are installed only once, no matter how many times and from how many
+/// threads this function is called, until they are removed by [`uninstall`]. The
+/// catching functions call it implicitly, which is a single atomic load once the
+/// hooks are installed. It should be called eagerly at program startup, so the
+/// installation, which allocates, does not happen for the first time under memory
+/// pressure.
+///
+/// If panics abort in this build, i.e. with `panic = "abort"`, the hooks are
+/// installed as well, but nothing can be caught. Use [`try_init`] to detect it.
+#[inline]
+pub fn init() {
+    if !INSTALLED.load(Ordering::Acquire) {
+        install();
+    }
+}
+
+/// The error type for [`try_init`], returned if panics abort in this build.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InitError(());
+
+impl fmt::Display for InitError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("allocation errors cannot be caught as panics abort in this build (panic = \"abort\")")
+    }
+}
+
+impl Error for InitError {}
+
+/// Returns `true` if panics unwind in this build, so allocation errors can be
+/// caught, or `false` if they abort, i.e. with `panic = "abort"`.
+#[must_use]
+#[inline]
+pub const fn can_unwind() -> bool {
+    cfg!(panic = "unwind")
+}
+
+/// Installs the hooks like [`init`], or returns an error if panics abort in this
+/// build.
+///
+/// With `panic = "abort"`, the out-of-memory panic aborts the process before it
+/// can be caught, so the catching functions cannot work. The work can be isolated
+/// in a child process instead, e.g. by `catch_oom_isolated` on Unix.
+#[inline]
+pub fn try_init() -> Result<(), InitError> {
+    if !can_unwind() {
+        return Err(InitError(()));
+    }
+    init();
+    Ok(())
+}
+
+/// The error type for [`verify_hook`], returned if our panic hook has been
+/// replaced.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HookReplaced(());
+
+impl fmt::Display for HookReplaced {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the panic hook of panic-safe was replaced by another one, and has been reinstalled")
+    }
+}
+
+impl Error for HookReplaced {}
+
+/// Checks if our panic hook has been replaced by `std::panic::set_hook` since
+/// [`init`], and reinstalls it if so, returning an error.
+///
+/// While replaced, the panics other than allocation error do not abort the
+/// process as documented, and the out-of-memory panics are reported by the
+/// replacing hook, which may allocate. The reinstalled hook invokes the replacing
+/// hook instead of the one installed before [`init`], and a replacing hook which
+/// chains to ours is supported.
+///
+/// The catching functions check the hook when they catch a panic which our hook
+/// has not seen. Call this function after initializing libraries which may set
+/// the panic hook, to restore the behavior before any panic. The panics of other
+/// threads meanwhile are reported by the default hook.
+///
+/// Does nothing if the hooks are not installed, or if called from a panicking
+/// thread, which cannot change the hook.
+pub fn verify_hook() -> Result<(), HookReplaced> {
+    if !INSTALLED.load(Ordering::Acquire) || std::thread::panicking() {
+        return Ok(());
+    }
+    let _lock = INSTALL_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    if !INSTALLED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let hook = std::panic::take_hook();
+    if &*hook as *const _ as *const () as usize == HOOK_ADDRESS.load(Ordering::Relaxed) {
+        std::panic::set_hook(hook);
+        return Ok(());
+    }
+    *REPLACING_HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(hook);
+    std::panic::set_hook(new_hook());
+    Err(HookReplaced(()))
+}
+
+/// Removes the panic hook and the allocation error hook, restoring the hooks
+/// installed before [`init`].
+///
+/// Hooks installed by others after [`init`] are discarded as well.
+///
+/// # Panics
+///
+/// Panics if called from a panicking thread.
+pub fn uninstall() {
+    let _lock = INSTALL_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    if !INSTALLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let previous = PREVIOUS_HOOK.write().unwrap_or_else(PoisonError::into_inner).take();
+    drop(std::panic::take_hook());
+    drop(REPLACING_HOOK.write().unwrap_or_else(PoisonError::into_inner).take());
+    if let Some(previous) = previous {
+        std::panic::set_hook(previous);
+    }
+
+    #[cfg(not(feature = "stable"))]
+    {
+        let previous = PREVIOUS_ALLOC_ERROR_HOOK
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match previous {
+            Some(previous) => std::alloc::set_alloc_error_hook(previous),
+            None => {
+                let _ = std::alloc::take_alloc_error_hook();
+            }
+        }
+    }
+
+    INSTALLED.store(false, Ordering::Release);
+}
+
+/// Installs the hooks like [`init`], returning a guard which restores the previous
+/// hooks on drop.
+///
+/// If the hooks have been installed, the returned guard does nothing on drop.
+#[inline]
+pub fn install_scoped() -> HookGuard {
+    HookGuard { installed: install() }
+}
+
+/// A guard restoring the previous panic hook and allocation error hook on drop.
+///
+/// This structure is created by [`install_scoped`].
+#[must_use = "if unused the hooks will immediately be uninstalled"]
+#[derive(Debug)]
+pub struct HookGuard {
+    installed: bool,
+}
+
+impl Drop for HookGuard {
+    /// Uninstalls the hooks if they were installed by this guard.
+    ///
+    /// The hooks cannot be changed from a panicking thread, so they are left
+    /// installed if the guard is dropped during unwinding.
+    #[inline]
+    fn drop(&mut self) {
+        if self.installed && !std::thread::panicking() {
+            uninstall();
+        }
+    }
+}
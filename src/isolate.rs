@@ -0,0 +1,447 @@
+//! The isolation of closures in child processes, forked on Unix and spawned in
+//! job objects on Windows.
+
+use std::alloc::Layout;
+use std::error::Error;
+#[cfg(unix)]
+use std::ffi::c_int;
+use std::fmt;
+#[cfg(unix)]
+use std::fs::File;
+#[cfg(unix)]
+use std::io::Read;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::panic::{Location, UnwindSafe};
+use std::process::ExitStatus;
+
+use crate::catch::catch_oom_at;
+use crate::{AllocError, AllocErrorKind};
+
+#[cfg(unix)]
+extern "C" {
+    fn fork() -> c_int;
+    #[cfg(target_os = "linux")]
+    fn pipe2(fds: *mut c_int, flags: c_int) -> c_int;
+    #[cfg(not(target_os = "linux"))]
+    fn pipe(fds: *mut c_int) -> c_int;
+    #[cfg(not(target_os = "linux"))]
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+    fn _exit(status: c_int) -> !;
+}
+
+#[cfg(target_os = "linux")]
+const O_CLOEXEC: c_int = 0o2000000;
+#[cfg(all(unix, not(target_os = "linux")))]
+const F_SETFD: c_int = 2;
+#[cfg(all(unix, not(target_os = "linux")))]
+const FD_CLOEXEC: c_int = 1;
+
+const TAG_OK: u8 = 0;
+const TAG_OOM: u8 = 1;
+const TAG_LIMIT: u8 = 2;
+
+/// The error type for `catch_oom_isolated` on Unix and `catch_oom_in_job` on
+/// Windows.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum IsolatedError {
+    /// Allocation error occurs in the child process.
+    Oom(AllocError),
+    /// The child process exits without reporting a result, e.g. it aborts on a
+    /// panic or is killed by a signal, or exits unsuccessfully.
+    Crashed(ExitStatus),
+    /// The child process cannot be run, or its address space cannot be limited.
+    Io(io::Error),
+}
+
+impl From<io::Error> for IsolatedError {
+    #[inline]
+    fn from(e: io::Error) -> Self {
+        IsolatedError::Io(e)
+    }
+}
+
+impl fmt::Display for IsolatedError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IsolatedError::Oom(e) => fmt::Display::fmt(e, f),
+            IsolatedError::Crashed(status) => write!(f, "isolated process crashed: {}", status),
+            IsolatedError::Io(e) => write!(f, "failed to run isolated process: {}", e),
+        }
+    }
+}
+
+impl Error for IsolatedError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            IsolatedError::Oom(e) => Some(e),
+            IsolatedError::Crashed(_) => None,
+            IsolatedError::Io(e) => Some(e),
+        }
+    }
+}
+
+/// Encodes the result of the closure run in the child process.
+fn encode_message(result: io::Result<Result<Vec<u8>, AllocError>>) -> Vec<u8> {
+    let mut message = Vec::new();
+    match result {
+        Ok(Ok(bytes)) => {
+            message.push(TAG_OK);
+            message.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            message.extend_from_slice(&bytes);
+        }
+        Ok(Err(e)) => {
+            message.push(TAG_OOM);
+            message.extend_from_slice(&(e.size() as u64).to_le_bytes());
+            message.extend_from_slice(&(e.align() as u64).to_le_bytes());
+            message.push(match e.kind() {
+                AllocErrorKind::CapacityOverflow => 1,
+                AllocErrorKind::InvalidLayout => 2,
+                AllocErrorKind::UnexpectedPanic => 3,
+                _ => 0,
+            });
+        }
+        Err(e) => {
+            message.push(TAG_LIMIT);
+            message.extend_from_slice(&e.raw_os_error().unwrap_or(0).to_le_bytes());
+        }
+    }
+    message
+}
+
+/// Runs the closure in the forked child process, writes the result to `out` and
+/// exits.
+#[cfg(unix)]
+fn run_child<F: FnOnce() -> Vec<u8> + UnwindSafe>(
+    caller: &'static Location<'static>,
+    limit: Option<usize>,
+    mut out: File,
+    f: F,
+) -> ! {
+    let run = || catch_oom_at(caller, f);
+    let result = match limit {
+        Some(bytes) => crate::rlimit::with_address_space_limit(bytes, run),
+        None => Ok(run()),
+    };
+    let status = if out.write_all(&encode_message(result)).is_ok() {
+        0
+    } else {
+        1
+    };
+    // SAFETY: exiting without running the destructors and the exit handlers, which
+    // belong to the parent process.
+    unsafe { _exit(status) }
+}
+
+/// Parses the message of the child process, or returns `None` if it is incomplete,
+/// e.g. as the child is killed while writing it.
+fn parse_message(message: &[u8], caller: &'static Location<'static>) -> Option<Result<Vec<u8>, IsolatedError>> {
+    let (&tag, rest) = message.split_first()?;
+    match tag {
+        TAG_OK if rest.len() >= 8 => {
+            let (len, bytes) = rest.split_at(8);
+            let len = u64::from_le_bytes(len.try_into().ok()?);
+            (u64::try_from(bytes.len()).ok()? == len).then(|| Ok(bytes.to_vec()))
+        }
+        TAG_OOM if rest.len() == 17 => {
+            let size = u64::from_le_bytes(rest[..8].try_into().ok()?);
+            let align = u64::from_le_bytes(rest[8..16].try_into().ok()?);
+            let layout = Layout::from_size_align(size.try_into().ok()?, align.try_into().ok()?).ok()?;
+            let kind = match rest[16] {
+                1 => AllocErrorKind::CapacityOverflow,
+                2 => AllocErrorKind::InvalidLayout,
+                3 => AllocErrorKind::UnexpectedPanic,
+                _ => AllocErrorKind::Exhausted,
+            };
+            Some(Err(IsolatedError::Oom(
+                AllocError::with_kind(layout, kind).caught_at(caller),
+            )))
+        }
+        TAG_LIMIT if rest.len() == 4 => {
+            let code = i32::from_le_bytes(rest.try_into().ok()?);
+            Some(Err(IsolatedError::Io(io::Error::from_raw_os_error(code))))
+        }
+        _ => None,
+    }
+}
+
+/// Creates a pipe whose file descriptors are closed on exec, so they do not leak
+/// into the processes spawned meanwhile by other threads, which would keep the
+/// pipe open after the child exits.
+#[cfg(unix)]
+fn pipe_cloexec() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` is an array of two ints to write to.
+    #[cfg(target_os = "linux")]
+    let created = unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) } == 0;
+    // SAFETY: `fds` is an array of two ints to write to.
+    #[cfg(not(target_os = "linux"))]
+    let created = unsafe { pipe(fds.as_mut_ptr()) } == 0;
+    if !created {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the file descriptors are just created and owned by the files.
+    let files = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    #[cfg(not(target_os = "linux"))]
+    for fd in fds {
+        // SAFETY: `fd` is open, and owned by one of the files.
+        if unsafe { fcntl(fd, F_SETFD, FD_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(files)
+}
+
+/// Invokes a closure in a forked child process, capturing the out-of-memory panic
+/// if one occurs, with the address space of the child limited to `limit` bytes.
+///
+/// The closure returns its result serialized as bytes, which are sent back to the
+/// parent over a pipe. As the closure runs in its own process, the parent is
+/// protected even from aborts on panics, double panics and crashes, e.g. stack
+/// overflow, which are returned as [`IsolatedError::Crashed`]. The limit is
+/// applied by [`with_address_space_limit`](crate::with_address_space_limit), so
+/// `None` runs the child unlimited.
+///
+/// The child is forked from the calling thread only, so the closure must not
+/// depend on other threads, e.g. on locks held by them at the time of forking.
+/// The child exits without running destructors or exit handlers.
+///
+/// ```
+/// use panic_safe::catch_oom_isolated;
+///
+/// # mod untrusted { pub fn render(input: &str) -> String { input.to_uppercase() } }
+/// # let input = "input";
+/// let output = catch_oom_isolated(Some(1 << 30), || untrusted::render(input).into_bytes());
+/// ```
+#[cfg(unix)]
+#[track_caller]
+pub fn catch_oom_isolated<F: FnOnce() -> Vec<u8> + UnwindSafe>(
+    limit: Option<usize>,
+    f: F,
+) -> Result<Vec<u8>, IsolatedError> {
+    let caller = Location::caller();
+    let (mut reader, writer) = pipe_cloexec()?;
+
+    // SAFETY: the child only runs the closure, see the documentation.
+    let pid = unsafe { fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    if pid == 0 {
+        drop(reader);
+        run_child(caller, limit, writer, f);
+    }
+
+    drop(writer);
+    let mut message = Vec::new();
+    let read = reader.read_to_end(&mut message);
+    let mut status = 0;
+    loop {
+        // SAFETY: `status` is an int to write to.
+        if unsafe { waitpid(pid, &mut status, 0) } >= 0 {
+            break;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e.into());
+        }
+    }
+    read?;
+    parse_result(&message, ExitStatus::from_raw(status), caller)
+}
+
+/// Returns the result reported by the child process which exits with `status`,
+/// or `Crashed` if the child does not exit successfully, which may leave the
+/// message incomplete, or reports no result.
+fn parse_result(
+    message: &[u8],
+    status: ExitStatus,
+    caller: &'static Location<'static>,
+) -> Result<Vec<u8>, IsolatedError> {
+    if !status.success() {
+        return Err(IsolatedError::Crashed(status));
+    }
+    parse_message(message, caller).unwrap_or(Err(IsolatedError::Crashed(status)))
+}
+
+/// Runs a closure as the child process of [`catch_oom_in_job`], capturing the
+/// out-of-memory panic if one occurs, and exits with the result reported to the
+/// parent process on the standard output.
+///
+/// The helper program spawned by the parent calls this function, typically first
+/// in its `main`, with the work to isolate. Nothing else may be written to the
+/// standard output meanwhile.
+#[track_caller]
+pub fn run_isolated_child<F: FnOnce() -> Vec<u8> + UnwindSafe>(f: F) -> ! {
+    let result = catch_oom_at(Location::caller(), f);
+    let mut stdout = io::stdout().lock();
+    let written = stdout
+        .write_all(&encode_message(Ok(result)))
+        .and_then(|()| stdout.flush());
+    std::process::exit(if written.is_ok() { 0 } else { 1 })
+}
+
+#[cfg(windows)]
+mod job {
+    use std::ffi::c_void;
+
+    pub(super) type Handle = *mut c_void;
+
+    pub(super) const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: i32 = 9;
+    pub(super) const JOB_OBJECT_LIMIT_PROCESS_MEMORY: u32 = 0x0000_0100;
+    pub(super) const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: u32 = 0x0000_2000;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub(super) struct BasicLimitInformation {
+        pub(super) per_process_user_time_limit: i64,
+        pub(super) per_job_user_time_limit: i64,
+        pub(super) limit_flags: u32,
+        pub(super) minimum_working_set_size: usize,
+        pub(super) maximum_working_set_size: usize,
+        pub(super) active_process_limit: u32,
+        pub(super) affinity: usize,
+        pub(super) priority_class: u32,
+        pub(super) scheduling_class: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub(super) struct ExtendedLimitInformation {
+        pub(super) basic: BasicLimitInformation,
+        pub(super) io_counters: [u64; 6],
+        pub(super) process_memory_limit: usize,
+        pub(super) job_memory_limit: usize,
+        pub(super) peak_process_memory_used: usize,
+        pub(super) peak_job_memory_used: usize,
+    }
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        pub(super) fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> Handle;
+        pub(super) fn SetInformationJobObject(job: Handle, class: i32, info: *const c_void, len: u32) -> i32;
+        pub(super) fn AssignProcessToJobObject(job: Handle, process: Handle) -> i32;
+        pub(super) fn CloseHandle(handle: Handle) -> i32;
+    }
+
+    /// The handle of a job object, closed when dropped, which kills the processes
+    /// in the job.
+    pub(super) struct Job(pub(super) Handle);
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // SAFETY: the handle is valid, and is not used afterwards.
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+}
+
+/// Spawns `command` as a child process in a job object limiting its memory to
+/// `limit` bytes, and returns the result of the closure run by
+/// [`run_isolated_child`] in it.
+///
+/// This gives the semantics of `catch_oom_isolated` on Windows, where processes
+/// cannot be forked: the command runs a helper program, e.g. the current
+/// executable with an argument selecting the work, whose allocations beyond the
+/// limit fail with `JOB_OBJECT_LIMIT_PROCESS_MEMORY`. The child is assigned to the
+/// job right after being spawned, so the limit does not apply to the very start
+/// of it. The standard output of the child is piped to the parent.
+///
+/// ```no_run
+/// use panic_safe::{catch_oom_in_job, run_isolated_child};
+/// use std::process::Command;
+///
+/// # mod untrusted { pub fn render(input: &str) -> String { input.to_uppercase() } }
+/// # fn main() -> std::io::Result<()> {
+/// # let input = "input";
+/// if std::env::args().nth(1).as_deref() == Some("--render") {
+///     run_isolated_child(|| untrusted::render(input).into_bytes());
+/// }
+/// let mut command = Command::new(std::env::current_exe()?);
+/// let output = catch_oom_in_job(1 << 30, command.arg("--render"));
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(windows)]
+#[track_caller]
+pub fn catch_oom_in_job(limit: usize, command: &mut std::process::Command) -> Result<Vec<u8>, IsolatedError> {
+    use std::io::Read;
+    use std::os::windows::io::AsRawHandle;
+
+    let caller = Location::caller();
+    // SAFETY: creating an anonymous job object has no preconditions.
+    let handle = unsafe { job::CreateJobObjectW(std::ptr::null_mut(), std::ptr::null()) };
+    if handle.is_null() {
+        return Err(io::Error::last_os_error().into());
+    }
+    let job = job::Job(handle);
+    let mut info = job::ExtendedLimitInformation::default();
+    info.basic.limit_flags = job::JOB_OBJECT_LIMIT_PROCESS_MEMORY | job::JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+    info.process_memory_limit = limit;
+    // SAFETY: `info` is a valid `JOBOBJECT_EXTENDED_LIMIT_INFORMATION`.
+    let ok = unsafe {
+        job::SetInformationJobObject(
+            job.0,
+            job::JOB_OBJECT_EXTENDED_LIMIT_INFORMATION,
+            (&info as *const job::ExtendedLimitInformation).cast(),
+            std::mem::size_of_val(&info) as u32,
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let mut child = command.stdout(std::process::Stdio::piped()).spawn()?;
+    // SAFETY: both handles are valid.
+    if unsafe { job::AssignProcessToJobObject(job.0, child.as_raw_handle()) } == 0 {
+        let e = io::Error::last_os_error();
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(e.into());
+    }
+    let mut message = Vec::new();
+    let read = child
+        .stdout
+        .take()
+        .map_or(Ok(0), |mut stdout| stdout.read_to_end(&mut message));
+    let status = child.wait()?;
+    read?;
+    parse_result(&message, status, caller)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_result_is_incomplete() {
+        let caller = Location::caller();
+        let message = encode_message(Ok(Ok(b"result".to_vec())));
+        assert!(matches!(parse_message(&message, caller), Some(Ok(bytes)) if bytes == b"result"));
+        for len in 0..message.len() {
+            assert!(parse_message(&message[..len], caller).is_none());
+        }
+        let mut extended = message.clone();
+        extended.push(0);
+        assert!(parse_message(&extended, caller).is_none());
+    }
+
+    #[test]
+    fn oom_result_round_trips() {
+        let caller = Location::caller();
+        let layout = Layout::from_size_align(1 << 20, 16).unwrap();
+        let message = encode_message(Ok(Err(AllocError::new(layout))));
+        let Some(Err(IsolatedError::Oom(e))) = parse_message(&message, caller) else {
+            panic!("unexpected result");
+        };
+        assert_eq!(e.layout(), layout);
+        assert_eq!(e.location(), Some(caller));
+    }
+}
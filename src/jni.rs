@@ -0,0 +1,98 @@
+//! Integration with JNI native libraries.
+//!
+//! The JNI functions are called through the function table of `JNIEnv`, whose
+//! layout is fixed by the JNI specification, so no JNI crate is required.
+
+use std::ffi::{c_char, c_void};
+use std::fmt::Write;
+use std::panic::{Location, UnwindSafe};
+
+use crate::catch::catch_oom_at;
+use crate::message::StackBuffer;
+use crate::AllocError;
+
+/// The prefix of `JNINativeInterface` up to `ExceptionCheck`, with the unused
+/// functions as opaque pointers.
+#[repr(C)]
+struct JniNativeInterface {
+    _reserved: [*const c_void; 6],
+    find_class: unsafe extern "system" fn(env: *mut c_void, name: *const c_char) -> *mut c_void,
+    _unused0: [*const c_void; 7],
+    throw_new: unsafe extern "system" fn(env: *mut c_void, class: *mut c_void, message: *const c_char) -> i32,
+    _unused1: [*const c_void; 8],
+    delete_local_ref: unsafe extern "system" fn(env: *mut c_void, obj: *mut c_void),
+    _unused2: [*const c_void; 204],
+    exception_check: unsafe extern "system" fn(env: *mut c_void) -> u8,
+}
+
+/// Throws `java.lang.OutOfMemoryError` with the message of `e` into `env`,
+/// unless an exception is already pending.
+///
+/// The message is formatted on the stack, so throwing does not allocate in Rust.
+///
+/// # Safety
+///
+/// `env` must be the valid `JNIEnv` of current thread.
+unsafe fn throw_out_of_memory(env: *mut c_void, e: &AllocError) {
+    // SAFETY: `JNIEnv` is a pointer to the function table.
+    let functions = unsafe { &**env.cast::<*const JniNativeInterface>() };
+    // SAFETY: guaranteed by the caller, and no exception is pending when
+    // `FindClass` and `ThrowNew` are called.
+    unsafe {
+        if (functions.exception_check)(env) != 0 {
+            return;
+        }
+        let class = (functions.find_class)(env, c"java/lang/OutOfMemoryError".as_ptr());
+        // `FindClass` throws an error itself if it fails.
+        if class.is_null() {
+            return;
+        }
+        let mut message = StackBuffer::new();
+        let _ = write!(message, "{}", e);
+        (functions.throw_new)(env, class, message.as_c_str().as_ptr());
+        (functions.delete_local_ref)(env, class);
+    }
+}
+
+/// Runs the body of a JNI native method under [`catch_oom`](crate::catch_oom),
+/// throwing `java.lang.OutOfMemoryError` into `env` if an allocation error
+/// occurs.
+///
+/// `env` is the raw `JNIEnv *` passed to the native method, e.g. from
+/// `jni::JNIEnv::get_raw`. The message of the thrown error is the `AllocError`,
+/// including the failed layout. The `AllocError` is returned as well, and the
+/// native method returns any value to the JVM, which raises the pending error in
+/// the Java caller. A Java exception already pending, e.g. thrown by a JNI call in
+/// the closure, is kept instead.
+///
+/// # Safety
+///
+/// `env` must be the valid `JNIEnv` of current thread.
+///
+/// ```
+/// # #![allow(non_camel_case_types)]
+/// # use std::ffi::c_void;
+/// # struct JNIEnv;
+/// # impl JNIEnv { fn get_raw(&self) -> *mut c_void { std::ptr::null_mut() } }
+/// # type JClass = *mut c_void;
+/// # type JByteArray = *mut c_void;
+/// # type jlong = i64;
+/// # struct Index;
+/// # impl Index { fn build(_: &JNIEnv, _: JByteArray) -> Self { Index } }
+/// #[no_mangle]
+/// pub extern "system" fn Java_com_example_Index_build(env: JNIEnv, _: JClass, input: JByteArray) -> jlong {
+///     let raw = env.get_raw();
+///     unsafe { panic_safe::catch_oom_jni(raw.cast(), || Index::build(&env, input)) }
+///         .map_or(0, |index| Box::into_raw(Box::new(index)) as jlong)
+/// }
+/// ```
+#[track_caller]
+#[inline]
+pub unsafe fn catch_oom_jni<F: FnOnce() -> R + UnwindSafe, R>(env: *mut c_void, f: F) -> Result<R, AllocError> {
+    let result = catch_oom_at(Location::caller(), f);
+    if let Err(e) = &result {
+        // SAFETY: guaranteed by the caller.
+        unsafe { throw_out_of_memory(env, e) };
+    }
+    result
+}
@@ -0,0 +1,75 @@
+//! The tracing of large allocations in catching scopes.
+
+use std::alloc::Layout;
+use std::cell::Cell;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+type Tracer = fn(Layout, &'static Location<'static>);
+
+static THRESHOLD: AtomicUsize = AtomicUsize::new(usize::MAX);
+static TRACER: RwLock<Option<Tracer>> = RwLock::new(None);
+
+thread_local! {
+    static THREAD_SCOPE: Cell<Option<&'static Location<'static>>> = const { Cell::new(None) };
+    static THREAD_TRACING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Sets the tracer called for every allocation larger than `threshold` bytes
+/// inside catching scopes, returns the previous one.
+///
+/// The tracer is called with the layout of the allocation and the location of the
+/// innermost catching scope, e.g. the call of [`catch_oom`](crate::catch_oom), to
+/// find the code pushing the process toward running out of memory. It may
+/// forward to `log` or `tracing`, and may capture a backtrace to find the exact
+/// call site. A reallocation is traced by its new size.
+///
+/// The tracer is called by [`TrackingAlloc`](crate::TrackingAlloc), which must be
+/// registered as the global allocator. It runs inside the allocator, so it should
+/// be quick and must not panic; the allocations made by it are not traced.
+pub fn set_large_alloc_tracer(threshold: usize, tracer: Option<Tracer>) -> Option<Tracer> {
+    let mut current = TRACER.write().unwrap_or_else(PoisonError::into_inner);
+    let threshold = if tracer.is_some() { threshold } else { usize::MAX };
+    THRESHOLD.store(threshold, Ordering::Relaxed);
+    std::mem::replace(&mut *current, tracer)
+}
+
+/// Calls the tracer if the allocation of `layout` is large and in a catching
+/// scope.
+#[inline]
+pub(crate) fn trace(layout: Layout) {
+    if layout.size() <= THRESHOLD.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(location) = THREAD_SCOPE.with(Cell::get) else {
+        return;
+    };
+    if THREAD_TRACING.with(|t| t.replace(true)) {
+        return;
+    }
+    // The lock is not waited for, as the tracer may be replaced meanwhile.
+    if let Ok(tracer) = TRACER.try_read() {
+        if let Some(tracer) = *tracer {
+            tracer(layout, location);
+        }
+    }
+    THREAD_TRACING.with(|t| t.set(false));
+}
+
+/// Records the location of the catching scope until dropped.
+pub(crate) struct TracingScope(Option<&'static Location<'static>>);
+
+impl TracingScope {
+    #[inline]
+    pub(crate) fn enter(caller: &'static Location<'static>) -> Self {
+        TracingScope(THREAD_SCOPE.with(|s| s.replace(Some(caller))))
+    }
+}
+
+impl Drop for TracingScope {
+    #[inline]
+    fn drop(&mut self) {
+        THREAD_SCOPE.with(|s| s.set(self.0));
+    }
+}
@@ -0,0 +1,69 @@
+//! The last allocation error caught in each thread.
+
+use std::cell::Cell;
+
+use crate::AllocError;
+
+std::thread_local! {
+    static THREAD_LAST_ERROR: Cell<Option<AllocError>> = const { Cell::new(None) };
+}
+
+/// Records `e` as the last error of current thread, replacing the previous one.
+#[inline]
+pub(crate) fn record(e: &AllocError) {
+    drop(THREAD_LAST_ERROR.replace(Some(e.clone())));
+}
+
+/// Takes the allocation error of the most recent failed call of
+/// [`catch_oom`](crate::catch_oom) and friends in current thread, leaving none.
+///
+/// Every allocation error caught in a thread is recorded, whether it is returned
+/// as `AllocError`, [`CaughtError`](crate::CaughtError) or another error, and
+/// replaces the previous one until taken, like `errno`. This is for the code
+/// which cannot pass the error back, e.g. a callback of a C library reporting a
+/// failure by its return value, whose caller fetches the error afterwards.
+///
+/// ```
+/// use panic_safe::{catch_ffi, take_last_error};
+/// # use std::ffi::{c_int, c_void};
+/// # struct Row;
+/// # unsafe fn index(_: *mut c_void, _: *const Row) {}
+/// # unsafe fn db_scan(_: &str, f: extern "C" fn(*mut c_void, *const Row) -> c_int, ctx: *mut c_void) -> c_int {
+/// #     f(ctx, std::ptr::null())
+/// # }
+/// # let (table, ctx) = ("table", std::ptr::null_mut());
+///
+/// extern "C" fn on_row(ctx: *mut c_void, row: *const Row) -> c_int {
+///     match catch_ffi(|| unsafe { index(ctx, row) }) {
+///         Ok(()) => 0,
+///         Err(_) => -1,
+///     }
+/// }
+///
+/// if unsafe { db_scan(table, on_row, ctx) } != 0 {
+///     if let Some(e) = take_last_error() {
+///         eprintln!("scan aborted: {}", e);
+///     }
+/// }
+/// ```
+#[must_use]
+#[inline]
+pub fn take_last_error() -> Option<AllocError> {
+    THREAD_LAST_ERROR.take()
+}
+
+/// Returns a clone of the allocation error of the most recent failed call of
+/// [`catch_oom`](crate::catch_oom) and friends in current thread, leaving it
+/// recorded.
+///
+/// See [`take_last_error`] for details.
+#[must_use]
+#[inline]
+pub fn peek_last_error() -> Option<AllocError> {
+    THREAD_LAST_ERROR.with(|last| {
+        let e = last.take();
+        let peeked = e.clone();
+        last.set(e);
+        peeked
+    })
+}
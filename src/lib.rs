@@ -1,133 +1,298 @@
 //! A library for catching panic.
+//!
+//! # Features
+//!
+//! - `std` (default): enables catching panics. Without it, only the error types and
+//!   the error slot are available, which is backed by the `thread-local` feature
+//!   (using the unstable `#[thread_local]` attribute) or the `critical-section`
+//!   feature (using a user-provided [`critical-section`] implementation).
+//! - `stable`: builds on stable Rust without the unstable allocation error hook.
+//!   Allocation errors are only caught if `CatchAlloc` is registered as the
+//!   global allocator.
+//! - `backtrace`: captures a backtrace where the allocation fails, available from
+//!   `AllocError::backtrace`.
+//! - `capi`: exports the C API in the `capi` module, declared in
+//!   `include/panic_safe.h`, for C and C++ hosts of Rust plugins.
+//! - `derive`: enables the `FromAllocError` derive macro, implementing
+//!   `From<AllocError>` for error enums, the `catch_oom_fn` attribute, running
+//!   functions by `catch_oom`, and the `ffi_guard` attribute, running
+//!   `extern "C"` functions by `catch_ffi`.
+//! - `fuzz`: enables `FailureSchedule`, failing allocations by the input of a
+//!   fuzzer.
+//! - `jni`: enables `catch_oom_jni`, throwing allocation errors in JNI native
+//!   methods into the JVM as `java.lang.OutOfMemoryError`.
+//! - `postgres`: enables `catch_oom_pg`, reporting allocation errors in
+//!   PostgreSQL extensions by `ereport(ERROR, ...)`.
+//! - `pyo3`: enables `catch_oom_py`, raising allocation errors in PyO3 extension
+//!   modules as `MemoryError`, which `AllocError` converts into as `PyErr`.
+//! - `rayon`: enables `install_catch_oom`, catching allocation errors in rayon
+//!   worker threads.
+//! - `serde`: implements `Serialize` and `Deserialize` for the error types and
+//!   `FailureTrace`.
+//! - `sqlstate`: maps allocation errors to database error codes by
+//!   `AllocError::sqlstate` and `AllocError::vendor_code`, e.g. `53200`
+//!   (`out_of_memory`).
+//! - `tokio`: enables `catch_oom_blocking`, running closures on the tokio blocking
+//!   thread pool.
+//!
+//! [`critical-section`]: https://docs.rs/critical-section
 
-#![feature(alloc_error_hook)]
-#![feature(allocator_api)]
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(all(feature = "std", not(feature = "stable")), feature(alloc_error_hook))]
+#![cfg_attr(all(not(feature = "std"), feature = "thread-local"), feature(thread_local))]
+#![cfg_attr(not(feature = "stable"), feature(allocator_api, error_generic_member_access))]
+#![cfg_attr(all(feature = "std", not(feature = "stable")), feature(try_reserve_kind))]
 
-use std::alloc::Layout;
-use std::cell::Cell;
-use std::error::Error;
-use std::fmt;
-use std::panic::{PanicInfo, UnwindSafe};
-use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+mod abort;
+#[cfg(feature = "backtrace")]
+mod backtrace;
+#[cfg(feature = "std")]
+mod budget;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "std")]
+mod catch;
+#[cfg(all(feature = "std", not(feature = "stable")))]
+mod context;
+#[cfg(feature = "std")]
+mod crash;
+#[cfg(feature = "std")]
+mod emergency;
+mod error;
+#[cfg(feature = "std")]
+mod ext;
+#[cfg(feature = "std")]
+mod fault;
+#[cfg(feature = "std")]
+mod ffi;
+#[cfg(feature = "std")]
+mod future;
+#[cfg(feature = "fuzz")]
+mod fuzz;
+#[cfg(feature = "std")]
+mod global_alloc;
+#[cfg(feature = "std")]
+mod guard;
+#[cfg(feature = "std")]
+mod histogram;
+#[cfg(feature = "std")]
+mod hook;
+#[cfg(all(
+    feature = "std",
+    any(
+        windows,
+        all(target_pointer_width = "64", any(target_os = "linux", target_os = "macos"))
+    )
+))]
+mod isolate;
+#[cfg(feature = "jni")]
+mod jni;
+#[cfg(feature = "std")]
+mod large_alloc;
+#[cfg(feature = "std")]
+mod last_error;
+#[cfg(feature = "std")]
+mod listener;
+#[cfg(feature = "std")]
+mod macros;
+#[cfg(feature = "std")]
+mod message;
+#[cfg(all(
+    feature = "std",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+mod mmap;
+#[cfg(feature = "std")]
+mod no_alloc;
+#[cfg(feature = "std")]
+mod observer;
+#[cfg(feature = "std")]
+mod panic;
+#[cfg(feature = "std")]
+mod pool;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "macos", windows)))]
+mod pressure;
+#[cfg(feature = "pyo3")]
+mod pyo3;
+#[cfg(feature = "rayon")]
+mod rayon;
+#[cfg(feature = "std")]
+mod report;
+#[cfg(all(feature = "std", not(feature = "stable")))]
+mod reserve;
+#[cfg(feature = "std")]
+mod retry;
+#[cfg(feature = "std")]
+mod rich;
+#[cfg(all(
+    feature = "std",
+    target_pointer_width = "64",
+    any(target_os = "linux", target_os = "macos")
+))]
+mod rlimit;
+#[cfg(feature = "std")]
+mod scope;
+#[cfg(all(
+    feature = "std",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+mod segment;
+#[cfg(feature = "serde")]
+mod serde;
+mod slot;
+#[cfg(feature = "sqlstate")]
+mod sqlstate;
+#[cfg(feature = "std")]
+mod stack;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod tag;
+#[cfg(feature = "std")]
+mod thread;
+#[cfg(feature = "std")]
+mod throw;
+#[cfg(feature = "tokio")]
+mod tokio;
+#[cfg(feature = "std")]
+mod tracking;
+#[cfg(feature = "std")]
+mod watchdog;
 
-/// The error type for allocation failure.
-#[derive(Copy, Clone)]
-#[repr(transparent)]
-pub struct AllocError(Layout);
-
-impl AllocError {
-    /// Creates a new `AllocError`.
-    #[must_use]
-    #[inline]
-    pub const fn new(layout: Layout) -> Self {
-        AllocError(layout)
-    }
-
-    /// Returns the memory layout of the `AllocError`.
-    #[must_use]
-    #[inline]
-    pub const fn layout(self) -> Layout {
-        self.0
-    }
-}
-
-impl fmt::Debug for AllocError {
-    #[inline]
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("AllocError")
-            .field("size", &self.0.size())
-            .field("align", &self.0.align())
-            .finish()
-    }
-}
-
-impl fmt::Display for AllocError {
-    #[inline]
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "failed to allocate memory by required layout {{size: {}, align: {}}}",
-            self.0.size(),
-            self.0.align()
-        )
-    }
-}
-
-impl Error for AllocError {}
-
-thread_local! {
-    static THREAD_ALLOC_ERROR: Cell<Option<AllocError>> = Cell::new(None);
-}
-
-struct ThreadAllocError;
-
-impl ThreadAllocError {
-    /// Injects alloc error to current thread.
-    #[inline]
-    fn inject(e: AllocError) {
-        debug_assert!(!ThreadAllocError::has_error());
-        THREAD_ALLOC_ERROR.with(|error| {
-            error.set(Some(e));
-        })
-    }
-
-    /// Checks if has alloc error in current thread.
-    #[inline]
-    fn has_error() -> bool {
-        THREAD_ALLOC_ERROR.with(|error| error.get().is_some())
-    }
-
-    /// Takes alloc error from current thread
-    #[inline]
-    fn take() -> Option<AllocError> {
-        THREAD_ALLOC_ERROR.with(|error| error.take())
-    }
-
-    /// Clears alloc error in current thread
-    #[inline]
-    fn clear() {
-        let _ = ThreadAllocError::take();
-    }
-}
-
-fn oom_hook(layout: Layout) {
-    ThreadAllocError::inject(AllocError(layout));
-    panic!("memory allocation of {} bytes failed", layout.size());
-}
-
-/// Invokes a closure, capturing the out-of-memory panic if one occurs.
-///
-/// This function will return `Ok` with the closure's result if the closure
-/// does not panic, and will return `AllocError` if allocation error occurs. The
-/// process will abort if other panics occur.
-#[inline]
-pub fn catch_oom<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, AllocError> {
-    type Hook = Box<dyn Fn(&PanicInfo<'_>) + 'static + Sync + Send>;
-
-    fn panic_hook(_: &PanicInfo<'_>) {
-        // panic abort except alloc error
-        if !ThreadAllocError::has_error() {
-            std::process::abort();
-        }
-    }
-
-    static SET_HOOK: AtomicBool = AtomicBool::new(false);
-    if !SET_HOOK.load(Ordering::Acquire) {
-        let hook: Hook = Box::try_new(panic_hook).map_err(|_| AllocError::new(Layout::new::<Hook>()))?;
-        std::panic::set_hook(hook);
-        std::alloc::set_alloc_error_hook(oom_hook);
-        SET_HOOK.store(true, Ordering::Release);
-    }
-
-    ThreadAllocError::clear();
-    let result = std::panic::catch_unwind(f);
-    match result {
-        Ok(r) => Ok(r),
-        Err(_) => match ThreadAllocError::take() {
-            None => {
-                unreachable!()
-            }
-            Some(e) => Err(e),
-        },
-    }
-}
+#[cfg(feature = "std")]
+pub use abort::{add_abort_hook, set_abort_hook, set_fatal_reporter, AbortGuard};
+#[cfg(feature = "std")]
+pub use budget::MemoryBudget;
+#[cfg(feature = "std")]
+pub use catch::{
+    abort_outside_scopes, catch_any, catch_mode, catch_oom, catch_oom_assert, catch_oom_mut, catch_oom_named,
+    catch_oom_result, catch_oom_with_hook, catch_oom_with_mode, catch_panic, set_abort_outside_scopes, set_catch_mode,
+    CatchMode, Catcher,
+};
+#[cfg(all(feature = "std", not(feature = "stable")))]
+pub use context::MemoryContext;
+#[cfg(feature = "std")]
+pub use crash::set_crash_report;
+#[cfg(feature = "std")]
+pub use emergency::{emergency_reserve, set_emergency_reserve};
+pub use error::{AllocError, AllocErrorKind, CatchError};
+#[cfg(feature = "std")]
+pub use ext::CatchOomExt;
+#[cfg(feature = "std")]
+pub use fault::{exhaustive_oom_check, replay_failure, FailureTrace, FaultInjector};
+#[cfg(feature = "std")]
+pub use ffi::catch_ffi;
+#[cfg(feature = "std")]
+pub use future::{catch_oom_future, CatchOom};
+#[cfg(feature = "fuzz")]
+pub use fuzz::FailureSchedule;
+#[cfg(feature = "std")]
+pub use global_alloc::CatchAlloc;
+#[cfg(feature = "std")]
+pub use guard::{defer, on_oom, Defer, OnOom};
+#[cfg(feature = "std")]
+pub use histogram::{heap_histogram, set_heap_histogram_reporter, HeapHistogram};
+#[cfg(feature = "std")]
+pub use hook::{
+    can_unwind, init, install_scoped, quiet_oom, set_quiet_oom, try_init, uninstall, verify_hook, HookGuard,
+    HookReplaced, InitError,
+};
+#[cfg(all(feature = "std", windows))]
+pub use isolate::catch_oom_in_job;
+#[cfg(all(
+    feature = "std",
+    target_pointer_width = "64",
+    any(target_os = "linux", target_os = "macos")
+))]
+pub use isolate::catch_oom_isolated;
+#[cfg(all(
+    feature = "std",
+    any(
+        windows,
+        all(target_pointer_width = "64", any(target_os = "linux", target_os = "macos"))
+    )
+))]
+pub use isolate::{run_isolated_child, IsolatedError};
+#[cfg(feature = "jni")]
+pub use jni::catch_oom_jni;
+#[cfg(feature = "std")]
+pub use large_alloc::set_large_alloc_tracer;
+#[cfg(feature = "std")]
+pub use last_error::{peek_last_error, take_last_error};
+#[cfg(feature = "std")]
+pub use listener::register_low_memory_listener;
+#[cfg(feature = "std")]
+pub use message::{set_oom_message, OomMessage};
+#[cfg(all(
+    feature = "std",
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+pub use mmap::{catch_mmap_fault, MmapFault};
+#[cfg(feature = "std")]
+pub use no_alloc::assert_no_alloc;
+#[cfg(feature = "std")]
+pub use observer::{register_oom_observer, AllocErrorInfo};
+#[cfg(feature = "std")]
+pub use panic::{payload_as_str, CaughtError, PanicError, PanicKind, PanicLocation};
+#[cfg(feature = "derive")]
+pub use panic_safe_derive::{catch_oom_fn, ffi_guard, FromAllocError};
+#[cfg(feature = "std")]
+pub use pool::{JobHandle, PanicSafeThreadPool};
+#[cfg(feature = "postgres")]
+pub use postgres::catch_oom_pg;
+#[cfg(all(feature = "std", windows))]
+pub use pressure::watch_memory_resource;
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "macos", windows)))]
+pub use pressure::{low_memory, register_memory_pressure_listener, PressureWatcher};
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub use pressure::{watch_memory_pressure, MemoryPressure, PressureStall};
+#[cfg(all(feature = "std", target_os = "macos"))]
+pub use pressure::{watch_memory_pressure_level, MemoryPressureLevel};
+#[cfg(feature = "pyo3")]
+pub use pyo3::catch_oom_py;
+#[cfg(feature = "rayon")]
+pub use rayon::install_catch_oom;
+#[cfg(feature = "std")]
+pub use report::{catch_oom_report, CatchReport};
+#[cfg(all(feature = "std", not(feature = "stable")))]
+pub use reserve::try_reserve_or_catch;
+#[cfg(feature = "std")]
+pub use retry::{add_memory_releaser, catch_oom_retry, catch_oom_retry_with_backoff};
+#[cfg(feature = "std")]
+pub use rich::{catch_oom_rich, RichAllocError};
+#[cfg(all(
+    feature = "std",
+    target_pointer_width = "64",
+    any(target_os = "linux", target_os = "macos")
+))]
+pub use rlimit::with_address_space_limit;
+#[cfg(feature = "std")]
+pub use scope::{ErrorScope, WithErrorScope};
+#[cfg(not(feature = "std"))]
+pub use slot::{raise_alloc_error, take_alloc_error};
+#[cfg(feature = "sqlstate")]
+pub use sqlstate::set_vendor_code;
+#[cfg(feature = "std")]
+pub use stack::{catch_oom_with_stack, remaining_stack, spawn_with_stack};
+#[cfg(feature = "std")]
+pub use tag::{catch_oom_tagged, tagged_usage};
+#[cfg(feature = "std")]
+pub use thread::{catch_oom_scoped, spawn, spawn_propagating, PropagatingJoinHandle};
+#[cfg(feature = "std")]
+pub use throw::{catch, throw};
+#[cfg(feature = "tokio")]
+pub use tokio::catch_oom_blocking;
+#[cfg(feature = "std")]
+pub use tracking::{
+    current_usage, max_alloc_size, peak_usage, reset_peak_usage, reset_thread_peak_usage, scope_high_water_mark,
+    set_max_alloc_size, set_strict_memory_mode, set_thread_alloc_limit, strict_memory_mode, thread_alloc_limit,
+    thread_current_usage, thread_peak_usage, TrackingAlloc,
+};
+#[cfg(feature = "std")]
+pub use watchdog::{start_rss_watchdog, RssWatchdog};
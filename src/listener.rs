@@ -0,0 +1,80 @@
+//! Listeners notified of low memory to shed caches.
+
+use std::alloc::Layout;
+use std::sync::{PoisonError, RwLock};
+
+type Listener = Box<dyn Fn(Layout) -> usize + 'static + Sync + Send>;
+
+/// The registered low-memory listeners, notified in the order of registration.
+static LISTENERS: RwLock<Vec<Listener>> = RwLock::new(Vec::new());
+
+/// Registers a low-memory listener, which is notified when an allocation error
+/// is caught.
+///
+/// The listener receives the layout of the failed allocation, and returns the
+/// number of bytes it freed, e.g. by evicting its caches, so unrelated subsystems
+/// can shed memory cooperatively. Listeners are notified on the catching thread
+/// after unwinding, when the memory held by the failed closure has been dropped.
+/// They must not register listeners, which deadlocks.
+///
+/// A listener freeing nothing can report the caught allocation errors, e.g. to
+/// the `log` facade with a target routed by the operators. The errors caught by
+/// the retrying functions are reported on each attempt, and the aborts are
+/// reported likewise by an [abort hook](crate::add_abort_hook):
+///
+/// ```ignore
+/// panic_safe::register_low_memory_listener(|layout| {
+///     log::warn!(target: "oom", "allocation of {} bytes caught", layout.size());
+///     0
+/// });
+/// panic_safe::add_abort_hook(|info| log::error!(target: "oom", "aborting: {}", info));
+/// ```
+///
+/// As the listeners are notified on the catching thread, an event emitted by
+/// them, e.g. by `tracing`, is recorded in the span entered around the catching
+/// scope:
+///
+/// ```ignore
+/// panic_safe::register_low_memory_listener(|layout| {
+///     let peak = panic_safe::peak_usage();
+///     tracing::warn!(size = layout.size(), align = layout.align(), peak, "allocation error caught");
+///     0
+/// });
+/// let result = tracing::info_span!("render", tag = "render")
+///     .in_scope(|| panic_safe::catch_oom_tagged("render", || render(&page)));
+/// tracing::info!(ok = result.is_ok(), "rendered");
+/// ```
+///
+/// Similarly, a listener feeds a metrics facade, e.g. `metrics` with the count
+/// of the caught errors, the sizes of the failed requests and the tracked usage,
+/// while the aborts are counted by an abort hook:
+///
+/// ```ignore
+/// panic_safe::register_low_memory_listener(|layout| {
+///     metrics::counter!("oom_caught_total").increment(1);
+///     metrics::histogram!("oom_failed_request_bytes").record(layout.size() as f64);
+///     metrics::gauge!("tracked_memory_bytes").set(panic_safe::current_usage() as f64);
+///     0
+/// });
+/// panic_safe::add_abort_hook(|_| metrics::counter!("oom_aborts_total").increment(1));
+/// ```
+pub fn register_low_memory_listener<F>(listener: F)
+where
+    F: Fn(Layout) -> usize + 'static + Sync + Send,
+{
+    LISTENERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Box::new(listener));
+}
+
+/// Notifies the low-memory listeners of the failed `layout`, returning the total
+/// number of bytes they freed.
+#[inline]
+pub(crate) fn notify_low_memory(layout: Layout) -> usize {
+    LISTENERS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .fold(0, |freed, listener| freed.saturating_add(listener(layout)))
+}
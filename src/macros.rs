@@ -0,0 +1,34 @@
+//! The macros of the catching functions.
+
+/// Invokes a block of statements by [`catch_oom`](crate::catch_oom), evaluating
+/// to `Result<T, AllocError>` with the value `T` of the block.
+///
+/// The block is the body of a closure, so `return` and `?` exit the block rather
+/// than the enclosing function, and the locals are captured without naming and
+/// moving a closure. Like the closure of `catch_oom`, the block must be
+/// `UnwindSafe`. A block starting with `move` captures the locals by value.
+///
+/// ```
+/// use panic_safe::catch_oom;
+///
+/// # fn main() -> Result<(), panic_safe::AllocError> {
+/// # let text = "b a b";
+/// let summary = catch_oom! {
+///     let mut words: Vec<_> = text.split_whitespace().collect();
+///     words.sort_unstable();
+///     words.dedup();
+///     words.join(" ")
+/// }?;
+/// # assert_eq!(summary, "a b");
+/// # Ok(())
+/// # }
+/// ```
+#[macro_export]
+macro_rules! catch_oom {
+    (move $($body:tt)*) => {
+        $crate::catch_oom(move || { $($body)* })
+    };
+    ($($body:tt)*) => {
+        $crate::catch_oom(|| { $($body)* })
+    };
+}
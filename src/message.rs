@@ -0,0 +1,88 @@
+//! The message of the out-of-memory panic.
+
+use std::alloc::Layout;
+use std::fmt::{self, Write};
+use std::sync::{PoisonError, RwLock};
+
+/// The message printed for an out-of-memory panic.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum OomMessage {
+    /// `memory allocation of N bytes failed`, as the standard library prints.
+    #[default]
+    Default,
+    /// A static message.
+    Static(&'static str),
+    /// A message formatted by the callback from the failed layout.
+    ///
+    /// The message is formatted into a fixed buffer on the stack, so it does not
+    /// allocate, and is truncated to the size of the buffer, 512 bytes.
+    Format(fn(&mut dyn Write, Layout) -> fmt::Result),
+}
+
+static OOM_MESSAGE: RwLock<OomMessage> = RwLock::new(OomMessage::Default);
+
+/// Sets the message printed for an out-of-memory panic, e.g. to add the wording
+/// and the error code of a product.
+///
+/// The message is printed to stderr by the panic hook when an out-of-memory panic
+/// is raised, unless suppressed by [`set_quiet_oom`](crate::set_quiet_oom).
+#[inline]
+pub fn set_oom_message(message: OomMessage) {
+    *OOM_MESSAGE.write().unwrap_or_else(PoisonError::into_inner) = message;
+}
+
+/// A writer into a fixed buffer, which discards the overflowing text.
+pub(crate) struct StackBuffer {
+    buf: [u8; 512],
+    len: usize,
+}
+
+impl StackBuffer {
+    #[inline]
+    pub(crate) const fn new() -> Self {
+        StackBuffer { buf: [0; 512], len: 0 }
+    }
+
+    #[inline]
+    fn as_str(&self) -> &str {
+        match std::str::from_utf8(&self.buf[..self.len]) {
+            Ok(s) => s,
+            // Truncated in the middle of a character.
+            Err(e) => std::str::from_utf8(&self.buf[..e.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+
+    /// Returns the text as a C string, truncated by the last byte of the buffer
+    /// reserved for the NUL, or by the first NUL in the text.
+    #[cfg(any(feature = "jni", feature = "postgres"))]
+    #[inline]
+    pub(crate) fn as_c_str(&mut self) -> &std::ffi::CStr {
+        self.len = self.len.min(self.buf.len() - 1);
+        let end = self.as_str().len();
+        self.buf[end] = 0;
+        std::ffi::CStr::from_bytes_until_nul(&self.buf[..=end]).unwrap_or_default()
+    }
+}
+
+impl Write for StackBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Writes the message of the out-of-memory panic by `layout`.
+pub(crate) fn write_oom_message<W: std::io::Write>(w: &mut W, layout: Layout) -> std::io::Result<()> {
+    let message = *OOM_MESSAGE.read().unwrap_or_else(PoisonError::into_inner);
+    match message {
+        OomMessage::Default => writeln!(w, "memory allocation of {} bytes failed", layout.size()),
+        OomMessage::Static(message) => writeln!(w, "{}", message),
+        OomMessage::Format(format) => {
+            let mut buf = StackBuffer::new();
+            let _ = format(&mut buf, layout);
+            writeln!(w, "{}", buf.as_str())
+        }
+    }
+}
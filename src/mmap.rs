@@ -0,0 +1,265 @@
+//! The guard converting faults on memory-mapped regions into errors.
+
+use std::cell::{Cell, UnsafeCell};
+use std::error::Error;
+use std::ffi::{c_int, c_void};
+use std::fmt;
+use std::panic::Location;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::c_int;
+
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_sigaction: usize,
+        pub sa_mask: [u64; 16],
+        pub sa_flags: c_int,
+        pub sa_restorer: usize,
+    }
+
+    /// The offset of `si_addr` in `siginfo_t`.
+    pub const SI_ADDR_OFFSET: usize = 16;
+    pub const SIGBUS: c_int = 7;
+    pub const SA_ONSTACK: c_int = 0x0800_0000;
+    pub const SA_SIGINFO: c_int = 4;
+    pub const MAP_ANONYMOUS: c_int = 0x20;
+    pub const SC_PAGESIZE: c_int = 30;
+}
+
+#[cfg(target_os = "macos")]
+mod sys {
+    use std::ffi::c_int;
+
+    #[repr(C)]
+    pub struct SigAction {
+        pub sa_sigaction: usize,
+        pub sa_mask: u32,
+        pub sa_flags: c_int,
+    }
+
+    /// The offset of `si_addr` in `siginfo_t`.
+    pub const SI_ADDR_OFFSET: usize = 24;
+    pub const SIGBUS: c_int = 10;
+    pub const SA_ONSTACK: c_int = 1;
+    pub const SA_SIGINFO: c_int = 0x40;
+    pub const MAP_ANONYMOUS: c_int = 0x1000;
+    pub const SC_PAGESIZE: c_int = 29;
+}
+
+use sys::SigAction;
+
+const SIGSEGV: c_int = 11;
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_PRIVATE: c_int = 2;
+const MAP_FIXED: c_int = 0x10;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+extern "C" {
+    fn sigaction(signum: c_int, act: *const SigAction, oldact: *mut SigAction) -> c_int;
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn sysconf(name: c_int) -> std::ffi::c_long;
+}
+
+thread_local! {
+    /// The region guarded on current thread, as `(start, end)`.
+    static THREAD_REGION: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+    /// The address of the first fault in the guarded region, or zero.
+    static THREAD_FAULT: Cell<usize> = const { Cell::new(0) };
+}
+
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+static PREVIOUS_ACTIONS: PreviousActions = PreviousActions(UnsafeCell::new([empty_action(), empty_action()]));
+
+/// The actions of `SIGBUS` and `SIGSEGV` before installing the fault handler.
+struct PreviousActions(UnsafeCell<[SigAction; 2]>);
+
+// SAFETY: the actions are written once before the fault handler reading them is
+// installed.
+unsafe impl Sync for PreviousActions {}
+
+const fn empty_action() -> SigAction {
+    // SAFETY: all-zero is a valid `struct sigaction` of `SIG_DFL`.
+    unsafe { std::mem::zeroed() }
+}
+
+/// The error type for [`catch_mmap_fault`], reporting a fault in the guarded
+/// region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmapFault {
+    address: usize,
+    location: &'static Location<'static>,
+}
+
+impl MmapFault {
+    /// Returns the address of the first fault in the guarded region.
+    #[inline]
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Returns the location of the `catch_mmap_fault` call catching the fault.
+    #[inline]
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+}
+
+impl fmt::Display for MmapFault {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "memory-mapped region faulted at {:#x}, caught at {}",
+            self.address, self.location
+        )
+    }
+}
+
+impl Error for MmapFault {}
+
+/// Handles `SIGBUS` and `SIGSEGV`, replacing the faulting page in the guarded
+/// region by a page of zeros, and passing other faults to the previous handler.
+extern "C" fn handle_fault(signum: c_int, info: *mut c_void, context: *mut c_void) {
+    // SAFETY: the kernel passes a valid `siginfo_t` for a `SA_SIGINFO` handler.
+    let address = unsafe { *info.cast::<u8>().add(sys::SI_ADDR_OFFSET).cast::<usize>() };
+    let (start, end) = THREAD_REGION.with(Cell::get);
+    if (start..end).contains(&address) {
+        let page_size = PAGE_SIZE.load(Ordering::Relaxed);
+        let page = (address & !(page_size - 1)) as *mut c_void;
+        // SAFETY: the page belongs to the guarded region, which nothing but the
+        // closure in `catch_mmap_fault` is reading meanwhile.
+        let mapped = unsafe {
+            mmap(
+                page,
+                page_size,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_FIXED | sys::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if mapped != MAP_FAILED {
+            THREAD_FAULT.with(|fault| {
+                if fault.get() == 0 {
+                    fault.set(address);
+                }
+            });
+            return;
+        }
+    }
+
+    let index = if signum == sys::SIGBUS { 0 } else { 1 };
+    let previous = PREVIOUS_ACTIONS
+        .0
+        .get()
+        .cast::<SigAction>()
+        .wrapping_add(index)
+        .cast_const();
+    // SAFETY: the previous action is a valid `struct sigaction`, and its handler
+    // expects the arguments of the signal.
+    unsafe {
+        match (*previous).sa_sigaction {
+            SIG_DFL | SIG_IGN => {
+                // Restore the previous action, so the fault happens again and
+                // kills the process when returning.
+                sigaction(signum, previous, ptr::null_mut());
+            }
+            handler if (*previous).sa_flags & sys::SA_SIGINFO != 0 => {
+                let handler: extern "C" fn(c_int, *mut c_void, *mut c_void) = std::mem::transmute(handler);
+                handler(signum, info, context);
+            }
+            handler => {
+                let handler: extern "C" fn(c_int) = std::mem::transmute(handler);
+                handler(signum);
+            }
+        }
+    }
+}
+
+/// Installs the fault handler once.
+fn install_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        // SAFETY: `sysconf` has no preconditions.
+        let page_size = unsafe { sysconf(sys::SC_PAGESIZE) };
+        PAGE_SIZE.store(page_size as usize, Ordering::Relaxed);
+
+        let mut action = empty_action();
+        action.sa_sigaction = handle_fault as extern "C" fn(c_int, *mut c_void, *mut c_void) as usize;
+        action.sa_flags = sys::SA_SIGINFO | sys::SA_ONSTACK;
+        // SAFETY: the previous actions are written before the handler reading
+        // them is installed, and `action` is a valid `struct sigaction`.
+        unsafe {
+            let previous = PREVIOUS_ACTIONS.0.get().cast::<SigAction>();
+            sigaction(sys::SIGBUS, &action, previous);
+            sigaction(SIGSEGV, &action, previous.add(1));
+        }
+    });
+}
+
+/// Restores the guarded region and the fault of the outer scope on drop.
+struct RegionGuard {
+    region: (usize, usize),
+    fault: usize,
+}
+
+impl RegionGuard {
+    fn enter(region: (usize, usize)) -> Self {
+        RegionGuard {
+            region: THREAD_REGION.with(|r| r.replace(region)),
+            fault: THREAD_FAULT.with(|f| f.replace(0)),
+        }
+    }
+}
+
+impl Drop for RegionGuard {
+    fn drop(&mut self) {
+        THREAD_REGION.with(|r| r.set(self.region));
+        THREAD_FAULT.with(|f| f.set(self.fault));
+    }
+}
+
+/// Invokes a closure reading a memory-mapped `region`, returning an error instead
+/// of killing the process if the region faults meanwhile.
+///
+/// Reading a memory-mapped file which is truncated underneath raises `SIGBUS`.
+/// The faults on `region` in current thread are handled by replacing the faulting
+/// pages by pages of zeros, so the closure runs to completion, and its result is
+/// discarded as it may be computed from the zeros. The replaced pages no longer
+/// map the file, so the region should be unmapped and mapped again afterwards.
+/// The faults outside `region`, or in other threads, are passed to the handlers
+/// installed before the first call.
+///
+/// Only Linux and macOS on x86-64 and AArch64 are supported.
+///
+/// ```
+/// use panic_safe::catch_mmap_fault;
+///
+/// # fn main() -> Result<(), panic_safe::MmapFault> {
+/// # let mmap = vec![1u8; 4096];
+/// let checksum = catch_mmap_fault(&mmap, || mmap.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)))?;
+/// # Ok(())
+/// # }
+/// ```
+#[track_caller]
+pub fn catch_mmap_fault<F: FnOnce() -> R, R>(region: &[u8], f: F) -> Result<R, MmapFault> {
+    let location = Location::caller();
+    install_handler();
+    let start = region.as_ptr() as usize;
+    let guard = RegionGuard::enter((start, start + region.len()));
+    let result = f();
+    let address = THREAD_FAULT.with(Cell::get);
+    drop(guard);
+    if address == 0 {
+        Ok(result)
+    } else {
+        Err(MmapFault { address, location })
+    }
+}
@@ -0,0 +1,79 @@
+//! The scopes where heap allocation is not allowed.
+
+use std::cell::Cell;
+use std::panic::{Location, UnwindSafe};
+
+use crate::catch::catch_oom_at;
+use crate::hook::ThreadPanic;
+use crate::AllocError;
+
+thread_local! {
+    static THREAD_NO_ALLOC: Cell<bool> = const { Cell::new(false) };
+    static THREAD_DENIED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Checks if the allocation of current thread should be denied, and records it.
+///
+/// The allocations handling the denial as an allocation error are allowed.
+#[inline]
+pub(crate) fn deny() -> bool {
+    let deny = THREAD_NO_ALLOC.with(Cell::get) && !ThreadPanic::handling_oom() && !std::thread::panicking();
+    if deny {
+        THREAD_DENIED.with(|d| d.set(true));
+    }
+    deny
+}
+
+struct NoAllocGuard(bool);
+
+impl NoAllocGuard {
+    #[inline]
+    fn enter() -> Self {
+        THREAD_DENIED.with(|d| d.set(false));
+        NoAllocGuard(THREAD_NO_ALLOC.with(|n| n.replace(true)))
+    }
+}
+
+impl Drop for NoAllocGuard {
+    #[inline]
+    fn drop(&mut self) {
+        THREAD_NO_ALLOC.with(|n| n.set(self.0));
+    }
+}
+
+/// Invokes a closure, failing any heap allocation inside it.
+///
+/// An allocation in the closure fails as if the memory were exhausted, so the
+/// closure is unwound, and an `AllocError` with the context
+/// `"heap allocation in no-alloc scope"` is returned. In debug builds, this
+/// function panics instead, pointing at the call. This is for the code which
+/// must not allocate, e.g. realtime audio callbacks.
+///
+/// [`TrackingAlloc`](crate::TrackingAlloc) must be registered as the global
+/// allocator, otherwise the allocations are not checked.
+///
+/// ```
+/// use panic_safe::assert_no_alloc;
+///
+/// let samples = [0.5f32; 256];
+/// let sum = assert_no_alloc(|| samples.iter().sum::<f32>()).unwrap();
+/// ```
+#[track_caller]
+pub fn assert_no_alloc<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, AllocError> {
+    let caller = Location::caller();
+    // Installing the hooks allocates, which must not be denied.
+    crate::init();
+    let result = {
+        let _guard = NoAllocGuard::enter();
+        catch_oom_at(caller, f)
+    };
+    match result {
+        Err(e) if THREAD_DENIED.with(|d| d.replace(false)) => {
+            if cfg!(debug_assertions) {
+                panic!("heap allocation of {} bytes in no-alloc scope", e.size());
+            }
+            Err(e.with_context("heap allocation in no-alloc scope"))
+        }
+        result => result,
+    }
+}
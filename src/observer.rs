@@ -0,0 +1,69 @@
+//! Observers notified of every caught allocation error.
+
+use std::sync::{PoisonError, RwLock};
+
+use crate::AllocError;
+
+/// The registered observers, notified in the order of registration.
+static OBSERVERS: RwLock<Vec<fn(&AllocErrorInfo<'_>)>> = RwLock::new(Vec::new());
+
+/// The information of a caught allocation error passed to the observers.
+#[derive(Copy, Clone, Debug)]
+pub struct AllocErrorInfo<'a> {
+    error: &'a AllocError,
+    freed: usize,
+}
+
+impl AllocErrorInfo<'_> {
+    /// Returns the caught allocation error, including its layout, kind and the
+    /// location where it is caught.
+    #[must_use]
+    #[inline]
+    pub fn error(&self) -> &AllocError {
+        self.error
+    }
+
+    /// Returns the number of bytes freed by the
+    /// [low-memory listeners](crate::register_low_memory_listener) for the error.
+    #[must_use]
+    #[inline]
+    pub fn freed(&self) -> usize {
+        self.freed
+    }
+}
+
+/// Registers an observer, which is notified of every allocation error caught in
+/// the process.
+///
+/// Observers let subsystems like logging, metrics and load shedding see the
+/// allocation errors without each catching site reporting them. They are
+/// notified in the order of registration on the catching thread after unwinding,
+/// once the low-memory listeners have been notified. They must not register
+/// observers, which deadlocks.
+///
+/// ```
+/// use panic_safe::{register_oom_observer, AllocErrorInfo};
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// # static OOM_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// # static FAILED_BYTES: AtomicUsize = AtomicUsize::new(0);
+///
+/// fn count_oom(info: &AllocErrorInfo<'_>) {
+///     OOM_COUNT.fetch_add(1, Ordering::Relaxed);
+///     FAILED_BYTES.fetch_add(info.error().size(), Ordering::Relaxed);
+/// }
+///
+/// register_oom_observer(count_oom);
+/// ```
+pub fn register_oom_observer(observer: fn(&AllocErrorInfo<'_>)) {
+    OBSERVERS.write().unwrap_or_else(PoisonError::into_inner).push(observer);
+}
+
+/// Notifies the observers of the caught `error`, after the low-memory listeners
+/// freed `freed` bytes.
+#[inline]
+pub(crate) fn notify_observers(error: &AllocError, freed: usize) {
+    let info = AllocErrorInfo { error, freed };
+    for observer in OBSERVERS.read().unwrap_or_else(PoisonError::into_inner).iter() {
+        observer(&info);
+    }
+}
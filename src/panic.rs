@@ -0,0 +1,332 @@
+//! The panic error types.
+
+use std::any::Any;
+use std::error::Error;
+#[cfg(not(feature = "stable"))]
+use std::error::Request;
+use std::fmt;
+use std::panic::{Location, PanicHookInfo};
+
+use crate::AllocError;
+
+/// The source location of a panic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PanicLocation {
+    file: String,
+    line: u32,
+    column: u32,
+}
+
+impl PanicLocation {
+    #[inline]
+    pub(crate) fn new(file: String, line: u32, column: u32) -> Self {
+        PanicLocation { file, line, column }
+    }
+
+    /// Returns the name of the source file from which the panic originated.
+    #[must_use]
+    #[inline]
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Returns the line number from which the panic originated.
+    #[must_use]
+    #[inline]
+    pub const fn line(&self) -> u32 {
+        self.line
+    }
+
+    /// Returns the column from which the panic originated.
+    #[must_use]
+    #[inline]
+    pub const fn column(&self) -> u32 {
+        self.column
+    }
+}
+
+impl PanicLocation {
+    /// Creates a `PanicLocation` from `location`, or returns `None` if allocating
+    /// the file name fails.
+    #[inline]
+    pub(crate) fn try_from_location(location: &Location<'_>) -> Option<Self> {
+        let mut file = String::new();
+        file.try_reserve_exact(location.file().len()).ok()?;
+        file.push_str(location.file());
+        Some(PanicLocation::new(file, location.line(), location.column()))
+    }
+}
+
+impl From<&Location<'_>> for PanicLocation {
+    #[inline]
+    fn from(location: &Location<'_>) -> Self {
+        PanicLocation::new(location.file().to_owned(), location.line(), location.column())
+    }
+}
+
+impl fmt::Display for PanicLocation {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Returns the message of a panic payload if it is a `&'static str` or a
+/// `String`, i.e. the payload of `panic!`.
+///
+/// Passing a `&Box<dyn Any + Send>` coerces the box itself into `dyn Any`, rather
+/// than the payload in it, which is a common mistake. The payload in such a box is
+/// looked into as well.
+///
+/// ```
+/// use panic_safe::payload_as_str;
+///
+/// let payload = std::panic::catch_unwind(|| panic!("boom")).unwrap_err();
+/// assert_eq!(payload_as_str(&payload), Some("boom"));
+/// ```
+#[must_use]
+pub fn payload_as_str<'a>(payload: &'a (dyn Any + Send + 'static)) -> Option<&'a str> {
+    if let Some(s) = payload.downcast_ref::<&'static str>() {
+        Some(s)
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        Some(s)
+    } else {
+        payload
+            .downcast_ref::<Box<dyn Any + Send + 'static>>()
+            .and_then(|payload| payload_as_str(&**payload))
+    }
+}
+
+/// The kind of a panic, classified by its payload.
+///
+/// The kinds other than `OutOfMemory` are classified by the messages of the
+/// standard library, which are not guaranteed to be stable, e.g. the panics of
+/// `expect` carry the messages passed to it, and are classified as `Explicit`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PanicKind {
+    /// The out-of-memory panic.
+    OutOfMemory,
+    /// A failed assertion of `assert!`, `assert_eq!` or `assert_ne!`.
+    Assertion,
+    /// An index or range out of the bounds of a slice or a string.
+    IndexOutOfBounds,
+    /// `unwrap` of `None`, or of `Err` or `Ok` of `Result`.
+    Unwrap,
+    /// An arithmetic overflow, or a division by zero.
+    ArithmeticOverflow,
+    /// Other panics with a message, e.g. by `panic!`.
+    Explicit,
+    /// Panics whose payload is not a string, e.g. by `panic_any`.
+    Unknown,
+}
+
+impl PanicKind {
+    /// Classifies a panic by its payload.
+    #[must_use]
+    pub fn of_payload(payload: &(dyn Any + Send + 'static)) -> Self {
+        if payload.is::<crate::hook::OomPanic>() || payload.is::<AllocError>() {
+            return PanicKind::OutOfMemory;
+        }
+        let Some(message) = payload_as_str(payload) else {
+            return PanicKind::Unknown;
+        };
+        if message.starts_with("assertion failed") || message.starts_with("assertion `") {
+            PanicKind::Assertion
+        } else if message.starts_with("index out of bounds")
+            || message.starts_with("range start index")
+            || message.starts_with("range end index")
+            || message.starts_with("slice index starts at")
+            || message.contains("byte index")
+            || message.starts_with("begin <= end")
+        {
+            PanicKind::IndexOutOfBounds
+        } else if message.starts_with("called `Option::unwrap") || message.starts_with("called `Result::unwrap") {
+            PanicKind::Unwrap
+        } else if message.starts_with("attempt to ") {
+            PanicKind::ArithmeticOverflow
+        } else {
+            PanicKind::Explicit
+        }
+    }
+
+    /// Classifies a panic by the information passed to the panic hook, e.g. in
+    /// an abort hook or the fatal reporter.
+    #[must_use]
+    #[inline]
+    pub fn of_hook_info(info: &PanicHookInfo<'_>) -> Self {
+        PanicKind::of_payload(info.payload())
+    }
+
+    /// Returns the name of the kind in snake case, e.g. `index_out_of_bounds`,
+    /// which is stable to bucket the panics by.
+    #[must_use]
+    #[inline]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            PanicKind::OutOfMemory => "out_of_memory",
+            PanicKind::Assertion => "assertion",
+            PanicKind::IndexOutOfBounds => "index_out_of_bounds",
+            PanicKind::Unwrap => "unwrap",
+            PanicKind::ArithmeticOverflow => "arithmetic_overflow",
+            PanicKind::Explicit => "explicit",
+            PanicKind::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for PanicKind {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The error type for a panic captured by [`catch_panic`].
+pub struct PanicError {
+    location: Option<PanicLocation>,
+    payload: Box<dyn Any + Send + 'static>,
+}
+
+impl PanicError {
+    #[inline]
+    pub(crate) fn new(location: Option<PanicLocation>, payload: Box<dyn Any + Send + 'static>) -> Self {
+        PanicError { location, payload }
+    }
+
+    /// Returns the panic message if the payload is a string, or if the panic is an
+    /// out-of-memory panic.
+    #[must_use]
+    #[inline]
+    pub fn message(&self) -> Option<&str> {
+        if self.payload.is::<crate::hook::OomPanic>() || self.payload.is::<AllocError>() {
+            Some("memory allocation failed")
+        } else {
+            payload_as_str(&*self.payload)
+        }
+    }
+
+    /// Returns the kind of the panic, classified by its payload.
+    #[must_use]
+    #[inline]
+    pub fn kind(&self) -> PanicKind {
+        PanicKind::of_payload(&*self.payload)
+    }
+
+    /// Returns the location from which the panic originated, if available.
+    #[must_use]
+    #[inline]
+    pub fn location(&self) -> Option<&PanicLocation> {
+        self.location.as_ref()
+    }
+
+    /// Returns the payload associated with the panic.
+    #[must_use]
+    #[inline]
+    pub fn payload(&self) -> &(dyn Any + Send + 'static) {
+        &*self.payload
+    }
+
+    /// Returns the payload associated with the panic if it is of type `T`, e.g. the
+    /// value passed to `panic_any`.
+    #[must_use]
+    #[inline]
+    pub fn downcast_payload<T: Any>(&self) -> Option<&T> {
+        self.payload.downcast_ref()
+    }
+
+    /// Consumes the `PanicError`, returning the payload associated with the panic.
+    ///
+    /// The payload can be passed to [`std::panic::resume_unwind`] to continue unwinding.
+    #[must_use]
+    #[inline]
+    pub fn into_payload(self) -> Box<dyn Any + Send + 'static> {
+        self.payload
+    }
+}
+
+impl fmt::Debug for PanicError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PanicError")
+            .field("message", &self.message())
+            .field("location", &self.location)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for PanicError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("panicked")?;
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        f.write_str(": ")?;
+        f.write_str(self.message().unwrap_or("Box<dyn Any>"))
+    }
+}
+
+impl Error for PanicError {
+    #[cfg(not(feature = "stable"))]
+    fn provide<'a>(&'a self, request: &mut Request<'a>) {
+        if let Some(location) = &self.location {
+            request.provide_ref::<PanicLocation>(location);
+        }
+    }
+}
+
+/// The error type for [`catch_any`], which is either an allocation error or a panic.
+///
+/// The error is transparent: it displays as the inner error, and its source is
+/// the source of the inner error, so error reporters do not print the inner error
+/// twice.
+#[derive(Debug)]
+pub enum CaughtError {
+    /// Allocation error occurs.
+    Oom(AllocError),
+    /// Panics other than allocation error occur.
+    Panic(PanicError),
+}
+
+impl From<AllocError> for CaughtError {
+    #[inline]
+    fn from(e: AllocError) -> Self {
+        CaughtError::Oom(e)
+    }
+}
+
+impl From<PanicError> for CaughtError {
+    #[inline]
+    fn from(e: PanicError) -> Self {
+        CaughtError::Panic(e)
+    }
+}
+
+impl fmt::Display for CaughtError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaughtError::Oom(e) => fmt::Display::fmt(e, f),
+            CaughtError::Panic(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl Error for CaughtError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CaughtError::Oom(e) => e.source(),
+            CaughtError::Panic(e) => e.source(),
+        }
+    }
+
+    #[cfg(not(feature = "stable"))]
+    fn provide<'a>(&'a self, request: &mut Request<'a>) {
+        match self {
+            CaughtError::Oom(e) => e.provide(request),
+            CaughtError::Panic(e) => e.provide(request),
+        }
+    }
+}
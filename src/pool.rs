@@ -0,0 +1,138 @@
+//! A thread pool running every job under the catcher.
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe, Location, UnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed-size thread pool where each submitted job runs under [`catch_oom`](crate::catch_oom).
+///
+/// The completion of a job is delivered as `Result<R, AllocError>` over a channel.
+/// Dropping the pool waits for all submitted jobs to complete.
+#[derive(Debug)]
+pub struct PanicSafeThreadPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl PanicSafeThreadPool {
+    /// Creates a new thread pool with `size` worker threads.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the OS fails to create a thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn new(size: usize) -> io::Result<Self> {
+        assert!(size > 0, "thread pool size must be greater than zero");
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::with_capacity(size);
+        for i in 0..size {
+            let receiver = Arc::clone(&receiver);
+            let worker = thread::Builder::new()
+                .name(format!("panic-safe-worker-{}", i))
+                .spawn(move || loop {
+                    let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+                    match job {
+                        // Keeps the worker alive if a panic is propagated out of a job.
+                        Ok(job) => {
+                            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                        }
+                        Err(_) => break,
+                    }
+                })?;
+            workers.push(worker);
+        }
+
+        Ok(PanicSafeThreadPool {
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    /// Returns the number of worker threads.
+    #[must_use]
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Submits a job to the pool, returning a [`JobHandle`] to wait for its result.
+    #[track_caller]
+    pub fn submit<F, R>(&self, f: F) -> JobHandle<R>
+    where
+        F: FnOnce() -> R + UnwindSafe + Send + 'static,
+        R: Send + 'static,
+    {
+        let caller = Location::caller();
+        let (sender, receiver) = mpsc::channel();
+        self.spawn(Box::new(move || {
+            let _ = sender.send(panic::catch_unwind(|| catch_oom_at(caller, f)));
+        }));
+        JobHandle { receiver }
+    }
+
+    /// Submits a job to the pool, sending its result to `sender` on completion.
+    ///
+    /// This is useful for collecting the results of a batch of jobs from a single
+    /// channel. If a panic other than allocation error is propagated out of the
+    /// job, nothing is sent.
+    #[track_caller]
+    pub fn execute<F, R>(&self, f: F, sender: Sender<Result<R, AllocError>>)
+    where
+        F: FnOnce() -> R + UnwindSafe + Send + 'static,
+        R: Send + 'static,
+    {
+        let caller = Location::caller();
+        self.spawn(Box::new(move || {
+            let _ = sender.send(catch_oom_at(caller, f));
+        }));
+    }
+
+    #[inline]
+    fn spawn(&self, job: Job) {
+        // The receiver lives as long as the workers, which are joined on drop.
+        let _ = self.sender.as_ref().expect("thread pool is dropped").send(job);
+    }
+}
+
+impl Drop for PanicSafeThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A handle to wait for the result of a job submitted to [`PanicSafeThreadPool`].
+#[derive(Debug)]
+pub struct JobHandle<R> {
+    receiver: Receiver<thread::Result<Result<R, AllocError>>>,
+}
+
+impl<R> JobHandle<R> {
+    /// Waits for the job to complete, returning its result.
+    ///
+    /// If a panic other than allocation error is propagated out of the job, which
+    /// is not aborted by the global [`catch_mode`](crate::catch_mode), the panic is
+    /// propagated to the caller.
+    #[inline]
+    pub fn join(self) -> Result<R, AllocError> {
+        match self.receiver.recv() {
+            Ok(Ok(result)) => result,
+            Ok(Err(payload)) => panic::resume_unwind(payload),
+            Err(_) => unreachable!("job is dropped without running"),
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Integration with PostgreSQL extensions.
+//!
+//! The errors are reported by the error API of the backend, `errstart` and
+//! `errfinish`, which are resolved against the server when the extension is
+//! loaded, so no binding crate is required. The error levels are those of
+//! PostgreSQL 14 and later.
+
+use std::ffi::{c_char, c_int};
+use std::fmt::Write;
+
+use crate::message::StackBuffer;
+use crate::CaughtError;
+
+const ERROR: c_int = 21;
+const PANIC: c_int = 23;
+
+extern "C" {
+    fn errstart(elevel: c_int, domain: *const c_char) -> bool;
+    fn errcode(sqlerrcode: c_int) -> c_int;
+    fn errmsg(fmt: *const c_char, ...) -> c_int;
+    fn errfinish(filename: *const c_char, lineno: c_int, funcname: *const c_char);
+}
+
+/// Encodes a SQLSTATE as `MAKE_SQLSTATE` does.
+fn make_sqlstate(sqlstate: &str) -> c_int {
+    sqlstate
+        .bytes()
+        .enumerate()
+        .map(|(i, c)| (c_int::from(c.wrapping_sub(b'0')) & 0x3f) << (6 * i))
+        .sum()
+}
+
+/// Reports an error of `elevel` by `ereport`, which does not return.
+///
+/// # Safety
+///
+/// It must be called in a backend, and the frames between it and the `sigsetjmp`
+/// of the backend must not have destructors to run.
+unsafe fn ereport(elevel: c_int, sqlstate: &str, message: &mut StackBuffer) -> ! {
+    // The location is kept by the backend after the jump, so it is the static
+    // location of this call, and the location of the caller is in the message.
+    let filename = concat!(file!(), "\0");
+    // SAFETY: guaranteed by the caller, and the format consumes one string.
+    unsafe {
+        if errstart(elevel, std::ptr::null()) {
+            errcode(make_sqlstate(sqlstate));
+            errmsg(c"%s".as_ptr(), message.as_c_str().as_ptr());
+            errfinish(filename.as_ptr().cast(), line!() as c_int, c"catch_oom_pg".as_ptr());
+        }
+    }
+    // Errors of `ERROR` and above are always reported.
+    std::process::abort()
+}
+
+/// Runs the body of a PostgreSQL function by [`catch_ffi`](crate::catch_ffi),
+/// reporting an allocation error by `ereport(ERROR, ...)`, which aborts the
+/// current transaction instead of the backend, and other panics by
+/// `ereport(PANIC, ...)`.
+///
+/// The SQLSTATE of the allocation error is given by
+/// [`AllocError::sqlstate`](crate::AllocError::sqlstate), e.g. `53200`
+/// (`out_of_memory`), and the message is the `AllocError`. The caught error is
+/// dropped before reporting.
+///
+/// `ereport` jumps to the error handler of the backend by `longjmp`, skipping the
+/// frames of the caller, so this is meant to be the whole body of an
+/// `extern "C"` function called by the backend, without values having
+/// destructors outside of the closure. With pgrx, whose `#[pg_extern]` functions
+/// convert panics raised by `ereport!` instead, report the error returned by
+/// [`catch_oom`](crate::catch_oom) by `ereport!` of pgrx.
+///
+/// # Safety
+///
+/// It must be called in a backend of PostgreSQL 14 or later, and the frames
+/// between it and the error handler of the backend must not have destructors to
+/// run.
+///
+/// ```no_run
+/// # type FunctionCallInfo = *mut std::ffi::c_void;
+/// # type Datum = usize;
+/// # struct Index;
+/// # impl Index {
+/// #     fn build(_: FunctionCallInfo) -> Self { Index }
+/// #     fn into_datum(self) -> Datum { 0 }
+/// # }
+/// #[no_mangle]
+/// pub extern "C" fn index_build(fcinfo: FunctionCallInfo) -> Datum {
+///     unsafe { panic_safe::catch_oom_pg(|| Index::build(fcinfo).into_datum()) }
+/// }
+/// ```
+#[track_caller]
+pub unsafe fn catch_oom_pg<F: FnOnce() -> R, R>(f: F) -> R {
+    let mut message = StackBuffer::new();
+    let (elevel, sqlstate) = match crate::catch_ffi(f) {
+        Ok(r) => return r,
+        Err(CaughtError::Oom(e)) => {
+            let _ = write!(message, "{}", e);
+            (ERROR, e.sqlstate())
+        }
+        Err(CaughtError::Panic(panic)) => {
+            let _ = write!(message, "{}", panic);
+            (PANIC, "XX000")
+        }
+    };
+    // SAFETY: guaranteed by the caller.
+    unsafe { ereport(elevel, sqlstate, &mut message) }
+}
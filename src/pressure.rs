@@ -0,0 +1,438 @@
+//! The memory pressure reported by the operating system, i.e. the PSI of the
+//! cgroup on Linux, the memory resource notification on Windows, and the memory
+//! pressure level on macOS.
+
+#[cfg(target_os = "linux")]
+use std::fs;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+type Listener = Box<dyn Fn(bool) + 'static + Sync + Send>;
+
+static LOW_MEMORY: AtomicBool = AtomicBool::new(false);
+/// The registered memory pressure listeners, notified in the order of registration.
+static LISTENERS: RwLock<Vec<Listener>> = RwLock::new(Vec::new());
+
+/// Returns `true` if the memory pressure watched by `watch_memory_pressure` on
+/// Linux, `watch_memory_resource` on Windows or `watch_memory_pressure_level` on
+/// macOS is high.
+///
+/// This lets the code in catching scopes shed memory before an allocation error
+/// actually occurs. It is always `false` if the memory pressure is not watched.
+/// The listeners registered by [`register_memory_pressure_listener`] are notified
+/// when it flips.
+#[must_use]
+#[inline]
+pub fn low_memory() -> bool {
+    LOW_MEMORY.load(Ordering::Relaxed)
+}
+
+/// Registers a memory pressure listener, which is notified with the new
+/// [`low_memory`] flag whenever it flips.
+///
+/// The listeners are notified on the thread of the watcher, both when the
+/// pressure rises above the threshold and when it falls below it again, e.g. to
+/// shed caches and to refill them. Dropping the watcher clears the flag, which
+/// notifies them as well. They must not register listeners, which deadlocks.
+///
+/// ```
+/// use panic_safe::register_memory_pressure_listener;
+///
+/// register_memory_pressure_listener(|low| eprintln!("low memory: {low}"));
+/// ```
+pub fn register_memory_pressure_listener<F>(listener: F)
+where
+    F: Fn(bool) + 'static + Sync + Send,
+{
+    LISTENERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Box::new(listener));
+}
+
+/// Sets the low memory flag, notifying the listeners and returning `true` if it
+/// flips.
+#[inline]
+fn set_low_memory(low: bool) -> bool {
+    let flipped = LOW_MEMORY.swap(low, Ordering::Relaxed) != low;
+    if flipped {
+        for listener in LISTENERS.read().unwrap_or_else(PoisonError::into_inner).iter() {
+            listener(low);
+        }
+    }
+    flipped
+}
+
+/// The share of time stalled on memory by some or all tasks, see [`MemoryPressure`].
+#[cfg(target_os = "linux")]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PressureStall {
+    avg10: f64,
+    avg60: f64,
+    avg300: f64,
+    total: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl PressureStall {
+    /// Returns the percentage of time stalled in the last 10 seconds.
+    #[must_use]
+    #[inline]
+    pub fn avg10(&self) -> f64 {
+        self.avg10
+    }
+
+    /// Returns the percentage of time stalled in the last 60 seconds.
+    #[must_use]
+    #[inline]
+    pub fn avg60(&self) -> f64 {
+        self.avg60
+    }
+
+    /// Returns the percentage of time stalled in the last 300 seconds.
+    #[must_use]
+    #[inline]
+    pub fn avg300(&self) -> f64 {
+        self.avg300
+    }
+
+    /// Returns the total time stalled in microseconds.
+    #[must_use]
+    #[inline]
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Parses a line like `some avg10=0.00 avg60=0.00 avg300=0.00 total=0`.
+    fn parse(line: &str) -> Self {
+        let mut stall = PressureStall::default();
+        for field in line.split_ascii_whitespace().skip(1) {
+            match field.split_once('=') {
+                Some(("avg10", value)) => stall.avg10 = value.parse().unwrap_or_default(),
+                Some(("avg60", value)) => stall.avg60 = value.parse().unwrap_or_default(),
+                Some(("avg300", value)) => stall.avg300 = value.parse().unwrap_or_default(),
+                Some(("total", value)) => stall.total = value.parse().unwrap_or_default(),
+                _ => {}
+            }
+        }
+        stall
+    }
+}
+
+/// The memory pressure stall information of a cgroup, read from its
+/// `memory.pressure`.
+#[cfg(target_os = "linux")]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MemoryPressure {
+    some: PressureStall,
+    full: PressureStall,
+}
+
+#[cfg(target_os = "linux")]
+impl MemoryPressure {
+    /// Returns the time in which at least some tasks stalled on memory.
+    #[must_use]
+    #[inline]
+    pub fn some(&self) -> &PressureStall {
+        &self.some
+    }
+
+    /// Returns the time in which all non-idle tasks stalled on memory at once.
+    #[must_use]
+    #[inline]
+    pub fn full(&self) -> &PressureStall {
+        &self.full
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut pressure = MemoryPressure::default();
+        for line in text.lines() {
+            if line.starts_with("some ") {
+                pressure.some = PressureStall::parse(line);
+            } else if line.starts_with("full ") {
+                pressure.full = PressureStall::parse(line);
+            }
+        }
+        pressure
+    }
+}
+
+/// Returns the `memory.pressure` file of the cgroup of the process, or the
+/// system-wide one if the process is in the root cgroup.
+#[cfg(target_os = "linux")]
+fn pressure_file() -> io::Result<PathBuf> {
+    let cgroup = fs::read_to_string("/proc/self/cgroup")?;
+    if let Some(path) = cgroup.lines().find_map(|line| line.strip_prefix("0::")) {
+        let file = PathBuf::from("/sys/fs/cgroup")
+            .join(path.trim_start_matches('/'))
+            .join("memory.pressure");
+        if file.exists() {
+            return Ok(file);
+        }
+    }
+    let file = PathBuf::from("/proc/pressure/memory");
+    if file.exists() {
+        Ok(file)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "memory pressure is not available",
+        ))
+    }
+}
+
+/// The watcher of the memory pressure, which stops watching when dropped.
+#[derive(Debug)]
+#[must_use = "the memory pressure is not watched once the watcher is dropped"]
+pub struct PressureWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for PressureWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+        set_low_memory(false);
+    }
+}
+
+/// Spawns the thread calling `sample` every `interval` until the watcher is dropped.
+fn spawn_watcher<F: FnMut() + Send + 'static>(interval: Duration, mut sample: F) -> io::Result<PressureWatcher> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = thread::Builder::new().name("panic-safe-pressure".into()).spawn({
+        let stop = Arc::clone(&stop);
+        move || {
+            while !stop.load(Ordering::Relaxed) {
+                sample();
+                thread::park_timeout(interval);
+            }
+        }
+    })?;
+    Ok(PressureWatcher {
+        stop,
+        thread: Some(thread),
+    })
+}
+
+/// Watches the memory pressure of the cgroup of the process on Linux, flipping
+/// the [`low_memory`] flag when the share of time in which some tasks stalled on
+/// memory in the last 10 seconds crosses `threshold` percent.
+///
+/// The pressure is read from `memory.pressure` of the cgroup v2, or from
+/// `/proc/pressure/memory` in the root cgroup, every `interval` on a background
+/// thread. The `callback` is called on that thread with the stall information
+/// whenever the flag flips, so caches can be shed before the hard limit is hit.
+///
+/// Returns an error if the pressure stall information is not available, e.g. on
+/// kernels without PSI.
+///
+/// ```no_run
+/// use panic_safe::{low_memory, watch_memory_pressure};
+/// use std::time::Duration;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let _watcher = watch_memory_pressure(10.0, Duration::from_secs(1), |pressure| {
+///     eprintln!("memory pressure: {:?}, low memory: {}", pressure.some(), low_memory());
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(target_os = "linux")]
+pub fn watch_memory_pressure<F>(threshold: f64, interval: Duration, callback: F) -> io::Result<PressureWatcher>
+where
+    F: Fn(&MemoryPressure) + Send + 'static,
+{
+    let file = pressure_file()?;
+    // Fail early if the file cannot be read.
+    fs::read_to_string(&file)?;
+    spawn_watcher(interval, move || {
+        if let Ok(text) = fs::read_to_string(&file) {
+            let pressure = MemoryPressure::parse(&text);
+            if set_low_memory(pressure.some.avg10 >= threshold) {
+                callback(&pressure);
+            }
+        }
+    })
+}
+
+#[cfg(windows)]
+type Handle = *mut std::ffi::c_void;
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateMemoryResourceNotification(notification_type: i32) -> Handle;
+    fn QueryMemoryResourceNotification(handle: Handle, state: *mut i32) -> i32;
+    fn CloseHandle(handle: Handle) -> i32;
+}
+
+/// The handle of a memory resource notification, closed when dropped.
+#[cfg(windows)]
+struct Notification(Handle);
+
+// SAFETY: the handle of a memory resource notification can be used by any thread.
+#[cfg(windows)]
+unsafe impl Send for Notification {}
+
+#[cfg(windows)]
+impl Notification {
+    /// Returns `true` if the memory is low, or `None` if the query fails.
+    fn query(&self) -> Option<bool> {
+        let mut state = 0;
+        // SAFETY: the handle is valid until dropped.
+        let ok = unsafe { QueryMemoryResourceNotification(self.0, &mut state) } != 0;
+        ok.then_some(state != 0)
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Notification {
+    fn drop(&mut self) {
+        // SAFETY: the handle is valid, and is not used afterwards.
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+/// Watches the available physical memory on Windows, flipping the [`low_memory`]
+/// flag when the system signals low memory or its end.
+///
+/// The low memory resource notification created by
+/// `CreateMemoryResourceNotification` is queried every `interval` on a background
+/// thread. The `callback` is called on that thread with the new flag whenever it
+/// flips, so services can shed load before allocations start failing.
+///
+/// Returns an error if the notification cannot be created.
+///
+/// ```no_run
+/// use panic_safe::watch_memory_resource;
+/// use std::time::Duration;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let _watcher = watch_memory_resource(Duration::from_secs(1), |low| {
+///     eprintln!("low memory: {low}");
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(windows)]
+pub fn watch_memory_resource<F>(interval: Duration, callback: F) -> io::Result<PressureWatcher>
+where
+    F: Fn(bool) + Send + 'static,
+{
+    const LOW_MEMORY_RESOURCE_NOTIFICATION: i32 = 0;
+
+    // SAFETY: creating a notification has no preconditions.
+    let handle = unsafe { CreateMemoryResourceNotification(LOW_MEMORY_RESOURCE_NOTIFICATION) };
+    if handle.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    let notification = Notification(handle);
+    spawn_watcher(interval, move || {
+        if let Some(low) = notification.query() {
+            if set_low_memory(low) {
+                callback(low);
+            }
+        }
+    })
+}
+
+#[cfg(target_os = "macos")]
+extern "C" {
+    fn sysctlbyname(
+        name: *const std::ffi::c_char,
+        oldp: *mut std::ffi::c_void,
+        oldlenp: *mut usize,
+        newp: *mut std::ffi::c_void,
+        newlen: usize,
+    ) -> std::ffi::c_int;
+}
+
+/// The memory pressure level of the system on macOS.
+#[cfg(target_os = "macos")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum MemoryPressureLevel {
+    /// The memory is sufficient.
+    Normal,
+    /// The memory is short, and caches should be shed.
+    Warning,
+    /// The memory is critically short, and the processes are about to be killed.
+    Critical,
+}
+
+#[cfg(target_os = "macos")]
+impl MemoryPressureLevel {
+    /// Reads the level from `kern.memorystatus_vm_pressure_level`.
+    fn query() -> io::Result<Self> {
+        let mut level: std::ffi::c_int = 0;
+        let mut len = std::mem::size_of_val(&level);
+        // SAFETY: the name is nul-terminated, and `level` is an int of `len` bytes.
+        let ret = unsafe {
+            sysctlbyname(
+                c"kern.memorystatus_vm_pressure_level".as_ptr(),
+                (&mut level as *mut std::ffi::c_int).cast(),
+                &mut len,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The levels are the values of `DISPATCH_MEMORYPRESSURE_*`.
+        Ok(match level {
+            4 => MemoryPressureLevel::Critical,
+            2 => MemoryPressureLevel::Warning,
+            _ => MemoryPressureLevel::Normal,
+        })
+    }
+}
+
+/// Watches the memory pressure level of the system on macOS, flipping the
+/// [`low_memory`] flag when the level is [`Warning`](MemoryPressureLevel::Warning)
+/// or above.
+///
+/// The level is read from the `kern.memorystatus_vm_pressure_level` sysctl, the
+/// source of `DISPATCH_SOURCE_TYPE_MEMORYPRESSURE`, every `interval` on a
+/// background thread. The `callback` is called on that thread with the new level
+/// whenever it changes.
+///
+/// Returns an error if the level cannot be read.
+///
+/// ```no_run
+/// use panic_safe::watch_memory_pressure_level;
+/// use std::time::Duration;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let _watcher = watch_memory_pressure_level(Duration::from_secs(1), |level| {
+///     eprintln!("memory pressure: {level:?}");
+/// })?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(target_os = "macos")]
+pub fn watch_memory_pressure_level<F>(interval: Duration, callback: F) -> io::Result<PressureWatcher>
+where
+    F: Fn(MemoryPressureLevel) + Send + 'static,
+{
+    let mut current = MemoryPressureLevel::query()?;
+    set_low_memory(current >= MemoryPressureLevel::Warning);
+    spawn_watcher(interval, move || {
+        if let Ok(level) = MemoryPressureLevel::query() {
+            if level != current {
+                current = level;
+                set_low_memory(level >= MemoryPressureLevel::Warning);
+                callback(level);
+            }
+        }
+    })
+}
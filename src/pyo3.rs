@@ -0,0 +1,60 @@
+//! Integration with PyO3 extension modules.
+
+use std::panic::{Location, UnwindSafe};
+
+use ::pyo3::exceptions::PyMemoryError;
+use ::pyo3::{PyErr, PyResult};
+
+use crate::catch::{catch_oom_with_mode_at, CatchMode};
+use crate::{AllocError, CatchError, CaughtError};
+
+/// Raises the allocation error as `MemoryError` in Python.
+impl From<AllocError> for PyErr {
+    #[inline]
+    fn from(e: AllocError) -> Self {
+        PyMemoryError::new_err(e.to_string())
+    }
+}
+
+/// Raises the allocation error as `MemoryError` in Python, and the user error as
+/// itself.
+impl From<CatchError<PyErr>> for PyErr {
+    #[inline]
+    fn from(e: CatchError<PyErr>) -> Self {
+        match e {
+            CatchError::Alloc(e) => e.into(),
+            CatchError::User(e) => e,
+        }
+    }
+}
+
+/// Runs the body of a `#[pyfunction]` or `#[pymethods]` method, raising
+/// `MemoryError` in Python if an allocation error occurs.
+///
+/// Without it, an allocation failing in an extension module aborts the process
+/// and the interpreter with it. Other panics are propagated regardless of the
+/// global [`catch_mode`](crate::catch_mode), so PyO3 raises them as
+/// `PanicException` as usual.
+///
+/// ```no_run
+/// use pyo3::prelude::*;
+/// # struct Tokenizer;
+/// # impl Tokenizer {
+/// #     fn new() -> Self { Tokenizer }
+/// #     fn tokenize(&self, text: &str) -> Vec<String> { text.split(' ').map(String::from).collect() }
+/// # }
+///
+/// // The body of `#[pyfunction] fn tokenize(text: &str) -> PyResult<Vec<String>>`.
+/// fn tokenize(text: &str) -> PyResult<Vec<String>> {
+///     panic_safe::catch_oom_py(|| Ok(Tokenizer::new().tokenize(text)))
+/// }
+/// ```
+#[track_caller]
+#[inline]
+pub fn catch_oom_py<F: FnOnce() -> PyResult<R> + UnwindSafe, R>(f: F) -> PyResult<R> {
+    match catch_oom_with_mode_at(CatchMode::ResumeUnwind, Location::caller(), f) {
+        Ok(r) => r,
+        Err(CaughtError::Oom(e)) => Err(e.into()),
+        Err(CaughtError::Panic(panic)) => std::panic::resume_unwind(panic.into_payload()),
+    }
+}
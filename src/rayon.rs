@@ -0,0 +1,78 @@
+//! Integration with rayon thread pools.
+
+use std::cell::Cell;
+use std::panic::{Location, UnwindSafe};
+
+use ::rayon::ThreadPool;
+
+use crate::catch::catch_oom_at;
+use crate::hook::ThreadPanic;
+use crate::AllocError;
+
+thread_local! {
+    /// The number of `install_catch_oom` calls running on the pool of current
+    /// worker thread, and its payload transport before the first of them.
+    static THREAD_INSTALLS: Cell<(usize, bool)> = const { Cell::new((0, false)) };
+}
+
+/// Enables the payload transport of all worker threads of a pool until dropped.
+struct PayloadTransport<'a>(&'a ThreadPool);
+
+impl<'a> PayloadTransport<'a> {
+    fn enable(pool: &'a ThreadPool) -> Self {
+        pool.broadcast(|_| {
+            THREAD_INSTALLS.with(|installs| {
+                let (count, previous) = installs.get();
+                let previous = if count == 0 {
+                    ThreadPanic::payload_transport()
+                } else {
+                    previous
+                };
+                installs.set((count + 1, previous));
+            });
+            ThreadPanic::set_payload_transport(true);
+        });
+        PayloadTransport(pool)
+    }
+}
+
+impl Drop for PayloadTransport<'_> {
+    fn drop(&mut self) {
+        self.0.broadcast(|_| {
+            THREAD_INSTALLS.with(|installs| {
+                let (count, previous) = installs.get();
+                installs.set((count - 1, previous));
+                if count == 1 {
+                    ThreadPanic::set_payload_transport(previous);
+                }
+            });
+        });
+    }
+}
+
+/// Runs the closure in the thread pool under [`catch_oom`](crate::catch_oom), capturing the
+/// out-of-memory panic if one occurs in any worker thread.
+///
+/// Rayon propagates a panic of a parallel job, e.g. one inside `par_iter`, to the
+/// thread joining the job, so the catcher is installed in every worker thread of
+/// the pool, which makes the allocation error travel with the panic payload. An
+/// allocation error in any worker is then surfaced as `AllocError` at the calling
+/// site instead of aborting the process.
+///
+/// Installing the catcher broadcasts to all worker threads of the pool before and
+/// after the closure, so the closure is expected to be a coarse-grained unit of
+/// work. While it runs, the out-of-memory panics of the other jobs on the pool
+/// carry the allocation error in the payload as well, e.g. as seen by
+/// `std::panic::catch_unwind` in those jobs. The workers are restored once the
+/// last of the concurrent calls on the pool returns.
+#[track_caller]
+#[inline]
+pub fn install_catch_oom<F, R>(pool: &ThreadPool, f: F) -> Result<R, AllocError>
+where
+    F: FnOnce() -> R + UnwindSafe + Send,
+    R: Send,
+{
+    let caller = Location::caller();
+    let _transport = PayloadTransport::enable(pool);
+    pool.install(move || catch_oom_at(caller, f))
+}
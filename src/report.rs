@@ -0,0 +1,66 @@
+//! Allocation statistics of catching scopes.
+
+use std::panic::{Location, UnwindSafe};
+
+use crate::catch::catch_oom_at;
+use crate::tracking::ThreadUsage;
+use crate::AllocError;
+
+/// Allocation statistics of the closure invoked by [`catch_oom_report`].
+///
+/// The statistics cover the allocations made by the thread invoking the closure,
+/// as counted by [`TrackingAlloc`](crate::TrackingAlloc), which must be registered
+/// as the global allocator, otherwise all statistics are 0.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CatchReport {
+    allocated: usize,
+    peak: usize,
+    count: usize,
+}
+
+impl CatchReport {
+    /// Returns the total bytes allocated by the closure, including the bytes
+    /// deallocated afterwards.
+    #[must_use]
+    #[inline]
+    pub const fn allocated_bytes(&self) -> usize {
+        self.allocated
+    }
+
+    /// Returns the peak of the bytes held by the closure, i.e. the peak of the
+    /// bytes allocated by the thread above the usage when the closure was invoked.
+    #[must_use]
+    #[inline]
+    pub const fn peak_bytes(&self) -> usize {
+        self.peak
+    }
+
+    /// Returns the number of allocations, including reallocations, made by the
+    /// closure.
+    #[must_use]
+    #[inline]
+    pub const fn allocation_count(&self) -> usize {
+        self.count
+    }
+}
+
+/// Invokes a closure like [`catch_oom`](crate::catch_oom), returning the allocation
+/// statistics of the closure alongside the result.
+///
+/// The statistics are collected whether the closure succeeds or fails, e.g. to
+/// attribute the memory cost to a request.
+#[track_caller]
+pub fn catch_oom_report<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> (Result<R, AllocError>, CatchReport) {
+    let caller = Location::caller();
+    let start = ThreadUsage::get();
+    let peak = ThreadUsage::set_peak(start.current);
+    let result = catch_oom_at(caller, f);
+    let end = ThreadUsage::get();
+    ThreadUsage::set_peak(peak.max(end.peak));
+    let report = CatchReport {
+        allocated: end.total.wrapping_sub(start.total),
+        peak: end.peak.saturating_sub(start.current),
+        count: end.count.wrapping_sub(start.count),
+    };
+    (result, report)
+}
@@ -0,0 +1,29 @@
+//! Mixing fallible reservation and the catcher.
+
+use std::panic::{AssertUnwindSafe, Location, UnwindSafe};
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+/// Reserves capacity for at least `additional` more elements by
+/// [`Vec::try_reserve`], and then invokes the closure with the vector under
+/// [`catch_oom`](crate::catch_oom).
+///
+/// Both the failure of the reservation and the allocation error in the closure are
+/// returned as `AllocError`, so code filling a pre-reserved vector needs only one
+/// error type. The vector is left valid but possibly partially modified if the
+/// closure fails.
+///
+/// This function is not available with the `stable` feature, as the layout of
+/// the reservation error can only be extracted by the unstable API.
+#[track_caller]
+#[inline]
+pub fn try_reserve_or_catch<T, F, R>(vec: &mut Vec<T>, additional: usize, f: F) -> Result<R, AllocError>
+where
+    F: FnOnce(&mut Vec<T>) -> R + UnwindSafe,
+{
+    let caller = Location::caller();
+    vec.try_reserve(additional)
+        .map_err(|e| AllocError::from(e).caught_at(caller))?;
+    catch_oom_at(caller, AssertUnwindSafe(|| f(vec)))
+}
@@ -0,0 +1,90 @@
+//! Retrying closures after releasing memory.
+
+use std::panic::{AssertUnwindSafe, Location, UnwindSafe};
+use std::sync::{PoisonError, RwLock};
+use std::time::Duration;
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+type Releaser = Box<dyn Fn() + 'static + Sync + Send>;
+
+/// The registered memory releasers, invoked in the order of registration.
+static RELEASERS: RwLock<Vec<Releaser>> = RwLock::new(Vec::new());
+
+/// Registers a memory releaser, which is invoked before [`catch_oom_retry`] runs
+/// the closure again, e.g. to evict caches.
+///
+/// Releasers are invoked on the retrying thread. They must not register
+/// releasers, which deadlocks.
+pub fn add_memory_releaser<F>(releaser: F)
+where
+    F: Fn() + 'static + Sync + Send,
+{
+    RELEASERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Box::new(releaser));
+}
+
+#[inline]
+fn release_memory() {
+    for releaser in RELEASERS.read().unwrap_or_else(PoisonError::into_inner).iter() {
+        releaser();
+    }
+}
+
+/// Invokes a closure under [`catch_oom`](crate::catch_oom), and runs it again up
+/// to `retries` times if allocation error occurs, invoking the memory releasers
+/// registered by [`add_memory_releaser`] before each retry.
+///
+/// The low-memory listeners registered by
+/// [`register_low_memory_listener`](crate::register_low_memory_listener) are
+/// notified of each allocation error as well, as by every catching function.
+///
+/// Returns the allocation error of the last run if all runs fail, or of the first
+/// run which is impossible, see
+/// [`AllocErrorKind::is_impossible`](crate::AllocErrorKind::is_impossible), as
+/// retrying it does not help. The closure is run after an allocation error in it,
+/// so it should leave its state consistent on unwinding.
+#[track_caller]
+#[inline]
+pub fn catch_oom_retry<F: FnMut() -> R + UnwindSafe, R>(retries: usize, f: F) -> Result<R, AllocError> {
+    retry(Location::caller(), retries, None, f)
+}
+
+/// Invokes a closure like [`catch_oom_retry`], sleeping before each retry for
+/// `backoff`, which is doubled for every retry.
+///
+/// The sleep gives other threads the chance to finish and release their memory.
+#[track_caller]
+#[inline]
+pub fn catch_oom_retry_with_backoff<F: FnMut() -> R + UnwindSafe, R>(
+    retries: usize,
+    backoff: Duration,
+    f: F,
+) -> Result<R, AllocError> {
+    retry(Location::caller(), retries, Some(backoff), f)
+}
+
+fn retry<F: FnMut() -> R + UnwindSafe, R>(
+    caller: &'static Location<'static>,
+    retries: usize,
+    mut backoff: Option<Duration>,
+    mut f: F,
+) -> Result<R, AllocError> {
+    let mut result = catch_oom_at(caller, AssertUnwindSafe(&mut f));
+    for _ in 0..retries {
+        match &result {
+            Err(e) if !e.kind().is_impossible() => {}
+            _ => break,
+        }
+        release_memory();
+        if let Some(duration) = backoff {
+            std::thread::sleep(duration);
+            backoff = Some(duration.saturating_mul(2));
+        }
+        result = catch_oom_at(caller, AssertUnwindSafe(&mut f));
+    }
+    result
+}
@@ -0,0 +1,121 @@
+//! Allocation errors with the thread and time information.
+
+use std::error::Error;
+#[cfg(not(feature = "stable"))]
+use std::error::Request;
+use std::fmt;
+use std::panic::UnwindSafe;
+use std::thread::{self, ThreadId};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{catch_oom, AllocError};
+
+/// An allocation error recording the thread and the time of the failure.
+///
+/// This structure is created by [`catch_oom_rich`], with the information of the
+/// thread running the catching scope, where the allocation fails unless the error
+/// is propagated from another thread.
+#[derive(Clone, Debug)]
+pub struct RichAllocError {
+    error: AllocError,
+    thread_id: ThreadId,
+    thread_name: Option<String>,
+    timestamp: SystemTime,
+}
+
+impl RichAllocError {
+    /// Creates a new `RichAllocError` with the information of current thread and time.
+    #[must_use]
+    pub fn capture(error: AllocError) -> Self {
+        let thread = thread::current();
+        RichAllocError {
+            error,
+            thread_id: thread.id(),
+            thread_name: thread.name().map(ToOwned::to_owned),
+            timestamp: SystemTime::now(),
+        }
+    }
+
+    /// Returns the underlying allocation error.
+    #[must_use]
+    #[inline]
+    pub fn error(&self) -> &AllocError {
+        &self.error
+    }
+
+    /// Consumes the `RichAllocError`, returning the underlying allocation error.
+    #[must_use]
+    #[inline]
+    pub fn into_error(self) -> AllocError {
+        self.error
+    }
+
+    /// Returns the id of the thread where the allocation failed.
+    #[must_use]
+    #[inline]
+    pub fn thread_id(&self) -> ThreadId {
+        self.thread_id
+    }
+
+    /// Returns the name of the thread where the allocation failed, if it is named.
+    #[must_use]
+    #[inline]
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread_name.as_deref()
+    }
+
+    /// Returns the time when the allocation failed.
+    #[must_use]
+    #[inline]
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+}
+
+impl From<RichAllocError> for AllocError {
+    #[inline]
+    fn from(e: RichAllocError) -> Self {
+        e.error
+    }
+}
+
+impl fmt::Display for RichAllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in thread '{}' ",
+            self.error,
+            self.thread_name.as_deref().unwrap_or("<unnamed>")
+        )?;
+        write!(f, "({:?})", self.thread_id)?;
+        if let Ok(elapsed) = self.timestamp.duration_since(UNIX_EPOCH) {
+            write!(f, " at {}.{:06}", elapsed.as_secs(), elapsed.subsec_micros())?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RichAllocError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+
+    #[cfg(not(feature = "stable"))]
+    fn provide<'a>(&'a self, request: &mut Request<'a>) {
+        self.error.provide(request);
+        request
+            .provide_value::<ThreadId>(self.thread_id)
+            .provide_value::<SystemTime>(self.timestamp);
+    }
+}
+
+/// Invokes a closure, capturing the out-of-memory panic if one occurs, with the
+/// thread and time information recorded in the error.
+///
+/// This function behaves like [`catch_oom`] except for the error type.
+#[track_caller]
+#[inline]
+pub fn catch_oom_rich<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, RichAllocError> {
+    catch_oom(f).map_err(RichAllocError::capture)
+}
@@ -0,0 +1,94 @@
+//! The address space limit applied by `setrlimit`.
+
+use std::ffi::c_int;
+use std::io;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RLimit {
+    cur: u64,
+    max: u64,
+}
+
+extern "C" {
+    fn getrlimit(resource: c_int, rlim: *mut RLimit) -> c_int;
+    fn setrlimit(resource: c_int, rlim: *const RLimit) -> c_int;
+}
+
+#[cfg(all(target_os = "linux", not(target_arch = "mips64")))]
+const RESOURCES: [c_int; 2] = [9 /* RLIMIT_AS */, 2 /* RLIMIT_DATA */];
+#[cfg(all(target_os = "linux", target_arch = "mips64"))]
+const RESOURCES: [c_int; 2] = [6 /* RLIMIT_AS */, 2 /* RLIMIT_DATA */];
+#[cfg(target_os = "macos")]
+const RESOURCES: [c_int; 2] = [5 /* RLIMIT_AS */, 2 /* RLIMIT_DATA */];
+
+/// Restores the previous limits on drop.
+struct LimitGuard {
+    previous: Vec<(c_int, RLimit)>,
+}
+
+impl LimitGuard {
+    fn apply(bytes: usize) -> io::Result<Self> {
+        let mut guard = LimitGuard {
+            previous: Vec::with_capacity(RESOURCES.len()),
+        };
+        for resource in RESOURCES {
+            let mut previous = RLimit { cur: 0, max: 0 };
+            // SAFETY: `previous` is a valid `struct rlimit` to write to.
+            if unsafe { getrlimit(resource, &mut previous) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let limit = RLimit {
+                cur: bytes as u64,
+                max: previous.max,
+            };
+            // SAFETY: `limit` is a valid `struct rlimit`.
+            if unsafe { setrlimit(resource, &limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            guard.previous.push((resource, previous));
+        }
+        Ok(guard)
+    }
+}
+
+impl Drop for LimitGuard {
+    fn drop(&mut self) {
+        for (resource, previous) in self.previous.iter().rev() {
+            // SAFETY: `previous` is a valid `struct rlimit`. Raising the soft limit
+            // back to its previous value below the hard limit cannot fail.
+            unsafe { setrlimit(*resource, previous) };
+        }
+    }
+}
+
+/// Invokes a closure with the address space of the process limited to `bytes`,
+/// restoring the previous limits afterwards, even if the closure panics.
+///
+/// The soft limits `RLIMIT_AS` and `RLIMIT_DATA` are lowered by `setrlimit`, so
+/// the allocations exceeding the limit really fail, and are caught as
+/// `AllocError` by the catching scopes in the closure. This makes the allocation
+/// errors happen at a chosen budget, even with overcommitted memory. The limits
+/// apply to the whole process, including the memory already mapped, the stacks
+/// of the threads and the allocations of other threads meanwhile, so `bytes` must
+/// leave enough room for them.
+///
+/// Returns an error if the limits cannot be changed, e.g. if `bytes` exceeds the
+/// hard limit.
+///
+/// ```
+/// use panic_safe::{catch_oom, with_address_space_limit};
+/// # #[cfg(feature = "stable")]
+/// # #[global_allocator]
+/// # static GLOBAL: panic_safe::CatchAlloc = panic_safe::CatchAlloc::new(std::alloc::System);
+///
+/// # fn main() -> std::io::Result<()> {
+/// let result = with_address_space_limit(4 << 30, || catch_oom(|| vec![0u8; 8 << 30]))?;
+/// assert!(result.is_err());
+/// # Ok(())
+/// # }
+/// ```
+pub fn with_address_space_limit<F: FnOnce() -> R, R>(bytes: usize, f: F) -> io::Result<R> {
+    let _guard = LimitGuard::apply(bytes)?;
+    Ok(f())
+}
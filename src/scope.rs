@@ -0,0 +1,125 @@
+//! Error scopes carrying allocation errors with a logical unit of work.
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+
+use crate::AllocError;
+
+type Slot = Mutex<Option<AllocError>>;
+
+thread_local! {
+    static THREAD_ERROR_SCOPE: Cell<*const Slot> = const { Cell::new(ptr::null()) };
+}
+
+/// An error slot which travels with a logical unit of work rather than an OS thread.
+///
+/// The allocation error is recorded in the thread where the allocation fails, so
+/// it is lost if the panic is caught in another thread, e.g. when a task of a
+/// work-stealing executor is polled on one worker thread and joined on another.
+/// An allocation error occurring while an `ErrorScope` is entered is also recorded
+/// in the scope, and can be taken from any clone of the scope afterwards.
+///
+/// If the panic is caught by [`catch_oom`](crate::catch_oom) inside the scope, the
+/// error is returned by it and no longer kept in the scope.
+#[derive(Clone, Debug, Default)]
+pub struct ErrorScope {
+    slot: Arc<Slot>,
+}
+
+impl ErrorScope {
+    /// Creates a new `ErrorScope`.
+    #[must_use]
+    #[inline]
+    pub fn new() -> Self {
+        ErrorScope::default()
+    }
+
+    /// Invokes a closure with the scope entered in current thread.
+    ///
+    /// The previously entered scope is restored when the closure returns or panics.
+    /// The errors are recorded by the hooks, which are installed by [`init`](crate::init)
+    /// first, like the catching functions do.
+    #[inline]
+    pub fn enter<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        struct Restore(*const Slot);
+
+        impl Drop for Restore {
+            #[inline]
+            fn drop(&mut self) {
+                THREAD_ERROR_SCOPE.with(|scope| scope.set(self.0));
+            }
+        }
+
+        crate::init();
+        let _restore = Restore(THREAD_ERROR_SCOPE.with(|scope| scope.replace(Arc::as_ptr(&self.slot))));
+        f()
+    }
+
+    /// Wraps a future so that the scope is entered whenever the future is polled.
+    #[inline]
+    pub fn wrap<F: Future>(&self, future: F) -> WithErrorScope<F> {
+        WithErrorScope {
+            future,
+            scope: self.clone(),
+        }
+    }
+
+    /// Takes the allocation error recorded in the scope.
+    #[must_use]
+    #[inline]
+    pub fn take_error(&self) -> Option<AllocError> {
+        self.slot.lock().unwrap_or_else(PoisonError::into_inner).take()
+    }
+
+    /// Checks if an allocation error is recorded in the scope.
+    #[must_use]
+    #[inline]
+    pub fn has_error(&self) -> bool {
+        self.slot.lock().unwrap_or_else(PoisonError::into_inner).is_some()
+    }
+}
+
+/// Future for the [`ErrorScope::wrap`] method.
+#[must_use = "futures do nothing unless you `.await` or poll them"]
+#[derive(Debug)]
+pub struct WithErrorScope<F> {
+    future: F,
+    scope: ErrorScope,
+}
+
+impl<F: Future> Future for WithErrorScope<F> {
+    type Output = F::Output;
+
+    #[inline]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is pinned as a part of `self` and never moved.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        this.scope.enter(|| future.poll(cx))
+    }
+}
+
+/// Applies `f` to the slot of the scope entered in current thread, if any.
+#[inline]
+fn with_current<T>(f: impl FnOnce(&Slot) -> T) -> Option<T> {
+    let slot = THREAD_ERROR_SCOPE.with(|scope| scope.get());
+    // SAFETY: the pointer is set by `ErrorScope::enter`, which keeps the slot alive
+    // until the pointer is restored.
+    unsafe { slot.as_ref() }.map(f)
+}
+
+/// Records the allocation error in the scope entered in current thread.
+#[inline]
+pub(crate) fn record(e: &AllocError) {
+    with_current(|slot| *slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(e.clone()));
+}
+
+/// Clears the allocation error in the scope entered in current thread.
+#[inline]
+pub(crate) fn clear() {
+    with_current(|slot| *slot.lock().unwrap_or_else(PoisonError::into_inner) = None);
+}
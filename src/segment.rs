@@ -0,0 +1,195 @@
+//! Stack segments running closures on current thread.
+
+use std::ffi::{c_int, c_long, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[cfg(target_os = "linux")]
+const MAP_ANONYMOUS: c_int = 0x20;
+#[cfg(target_os = "macos")]
+const MAP_ANONYMOUS: c_int = 0x1000;
+#[cfg(target_os = "linux")]
+const SC_PAGESIZE: c_int = 30;
+#[cfg(target_os = "macos")]
+const SC_PAGESIZE: c_int = 29;
+const PROT_NONE: c_int = 0;
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_PRIVATE: c_int = 2;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn sysconf(name: c_int) -> c_long;
+    /// Switches to the stack ending at `top`, invokes `callback(data)` on it, and
+    /// switches back.
+    fn panic_safe_switch_stack(data: *mut u8, callback: unsafe extern "C" fn(*mut u8), top: *mut u8);
+}
+
+/// Defines `panic_safe_switch_stack`, which keeps the stack pointer of the caller
+/// in the frame pointer, so the frames on the segment unwind into the caller.
+macro_rules! switch_stack {
+    ($($body:literal,)*) => {
+        #[cfg(target_os = "linux")]
+        std::arch::global_asm!(
+            ".pushsection .text.panic_safe_switch_stack,\"ax\",%progbits",
+            ".globl panic_safe_switch_stack",
+            ".hidden panic_safe_switch_stack",
+            ".type panic_safe_switch_stack,%function",
+            ".p2align 4",
+            "panic_safe_switch_stack:",
+            ".cfi_startproc",
+            $($body,)*
+            ".cfi_endproc",
+            ".size panic_safe_switch_stack,.-panic_safe_switch_stack",
+            ".popsection",
+        );
+        #[cfg(target_os = "macos")]
+        std::arch::global_asm!(
+            ".globl _panic_safe_switch_stack",
+            ".private_extern _panic_safe_switch_stack",
+            ".p2align 4",
+            "_panic_safe_switch_stack:",
+            ".cfi_startproc",
+            $($body,)*
+            ".cfi_endproc",
+        );
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+switch_stack!(
+    "push rbp",
+    ".cfi_def_cfa_offset 16",
+    ".cfi_offset rbp, -16",
+    "mov rbp, rsp",
+    ".cfi_def_cfa_register rbp",
+    "mov rsp, rdx",
+    "call rsi",
+    "mov rsp, rbp",
+    "pop rbp",
+    ".cfi_def_cfa rsp, 8",
+    "ret",
+);
+
+#[cfg(target_arch = "aarch64")]
+switch_stack!(
+    "stp x29, x30, [sp, #-16]!",
+    ".cfi_def_cfa_offset 16",
+    ".cfi_offset x30, -8",
+    ".cfi_offset x29, -16",
+    "mov x29, sp",
+    ".cfi_def_cfa_register x29",
+    "mov sp, x2",
+    "blr x1",
+    "mov sp, x29",
+    ".cfi_def_cfa sp, 16",
+    "ldp x29, x30, [sp], #16",
+    ".cfi_def_cfa_offset 0",
+    ".cfi_restore x29",
+    ".cfi_restore x30",
+    "ret",
+);
+
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+fn page_size() -> usize {
+    match PAGE_SIZE.load(Ordering::Relaxed) {
+        0 => {
+            // SAFETY: `sysconf` has no preconditions.
+            let size = usize::try_from(unsafe { sysconf(SC_PAGESIZE) }).unwrap_or(4096);
+            PAGE_SIZE.store(size, Ordering::Relaxed);
+            size
+        }
+        size => size,
+    }
+}
+
+/// A stack segment mapped with a guard page below it, which is unmapped when
+/// dropped.
+pub(crate) struct Segment {
+    base: *mut c_void,
+    len: usize,
+    guard: usize,
+}
+
+/// The closure run on a segment, and its result.
+struct Call<F, R> {
+    f: Option<F>,
+    result: Option<thread::Result<R>>,
+}
+
+impl Segment {
+    /// Maps a segment of at least `size` bytes, returning `None` if it cannot be
+    /// mapped.
+    pub(crate) fn map(size: usize) -> Option<Segment> {
+        let guard = page_size();
+        let len = size.checked_add(guard - 1)? & !(guard - 1);
+        let len = len.checked_add(guard)?;
+        // SAFETY: an anonymous private mapping has no preconditions.
+        let base = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if base == MAP_FAILED {
+            return None;
+        }
+        let segment = Segment { base, len, guard };
+        // SAFETY: the lowest page is in the mapping, and faults on overflowing the
+        // segment rather than writing below it.
+        if unsafe { mprotect(base, guard, PROT_NONE) } != 0 {
+            return None;
+        }
+        Some(segment)
+    }
+
+    /// Returns the lowest address of the segment usable as stack.
+    pub(crate) fn limit(&self) -> usize {
+        self.base as usize + self.guard
+    }
+
+    /// Invokes a closure on the segment, resuming its panic, if any, on the
+    /// current stack.
+    pub(crate) fn run<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        let mut call = Call {
+            f: Some(f),
+            result: None,
+        };
+        let top = self.base.cast::<u8>().wrapping_add(self.len);
+        // SAFETY: `top` is the page-aligned end of the segment, which is mapped
+        // until `self` is dropped, and `call` outlives the call on the segment.
+        unsafe { panic_safe_switch_stack(ptr::addr_of_mut!(call).cast(), run_call::<F, R>, top) };
+        match call.result {
+            Some(Ok(result)) => result,
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => unreachable!("the closure did not run on the segment"),
+        }
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        // SAFETY: the segment is mapped by `map`, and no longer used as stack.
+        unsafe { munmap(self.base, self.len) };
+    }
+}
+
+/// Invokes the closure of the `Call` at `data`, catching the unwinding, which
+/// cannot cross the switch of stacks.
+unsafe extern "C" fn run_call<F: FnOnce() -> R, R>(data: *mut u8) {
+    // SAFETY: `data` points to the `Call` of `Segment::run`.
+    let call = unsafe { &mut *data.cast::<Call<F, R>>() };
+    if let Some(f) = call.f.take() {
+        call.result = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+    }
+}
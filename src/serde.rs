@@ -0,0 +1,227 @@
+//! Serialization of the error types.
+//!
+//! Allocation errors are serialized with the size and the alignment of the layout,
+//! the kind, the context, the catching location, the backtrace text if captured,
+//! and the thread information if recorded. Panic errors are serialized with the
+//! message, the kind and the location. Only the information which can be
+//! restored is deserialized, i.e., the layout and the kind of `AllocError`, and
+//! the message and location of `PanicError`. `FailureTrace` is serialized with
+//! the index, the size and the alignment, and is fully restored.
+
+use std::alloc::Layout;
+use std::thread::ThreadId;
+
+use ::serde::de::{self, Deserialize, Deserializer};
+use ::serde::ser::{Serialize, SerializeStruct, Serializer};
+
+use crate::{AllocError, AllocErrorKind, CaughtError, FailureTrace, PanicError, PanicLocation, RichAllocError};
+
+#[derive(::serde::Deserialize)]
+#[serde(crate = "::serde", rename = "AllocError")]
+struct AllocErrorRepr {
+    size: usize,
+    align: usize,
+    #[serde(default)]
+    kind: AllocErrorKindRepr,
+}
+
+#[derive(Copy, Clone, Default, ::serde::Serialize, ::serde::Deserialize)]
+#[serde(crate = "::serde", rename = "AllocErrorKind")]
+enum AllocErrorKindRepr {
+    #[default]
+    Exhausted,
+    CapacityOverflow,
+    InvalidLayout,
+    UnexpectedPanic,
+}
+
+impl From<AllocErrorKind> for AllocErrorKindRepr {
+    #[inline]
+    fn from(kind: AllocErrorKind) -> Self {
+        match kind {
+            AllocErrorKind::Exhausted => AllocErrorKindRepr::Exhausted,
+            AllocErrorKind::CapacityOverflow => AllocErrorKindRepr::CapacityOverflow,
+            AllocErrorKind::InvalidLayout => AllocErrorKindRepr::InvalidLayout,
+            AllocErrorKind::UnexpectedPanic => AllocErrorKindRepr::UnexpectedPanic,
+        }
+    }
+}
+
+impl From<AllocErrorKindRepr> for AllocErrorKind {
+    #[inline]
+    fn from(kind: AllocErrorKindRepr) -> Self {
+        match kind {
+            AllocErrorKindRepr::Exhausted => AllocErrorKind::Exhausted,
+            AllocErrorKindRepr::CapacityOverflow => AllocErrorKind::CapacityOverflow,
+            AllocErrorKindRepr::InvalidLayout => AllocErrorKind::InvalidLayout,
+            AllocErrorKindRepr::UnexpectedPanic => AllocErrorKind::UnexpectedPanic,
+        }
+    }
+}
+
+impl Serialize for AllocErrorKind {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AllocErrorKindRepr::from(*self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AllocErrorKind {
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        AllocErrorKindRepr::deserialize(deserializer).map(AllocErrorKind::from)
+    }
+}
+
+impl Serialize for AllocError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AllocError", 6)?;
+        state.serialize_field("size", &self.size())?;
+        state.serialize_field("align", &self.align())?;
+        state.serialize_field("kind", &self.kind())?;
+        state.serialize_field("context", &self.context())?;
+        state.serialize_field("location", &self.location().map(ToString::to_string))?;
+        #[cfg(feature = "backtrace")]
+        state.serialize_field("backtrace", &self.backtrace().map(ToString::to_string))?;
+        #[cfg(not(feature = "backtrace"))]
+        state.skip_field("backtrace")?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for AllocError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = AllocErrorRepr::deserialize(deserializer)?;
+        let layout = Layout::from_size_align(repr.size, repr.align).map_err(de::Error::custom)?;
+        Ok(AllocError::with_kind(layout, repr.kind.into()))
+    }
+}
+
+/// Serializes a thread id by its debug representation, as it has no stable numeric value.
+struct ThreadIdRepr(ThreadId);
+
+impl Serialize for ThreadIdRepr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", self.0))
+    }
+}
+
+impl Serialize for RichAllocError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RichAllocError", 4)?;
+        state.serialize_field("error", self.error())?;
+        state.serialize_field("thread_id", &ThreadIdRepr(self.thread_id()))?;
+        state.serialize_field("thread_name", &self.thread_name())?;
+        state.serialize_field("timestamp", &self.timestamp())?;
+        state.end()
+    }
+}
+
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(crate = "::serde", rename = "PanicLocation")]
+struct PanicLocationRepr<'a> {
+    #[serde(borrow)]
+    file: std::borrow::Cow<'a, str>,
+    line: u32,
+    column: u32,
+}
+
+impl Serialize for PanicLocation {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PanicLocationRepr {
+            file: self.file().into(),
+            line: self.line(),
+            column: self.column(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PanicLocation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = PanicLocationRepr::deserialize(deserializer)?;
+        Ok(PanicLocation::new(repr.file.into_owned(), repr.line, repr.column))
+    }
+}
+
+#[derive(::serde::Deserialize)]
+#[serde(crate = "::serde", rename = "PanicError")]
+struct PanicErrorRepr {
+    message: Option<String>,
+    location: Option<PanicLocation>,
+}
+
+impl Serialize for PanicError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("PanicError", 3)?;
+        state.serialize_field("message", &self.message())?;
+        state.serialize_field("kind", self.kind().as_str())?;
+        state.serialize_field("location", &self.location())?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for PanicError {
+    /// Deserializes a `PanicError`, whose payload is the message as a `String`, or
+    /// `()` if there is no message.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = PanicErrorRepr::deserialize(deserializer)?;
+        let payload: Box<dyn std::any::Any + Send> = match repr.message {
+            Some(message) => Box::new(message),
+            None => Box::new(()),
+        };
+        Ok(PanicError::new(repr.location, payload))
+    }
+}
+
+#[derive(::serde::Deserialize)]
+#[serde(crate = "::serde", rename = "CaughtError")]
+enum CaughtErrorRepr {
+    Oom(AllocError),
+    Panic(PanicError),
+}
+
+impl Serialize for CaughtError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            CaughtError::Oom(e) => serializer.serialize_newtype_variant("CaughtError", 0, "Oom", e),
+            CaughtError::Panic(e) => serializer.serialize_newtype_variant("CaughtError", 1, "Panic", e),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CaughtError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match CaughtErrorRepr::deserialize(deserializer)? {
+            CaughtErrorRepr::Oom(e) => CaughtError::Oom(e),
+            CaughtErrorRepr::Panic(e) => CaughtError::Panic(e),
+        })
+    }
+}
+
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(crate = "::serde", rename = "FailureTrace")]
+struct FailureTraceRepr {
+    index: usize,
+    size: usize,
+    align: usize,
+}
+
+impl Serialize for FailureTrace {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FailureTraceRepr {
+            index: self.index(),
+            size: self.layout().size(),
+            align: self.layout().align(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FailureTrace {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = FailureTraceRepr::deserialize(deserializer)?;
+        let layout = Layout::from_size_align(repr.size, repr.align).map_err(de::Error::custom)?;
+        Ok(FailureTrace::new(repr.index, layout))
+    }
+}
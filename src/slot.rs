@@ -0,0 +1,149 @@
+//! The thread error slot, where the allocation error is recorded before panicking.
+//!
+//! With the `std` feature the slot is backed by `std::thread_local!`. Without it,
+//! the slot is backed by a `#[thread_local]` static with the `thread-local`
+//! feature, or by a global cell guarded by the user-provided `critical-section`
+//! implementation with the `critical-section` feature.
+
+use core::alloc::Layout;
+#[cfg(any(feature = "std", feature = "thread-local", feature = "critical-section"))]
+use core::cell::Cell;
+
+use crate::AllocError;
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    static THREAD_ALLOC_ERROR: Cell<Option<AllocError>> = const { Cell::new(None) };
+}
+
+#[cfg(feature = "std")]
+#[inline]
+fn with_slot<T>(f: impl FnOnce(&Cell<Option<AllocError>>) -> T) -> T {
+    THREAD_ALLOC_ERROR.with(f)
+}
+
+#[cfg(all(not(feature = "std"), feature = "thread-local"))]
+#[thread_local]
+static THREAD_ALLOC_ERROR: Cell<Option<AllocError>> = Cell::new(None);
+
+#[cfg(all(not(feature = "std"), feature = "thread-local"))]
+#[inline]
+fn with_slot<T>(f: impl FnOnce(&Cell<Option<AllocError>>) -> T) -> T {
+    f(&THREAD_ALLOC_ERROR)
+}
+
+#[cfg(all(not(feature = "std"), not(feature = "thread-local"), feature = "critical-section"))]
+static ALLOC_ERROR: critical_section::Mutex<Cell<Option<AllocError>>> = critical_section::Mutex::new(Cell::new(None));
+
+#[cfg(all(not(feature = "std"), not(feature = "thread-local"), feature = "critical-section"))]
+#[inline]
+fn with_slot<T>(f: impl FnOnce(&Cell<Option<AllocError>>) -> T) -> T {
+    critical_section::with(|cs| f(ALLOC_ERROR.borrow(cs)))
+}
+
+#[cfg(not(any(feature = "std", feature = "thread-local", feature = "critical-section")))]
+compile_error!("one of the `std`, `thread-local` and `critical-section` features must be enabled");
+
+pub(crate) struct ThreadAllocError;
+
+impl ThreadAllocError {
+    /// Injects alloc error to current thread.
+    ///
+    /// An error left by a panic escaping an error scope, which is taken from the
+    /// scope in another thread, is overwritten.
+    #[inline]
+    pub(crate) fn inject(e: AllocError) {
+        #[cfg(feature = "std")]
+        crate::scope::record(&e);
+        with_slot(|error| {
+            error.set(Some(e));
+        })
+    }
+
+    /// Returns the layout of the alloc error in current thread, if any.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn layout() -> Option<Layout> {
+        with_slot(|error| {
+            let e = error.take();
+            let layout = e.as_ref().map(AllocError::layout);
+            error.set(e);
+            layout
+        })
+    }
+
+    /// Takes alloc error from current thread
+    ///
+    /// The error is taken from the entered error scope as well.
+    #[inline]
+    pub(crate) fn take() -> Option<AllocError> {
+        #[cfg(feature = "std")]
+        crate::scope::clear();
+        with_slot(|error| error.take())
+    }
+
+    /// Records the failure of an allocation of `layout` as suppressed by the alloc
+    /// error in current thread, if any.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn suppress(layout: Layout) {
+        with_slot(|error| {
+            let mut e = error.take();
+            if let Some(e) = &mut e {
+                e.suppress(layout);
+            }
+            error.set(e);
+        })
+    }
+
+    /// Clears alloc error in current thread
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn clear() {
+        with_slot(|error| error.set(None));
+    }
+
+    /// Enters a frame of the slot for a catching scope, returning a guard which
+    /// leaves it on drop.
+    ///
+    /// The error of the outer scope, e.g. one which is unwinding while the inner
+    /// scope runs in a destructor, is saved in the guard, and the slot is cleared
+    /// for the inner scope. The saved error is restored on drop, discarding the
+    /// error the inner scope has not taken.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub(crate) fn enter_frame() -> SlotFrame {
+        SlotFrame(with_slot(|error| error.take()))
+    }
+}
+
+/// A frame of the thread error slot, created by [`ThreadAllocError::enter_frame`].
+#[cfg(feature = "std")]
+pub(crate) struct SlotFrame(Option<AllocError>);
+
+#[cfg(feature = "std")]
+impl Drop for SlotFrame {
+    #[inline]
+    fn drop(&mut self) {
+        let saved = self.0.take();
+        with_slot(|error| error.set(saved));
+    }
+}
+
+/// Records the allocation error of `layout` in the error slot and panics.
+///
+/// This is meant to be called by the allocation error handler in `no_std`
+/// environments, where the panic is caught by the user's unwinding runtime, and
+/// the error is then taken by [`take_alloc_error`].
+#[cfg(not(feature = "std"))]
+pub fn raise_alloc_error(layout: Layout) -> ! {
+    ThreadAllocError::inject(AllocError::new(layout));
+    panic!("memory allocation of {} bytes failed", layout.size());
+}
+
+/// Takes the allocation error recorded by [`raise_alloc_error`] from the error slot.
+#[cfg(not(feature = "std"))]
+#[inline]
+pub fn take_alloc_error() -> Option<AllocError> {
+    ThreadAllocError::take()
+}
@@ -0,0 +1,70 @@
+//! The mapping of allocation errors to database error codes.
+
+use core::sync::atomic::{AtomicI32, Ordering};
+
+use crate::{AllocError, AllocErrorKind};
+
+static VENDOR_CODE: AtomicI32 = AtomicI32::new(0);
+
+/// Sets the vendor-specific error code returned by [`AllocError::vendor_code`]
+/// for all allocation errors, e.g. `1041` (`ER_OUT_OF_RESOURCES`) for a server
+/// speaking the MySQL protocol.
+///
+/// The vendor code is unset by default, or if `code` is 0.
+#[inline]
+pub fn set_vendor_code(code: i32) {
+    VENDOR_CODE.store(code, Ordering::Relaxed);
+}
+
+impl AllocErrorKind {
+    /// Returns the SQLSTATE of the kind of allocation failure:
+    ///
+    /// - `53200` (`out_of_memory`) for [`AllocErrorKind::Exhausted`].
+    /// - `54000` (`program_limit_exceeded`) for the impossible allocations, which
+    ///   are requested by a size too large to allocate.
+    /// - `XX000` (`internal_error`) for [`AllocErrorKind::UnexpectedPanic`].
+    #[must_use]
+    #[inline]
+    pub const fn sqlstate(self) -> &'static str {
+        match self {
+            AllocErrorKind::Exhausted => "53200",
+            AllocErrorKind::CapacityOverflow | AllocErrorKind::InvalidLayout => "54000",
+            AllocErrorKind::UnexpectedPanic => "XX000",
+        }
+    }
+}
+
+impl AllocError {
+    /// Returns the SQLSTATE of the allocation error, e.g. `53200`
+    /// (`out_of_memory`) for exhausted memory, to report it by database extensions
+    /// and wire-protocol servers.
+    ///
+    /// See [`AllocErrorKind::sqlstate`] for the codes of the kinds.
+    ///
+    /// ```
+    /// # struct Conn;
+    /// # fn execute(plan: &str) -> Vec<String> { vec![plan.to_owned()] }
+    /// # fn send_rows(_: &mut Conn, _: Vec<String>) {}
+    /// # fn send_error(_: &mut Conn, _sqlstate: &str, _vendor_code: i32, _message: &str) {}
+    /// # let (conn, plan) = (&mut Conn, "plan");
+    /// match panic_safe::catch_oom(|| execute(&plan)) {
+    ///     Ok(rows) => send_rows(conn, rows),
+    ///     Err(e) => send_error(conn, e.sqlstate(), e.vendor_code().unwrap_or(0), &e.to_string()),
+    /// }
+    /// ```
+    #[must_use]
+    #[inline]
+    pub const fn sqlstate(&self) -> &'static str {
+        self.kind().sqlstate()
+    }
+
+    /// Returns the vendor-specific error code set by [`set_vendor_code`], if any.
+    #[must_use]
+    #[inline]
+    pub fn vendor_code(&self) -> Option<i32> {
+        match VENDOR_CODE.load(Ordering::Relaxed) {
+            0 => None,
+            code => Some(code),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Growing the stack for deep recursion in catching scopes.
+
+use std::alloc::Layout;
+use std::cell::Cell;
+use std::panic::{self, Location, UnwindSafe};
+use std::thread;
+
+use crate::budget::Attached;
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+thread_local! {
+    static THREAD_STACK_LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Returns the lowest address of the stack of current thread.
+#[cfg(target_os = "linux")]
+fn stack_limit() -> Option<usize> {
+    use std::ffi::{c_int, c_void};
+
+    #[repr(C, align(8))]
+    struct PthreadAttr([u8; 64]);
+
+    extern "C" {
+        fn pthread_self() -> usize;
+        fn pthread_getattr_np(thread: usize, attr: *mut PthreadAttr) -> c_int;
+        fn pthread_attr_getstack(attr: *const PthreadAttr, addr: *mut *mut c_void, size: *mut usize) -> c_int;
+        fn pthread_attr_destroy(attr: *mut PthreadAttr) -> c_int;
+    }
+
+    let mut attr = PthreadAttr([0; 64]);
+    let mut addr = std::ptr::null_mut();
+    let mut size = 0;
+    // SAFETY: `attr` is large enough for `pthread_attr_t`, and is destroyed after
+    // being initialized by `pthread_getattr_np`.
+    unsafe {
+        if pthread_getattr_np(pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let ret = pthread_attr_getstack(&attr, &mut addr, &mut size);
+        pthread_attr_destroy(&mut attr);
+        (ret == 0).then_some(addr as usize)
+    }
+}
+
+/// Returns the lowest address of the stack of current thread.
+#[cfg(target_os = "macos")]
+fn stack_limit() -> Option<usize> {
+    use std::ffi::c_void;
+
+    extern "C" {
+        fn pthread_self() -> usize;
+        fn pthread_get_stackaddr_np(thread: usize) -> *mut c_void;
+        fn pthread_get_stacksize_np(thread: usize) -> usize;
+    }
+
+    // SAFETY: querying the stack of current thread has no preconditions.
+    unsafe {
+        let thread = pthread_self();
+        // The address is the top of the stack, which grows downwards.
+        (pthread_get_stackaddr_np(thread) as usize).checked_sub(pthread_get_stacksize_np(thread))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn stack_limit() -> Option<usize> {
+    None
+}
+
+/// Returns the bytes of stack remaining in current thread, or `None` if unknown.
+///
+/// The stack is known on Linux and macOS. The bounds of the stack are queried
+/// once per thread.
+#[must_use]
+#[inline(never)]
+pub fn remaining_stack() -> Option<usize> {
+    let limit = match THREAD_STACK_LIMIT.with(Cell::get) {
+        Some(limit) => limit,
+        None => {
+            let limit = stack_limit()?;
+            THREAD_STACK_LIMIT.with(|l| l.set(Some(limit)));
+            limit
+        }
+    };
+    let marker = 0u8;
+    (std::ptr::addr_of!(marker) as usize).checked_sub(limit)
+}
+
+/// Returns the error of failing to grow the stack by `stack_size` bytes.
+fn grow_error(caller: &'static Location<'static>, stack_size: usize) -> AllocError {
+    let layout = Layout::from_size_align(stack_size, 1).unwrap_or(Layout::new::<()>());
+    AllocError::new(layout)
+        .with_context("failed to grow the stack")
+        .caught_at(caller)
+}
+
+/// Invokes a closure under [`catch_oom`](crate::catch_oom) on a new segment of
+/// `stack_size` bytes of the stack of current thread.
+///
+/// Returns an `AllocError` if the segment cannot be mapped. A panic other than
+/// allocation error, which is not aborted by the global
+/// [`catch_mode`](crate::catch_mode), is propagated to the caller.
+#[cfg(all(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+fn catch_oom_on_segment<F, R>(caller: &'static Location<'static>, stack_size: usize, f: F) -> Result<R, AllocError>
+where
+    F: FnOnce() -> R + UnwindSafe,
+{
+    /// Restores the stack limit of current thread on leaving the segment.
+    struct RestoreLimit(Option<usize>);
+
+    impl Drop for RestoreLimit {
+        fn drop(&mut self) {
+            THREAD_STACK_LIMIT.with(|limit| limit.set(self.0));
+        }
+    }
+
+    let Some(segment) = crate::segment::Segment::map(stack_size) else {
+        return Err(grow_error(caller, stack_size));
+    };
+    let _restore = RestoreLimit(THREAD_STACK_LIMIT.with(|limit| limit.replace(Some(segment.limit()))));
+    segment.run(|| catch_oom_at(caller, f))
+}
+
+#[cfg(not(all(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    any(target_os = "linux", target_os = "macos")
+)))]
+fn catch_oom_on_segment<F, R>(caller: &'static Location<'static>, _: usize, f: F) -> Result<R, AllocError>
+where
+    F: FnOnce() -> R + UnwindSafe,
+{
+    catch_oom_at(caller, f)
+}
+
+/// Invokes a closure on a new thread with a stack of `stack_size` bytes under
+/// [`catch_oom`](crate::catch_oom), joining it.
+///
+/// Returns an `AllocError` if the thread cannot be created, e.g. as its stack
+/// cannot be allocated. A panic other than allocation error, which is not aborted
+/// by the global [`catch_mode`](crate::catch_mode), is propagated to the caller.
+///
+/// The new thread is attached to the memory budget and the tag of current thread,
+/// and may allocate the bytes remaining in the allocation limit of current thread.
+fn catch_oom_on_new_stack<F, R>(caller: &'static Location<'static>, stack_size: usize, f: F) -> Result<R, AllocError>
+where
+    F: FnOnce() -> R + UnwindSafe + Send,
+    R: Send,
+{
+    let budget = Attached::current();
+    let tag = crate::tag::current();
+    let limit = crate::thread_alloc_limit().map(|limit| limit.saturating_sub(crate::thread_current_usage()));
+    thread::scope(|scope| {
+        let spawned = thread::Builder::new()
+            .stack_size(stack_size)
+            .spawn_scoped(scope, move || {
+                // SAFETY: the scoped thread exits before this function returns, where
+                // the budget is still attached to current thread.
+                unsafe { budget.attach() };
+                crate::tag::set_current(tag);
+                crate::set_thread_alloc_limit(limit);
+                catch_oom_at(caller, f)
+            });
+        match spawned {
+            Ok(handle) => handle.join().unwrap_or_else(|payload| panic::resume_unwind(payload)),
+            Err(_) => Err(grow_error(caller, stack_size)),
+        }
+    })
+}
+
+/// Invokes a closure like [`catch_oom`](crate::catch_oom), running it on a new
+/// stack of `grow_by` bytes if less than `red_zone` bytes of stack remain.
+///
+/// This lets deeply recursive code, e.g. a parser, protect itself by calling this
+/// function at each level of recursion: the stack grows by a new segment only
+/// when it is about to be exhausted. The new segment is mapped for the call and
+/// switched to on current thread, so the closure keeps all the thread-local
+/// state, e.g. the [`MemoryBudget`](crate::MemoryBudget) and the handler of an
+/// enclosing [`catch_oom_with_hook`](crate::catch_oom_with_hook). A panic which
+/// is not caught unwinds back to the current stack. If the segment cannot be
+/// mapped, an `AllocError` with the context `"failed to grow the stack"` is
+/// returned instead of overflowing the stack.
+///
+/// Only Linux and macOS on x86-64 and AArch64 are supported. Elsewhere, or where
+/// the remaining stack is unknown, see [`remaining_stack`], the closure always
+/// runs on the current stack.
+///
+/// ```
+/// use panic_safe::catch_oom_with_stack;
+/// # struct Node { children: Vec<Node> }
+///
+/// fn depth(node: &Node) -> Result<usize, panic_safe::AllocError> {
+///     catch_oom_with_stack(64 * 1024, 1024 * 1024, || {
+///         node.children.iter().map(depth).try_fold(0, |max, d| d.map(|d| max.max(d + 1)))
+///     })?
+/// }
+/// # let tree = Node { children: vec![Node { children: Vec::new() }] };
+/// # assert_eq!(depth(&tree).unwrap(), 1);
+/// ```
+#[track_caller]
+pub fn catch_oom_with_stack<F, R>(red_zone: usize, grow_by: usize, f: F) -> Result<R, AllocError>
+where
+    F: FnOnce() -> R + UnwindSafe,
+{
+    let caller = Location::caller();
+    if remaining_stack().map_or(true, |remaining| remaining >= red_zone) {
+        catch_oom_at(caller, f)
+    } else {
+        catch_oom_on_segment(caller, grow_by, f)
+    }
+}
+
+/// Invokes a closure like [`catch_oom`](crate::catch_oom) on a new thread with a
+/// stack of `stack_size` bytes, joining it.
+///
+/// This gives deeply recursive code a stack large enough for it, without
+/// consuming the stack of the caller. Returns an `AllocError` with the context
+/// `"failed to grow the stack"` if the thread cannot be created, e.g. as its stack
+/// cannot be allocated. A panic other than allocation error is propagated to the
+/// caller, unless aborted by the global [`catch_mode`](crate::catch_mode).
+///
+/// The closure keeps the [`MemoryBudget`](crate::MemoryBudget) and the
+/// [tag](crate::catch_oom_tagged) of current thread, and may allocate the bytes
+/// remaining in the [allocation limit](crate::set_thread_alloc_limit) of current
+/// thread. Other thread-local state is not carried, e.g. the handler of an
+/// enclosing [`catch_oom_with_hook`](crate::catch_oom_with_hook), which may not
+/// be shared with another thread, or the failures injected by a
+/// [`FaultInjector`](crate::FaultInjector). Use [`catch_oom_with_stack`] to keep
+/// the closure on current thread.
+///
+/// ```
+/// use panic_safe::spawn_with_stack;
+///
+/// # fn parse(input: &str) -> Vec<&str> { input.split(' ').collect() }
+/// # fn main() -> Result<(), panic_safe::AllocError> {
+/// # let input = String::from("a b");
+/// let tree = spawn_with_stack(256 << 20, || parse(&input))?;
+/// # Ok(())
+/// # }
+/// ```
+#[track_caller]
+pub fn spawn_with_stack<F, R>(stack_size: usize, f: F) -> Result<R, AllocError>
+where
+    F: FnOnce() -> R + UnwindSafe + Send,
+    R: Send,
+{
+    catch_oom_on_new_stack(Location::caller(), stack_size, f)
+}
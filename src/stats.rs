@@ -0,0 +1,33 @@
+//! Process-wide statistics of allocation errors.
+
+use std::alloc::Layout;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static OOM_COUNT: AtomicUsize = AtomicUsize::new(0);
+static LAST_OOM_SIZE: AtomicUsize = AtomicUsize::new(0);
+static LAST_OOM_ALIGN: AtomicUsize = AtomicUsize::new(0);
+
+/// Records an allocation error by the layout.
+#[inline]
+pub(crate) fn record_oom(layout: Layout) {
+    LAST_OOM_SIZE.store(layout.size(), Ordering::Relaxed);
+    LAST_OOM_ALIGN.store(layout.align(), Ordering::Relaxed);
+    OOM_COUNT.fetch_add(1, Ordering::Release);
+}
+
+/// Returns the number of allocation errors so far, and the size and alignment of
+/// the last one.
+///
+/// The size and alignment are updated separately, so they may be torn if
+/// allocation errors occur concurrently.
+#[inline]
+pub(crate) fn oom_stats() -> (usize, Option<(usize, usize)>) {
+    let count = OOM_COUNT.load(Ordering::Acquire);
+    let last = (count > 0).then(|| {
+        (
+            LAST_OOM_SIZE.load(Ordering::Relaxed),
+            LAST_OOM_ALIGN.load(Ordering::Relaxed),
+        )
+    });
+    (count, last)
+}
@@ -0,0 +1,96 @@
+//! Attributing allocated bytes to tagged scopes.
+
+use std::cell::Cell;
+use std::panic::{Location, UnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+/// Maximum number of distinct tags, the bytes of more tags are not attributed.
+const MAX_TAGS: usize = 256;
+
+/// The registered tags, indexed the same as `TAG_BYTES`.
+static TAGS: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+static TAG_BYTES: [AtomicUsize; MAX_TAGS] = [const { AtomicUsize::new(0) }; MAX_TAGS];
+
+thread_local! {
+    /// The index of the innermost tag of current thread plus 1, or 0 if none.
+    static THREAD_TAG: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns the index of `tag` plus 1, registering it if new, or 0 if there are
+/// too many tags.
+fn register(tag: &'static str) -> usize {
+    let mut tags = TAGS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(index) = tags.iter().position(|&t| t == tag) {
+        return index + 1;
+    }
+    if tags.len() == MAX_TAGS {
+        return 0;
+    }
+    tags.push(tag);
+    tags.len()
+}
+
+/// Invokes a closure like [`catch_oom`](crate::catch_oom), attributing the bytes
+/// allocated by current thread in the closure to `tag`.
+///
+/// Bytes are attributed to the innermost tag, so the bytes allocated in a nested
+/// tagged scope are attributed to the nested tag only. The attributed bytes are
+/// queried by [`tagged_usage`]. Up to 256 distinct tags are attributed.
+///
+/// The bytes are counted by [`TrackingAlloc`](crate::TrackingAlloc), which must be
+/// registered as the global allocator, otherwise nothing is attributed.
+#[track_caller]
+pub fn catch_oom_tagged<F: FnOnce() -> R + UnwindSafe, R>(tag: &'static str, f: F) -> Result<R, AllocError> {
+    struct Restore(usize);
+
+    impl Drop for Restore {
+        #[inline]
+        fn drop(&mut self) {
+            THREAD_TAG.with(|t| t.set(self.0));
+        }
+    }
+
+    let caller = Location::caller();
+    let index = register(tag);
+    let _restore = Restore(THREAD_TAG.with(|t| t.replace(index)));
+    catch_oom_at(caller, f)
+}
+
+/// Returns the total bytes allocated in the scopes of each tag, in the order the
+/// tags are first used.
+///
+/// The bytes are the total bytes allocated, including the bytes deallocated
+/// afterwards, as a deallocation may happen outside the scope.
+#[must_use]
+pub fn tagged_usage() -> Vec<(&'static str, usize)> {
+    let tags = TAGS.lock().unwrap_or_else(PoisonError::into_inner);
+    tags.iter()
+        .zip(&TAG_BYTES)
+        .map(|(&tag, bytes)| (tag, bytes.load(Ordering::Relaxed)))
+        .collect()
+}
+
+/// Returns the innermost tag of current thread, as an index of `TAGS` plus 1.
+#[inline]
+pub(crate) fn current() -> usize {
+    THREAD_TAG.with(Cell::get)
+}
+
+/// Sets the innermost tag of current thread, e.g. to carry it to another thread.
+#[inline]
+pub(crate) fn set_current(index: usize) {
+    THREAD_TAG.with(|t| t.set(index));
+}
+
+/// Attributes `size` bytes allocated by current thread to its innermost tag.
+#[inline]
+pub(crate) fn attribute(size: usize) {
+    let index = THREAD_TAG.with(Cell::get);
+    if index != 0 {
+        TAG_BYTES[index - 1].fetch_add(size, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,115 @@
+//! Spawning threads running under the catcher.
+
+use std::panic::{self, Location, UnwindSafe};
+use std::thread::{self, JoinHandle, Thread};
+
+use crate::catch::catch_oom_at;
+use crate::hook::ThreadPanic;
+use crate::{init, AllocError};
+
+/// Spawns a new thread running the closure under [`catch_oom`](crate::catch_oom), returning a
+/// [`JoinHandle`] for it.
+///
+/// This function mirrors [`std::thread::spawn`], except that joining the thread
+/// yields `Ok(Err(AllocError))` if allocation error occurs in the closure.
+///
+/// # Panics
+///
+/// Panics if the OS fails to create a thread, as [`std::thread::spawn`] does.
+#[track_caller]
+#[inline]
+pub fn spawn<F, R>(f: F) -> JoinHandle<Result<R, AllocError>>
+where
+    F: FnOnce() -> R + UnwindSafe + Send + 'static,
+    R: Send + 'static,
+{
+    let caller = Location::caller();
+    thread::spawn(move || catch_oom_at(caller, f))
+}
+
+/// Runs each closure on its own scoped thread under [`catch_oom`](crate::catch_oom), returning the
+/// results in the order of the closures.
+///
+/// As the threads are spawned by [`std::thread::scope`], the closures may borrow
+/// non-`'static` data. All threads are joined before this function returns. If a
+/// thread panics with a panic other than allocation error, which is not aborted
+/// by the global [`catch_mode`](crate::catch_mode), the panic is propagated to the
+/// caller after all threads are joined.
+///
+/// # Panics
+///
+/// Panics if the OS fails to create a thread, as [`std::thread::scope`] does.
+#[track_caller]
+pub fn catch_oom_scoped<'env, I, F, R>(jobs: I) -> Vec<Result<R, AllocError>>
+where
+    I: IntoIterator<Item = F>,
+    F: FnOnce() -> R + UnwindSafe + Send + 'env,
+    R: Send + 'env,
+{
+    let caller = Location::caller();
+    thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .map(|f| scope.spawn(move || catch_oom_at(caller, f)))
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|handle| handle.join()).collect();
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|payload| panic::resume_unwind(payload)))
+            .collect()
+    })
+}
+
+/// Spawns a new thread whose out-of-memory panic is propagated to the joining
+/// thread, returning a [`PropagatingJoinHandle`] for it.
+///
+/// An allocation error in a thread spawned by [`std::thread::spawn`] is recorded
+/// in the thread itself, so it is invisible to the enclosing [`catch_oom`](crate::catch_oom) scope.
+/// The out-of-memory panic of a thread spawned by this function carries the
+/// allocation error instead, and [`PropagatingJoinHandle::join`] resumes the panic
+/// in the joining thread, where it is captured by the enclosing scope.
+///
+/// # Panics
+///
+/// Panics if the OS fails to create a thread, as [`std::thread::spawn`] does.
+#[inline]
+pub fn spawn_propagating<F, R>(f: F) -> PropagatingJoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    init();
+    PropagatingJoinHandle(thread::spawn(move || {
+        ThreadPanic::set_payload_transport(true);
+        f()
+    }))
+}
+
+/// A handle to join a thread spawned by [`spawn_propagating`].
+#[derive(Debug)]
+pub struct PropagatingJoinHandle<R>(JoinHandle<R>);
+
+impl<R> PropagatingJoinHandle<R> {
+    /// Waits for the thread to finish, returning its result.
+    ///
+    /// If the thread panics, the panic is resumed in current thread, so an
+    /// out-of-memory panic is captured by the enclosing [`catch_oom`](crate::catch_oom) scope.
+    #[inline]
+    pub fn join(self) -> R {
+        self.0.join().unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    /// Returns a handle of the underlying thread.
+    #[must_use]
+    #[inline]
+    pub fn thread(&self) -> &Thread {
+        self.0.thread()
+    }
+
+    /// Checks if the thread has finished running.
+    #[must_use]
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.0.is_finished()
+    }
+}
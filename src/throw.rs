@@ -0,0 +1,88 @@
+//! The typed values thrown through panics.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::panic::{self, UnwindSafe};
+
+thread_local! {
+    static THREAD_CATCH_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The payload of the unwinding started by [`throw`], carrying the thrown value.
+///
+/// The catching scopes of the crate propagate it without handling it as a panic.
+pub(crate) struct Thrown(Box<dyn Any + Send>);
+
+/// Decrements the depth of the `catch` scopes of current thread on drop.
+struct DepthGuard;
+
+impl DepthGuard {
+    #[inline]
+    fn enter() -> Self {
+        THREAD_CATCH_DEPTH.with(|d| d.set(d.get() + 1));
+        DepthGuard
+    }
+}
+
+impl Drop for DepthGuard {
+    #[inline]
+    fn drop(&mut self) {
+        THREAD_CATCH_DEPTH.with(|d| d.set(d.get() - 1));
+    }
+}
+
+/// Throws `value` to the innermost enclosing [`catch`] of its type by unwinding.
+///
+/// Unlike a panic, the unwinding does not invoke the panic hook, so it is not
+/// reported and does not abort the process in any [`CatchMode`](crate::CatchMode).
+/// It passes through the catching functions of the crate, e.g.
+/// [`catch_oom`](crate::catch_oom) and [`catch_any`](crate::catch_any), and
+/// through the `catch` of other types.
+///
+/// # Panics
+///
+/// Panics if current thread is not in any `catch` scope, as the value would
+/// unwind the whole thread.
+#[track_caller]
+pub fn throw<T: Send + 'static>(value: T) -> ! {
+    if THREAD_CATCH_DEPTH.with(Cell::get) == 0 {
+        panic!("value thrown without an enclosing `catch`");
+    }
+    panic::resume_unwind(Box::new(Thrown(Box::new(value))))
+}
+
+/// Invokes a closure, returning the value of type `T` if one is thrown by
+/// [`throw`] in it.
+///
+/// This propagates typed errors across deep call stacks, e.g. through callbacks
+/// of C libraries, which cannot return them. The values of other types are
+/// propagated to the enclosing `catch`, as are panics.
+///
+/// ```
+/// use panic_safe::{catch, throw};
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Invalid(u32);
+///
+/// let result = catch::<Invalid, _, _>(|| {
+///     [1, 2, 30].iter().map(|&n| if n > 9 { throw(Invalid(n)) } else { n }).sum::<u32>()
+/// });
+/// assert_eq!(result, Err(Invalid(30)));
+/// ```
+#[inline]
+pub fn catch<T: Send + 'static, F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R, T> {
+    let result = {
+        let _depth = DepthGuard::enter();
+        panic::catch_unwind(f)
+    };
+    match result {
+        Ok(r) => Ok(r),
+        Err(payload) => match payload.downcast::<Thrown>() {
+            Ok(thrown) => match thrown.0.downcast::<T>() {
+                Ok(value) => Err(*value),
+                Err(value) => panic::resume_unwind(Box::new(Thrown(value))),
+            },
+            Err(payload) => panic::resume_unwind(payload),
+        },
+    }
+}
@@ -0,0 +1,29 @@
+//! Integration with the tokio runtime.
+
+use std::panic::{Location, UnwindSafe};
+
+use ::tokio::task::{self, JoinHandle};
+
+use crate::catch::catch_oom_at;
+use crate::AllocError;
+
+/// Runs the closure on tokio's blocking thread pool, capturing the out-of-memory
+/// panic if one occurs.
+///
+/// The closure is invoked by [`catch_oom`](crate::catch_oom) on the blocking thread, so the allocation
+/// error is recorded and taken on the same thread, and then delivered through the
+/// returned `JoinHandle` to whichever thread awaits it.
+///
+/// # Panics
+///
+/// Panics if called outside of a tokio runtime, as [`tokio::task::spawn_blocking`] does.
+#[track_caller]
+#[inline]
+pub fn catch_oom_blocking<F, R>(f: F) -> JoinHandle<Result<R, AllocError>>
+where
+    F: FnOnce() -> R + UnwindSafe + Send + 'static,
+    R: Send + 'static,
+{
+    let caller = Location::caller();
+    task::spawn_blocking(move || catch_oom_at(caller, f))
+}
@@ -0,0 +1,442 @@
+//! The tracking allocator counting the allocated bytes.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::budget;
+use crate::hook::{suppress_oom, ThreadPanic};
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static MAX_ALLOC_SIZE: AtomicUsize = AtomicUsize::new(usize::MAX);
+static STRICT_LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+thread_local! {
+    // None needs a destructor, so accessing them never allocates.
+    static THREAD_ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    static THREAD_PEAK: Cell<usize> = const { Cell::new(0) };
+    static THREAD_TOTAL: Cell<usize> = const { Cell::new(0) };
+    static THREAD_COUNT: Cell<usize> = const { Cell::new(0) };
+    static THREAD_SCOPE_BASE: Cell<Option<usize>> = const { Cell::new(None) };
+    static THREAD_ALLOC_LIMIT: Cell<usize> = const { Cell::new(usize::MAX) };
+}
+
+/// Returns the bytes currently allocated by the process.
+///
+/// The bytes are counted by [`TrackingAlloc`], which must be registered as the
+/// global allocator, otherwise this function returns 0, as do the other usage
+/// queries.
+#[must_use]
+#[inline]
+pub fn current_usage() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+/// Returns the peak of the bytes allocated by the process.
+#[must_use]
+#[inline]
+pub fn peak_usage() -> usize {
+    PEAK.load(Ordering::Relaxed)
+}
+
+/// Resets the peak of the bytes allocated by the process to the current usage.
+#[inline]
+pub fn reset_peak_usage() {
+    PEAK.store(current_usage(), Ordering::Relaxed);
+}
+
+/// Returns the bytes currently allocated by current thread.
+///
+/// The count is the bytes allocated minus the bytes deallocated by current
+/// thread, see [`set_thread_alloc_limit`].
+#[must_use]
+#[inline]
+pub fn thread_current_usage() -> usize {
+    THREAD_ALLOCATED.with(Cell::get)
+}
+
+/// Returns the peak of the bytes allocated by current thread.
+#[must_use]
+#[inline]
+pub fn thread_peak_usage() -> usize {
+    THREAD_PEAK.with(Cell::get)
+}
+
+/// Resets the peak of the bytes allocated by current thread to its current usage.
+#[inline]
+pub fn reset_thread_peak_usage() {
+    THREAD_PEAK.with(|p| p.set(thread_current_usage()));
+}
+
+/// Sets the limit of the bytes allocated by current thread, returning the previous
+/// limit. `None` removes the limit.
+///
+/// An allocation which would make the bytes allocated by current thread exceed the
+/// limit fails, so it is reported as `AllocError` in catching scopes, which makes
+/// the allocation error deterministic even with overcommitted memory.
+///
+/// The bytes are counted by [`TrackingAlloc`], which must be registered as the
+/// global allocator, otherwise the limit has no effect. The count is the bytes
+/// allocated minus the bytes deallocated by current thread, so memory allocated by
+/// other threads and deallocated by current thread offsets the count.
+#[inline]
+pub fn set_thread_alloc_limit(limit: Option<usize>) -> Option<usize> {
+    let previous = THREAD_ALLOC_LIMIT.with(|l| l.replace(limit.unwrap_or(usize::MAX)));
+    (previous != usize::MAX).then_some(previous)
+}
+
+/// Returns the limit of the bytes allocated by current thread, see
+/// [`set_thread_alloc_limit`].
+#[must_use]
+#[inline]
+pub fn thread_alloc_limit() -> Option<usize> {
+    let limit = THREAD_ALLOC_LIMIT.with(Cell::get);
+    (limit != usize::MAX).then_some(limit)
+}
+
+/// Sets the maximum size of a single allocation in the process, returning the
+/// previous maximum. `None` removes the maximum.
+///
+/// A larger allocation fails immediately without reaching the wrapped allocator,
+/// so it is reported as `AllocError` in catching scopes. This turns an absurd
+/// request, e.g. computed from a corrupted length field, into a clean error
+/// instead of thrashing the swap until the OOM killer steps in. A reallocation is
+/// checked by its new size.
+///
+/// The maximum is enforced by [`TrackingAlloc`], which must be registered as the
+/// global allocator, otherwise it has no effect.
+#[inline]
+pub fn set_max_alloc_size(bytes: Option<usize>) -> Option<usize> {
+    let previous = MAX_ALLOC_SIZE.swap(bytes.unwrap_or(usize::MAX), Ordering::Relaxed);
+    (previous != usize::MAX).then_some(previous)
+}
+
+/// Returns the maximum size of a single allocation in the process, see
+/// [`set_max_alloc_size`].
+#[must_use]
+#[inline]
+pub fn max_alloc_size() -> Option<usize> {
+    let bytes = MAX_ALLOC_SIZE.load(Ordering::Relaxed);
+    (bytes != usize::MAX).then_some(bytes)
+}
+
+/// Enables the strict memory mode with the ceiling of the bytes allocated by the
+/// process, returning the previous ceiling. `None` disables the mode.
+///
+/// On Linux, allocation rarely fails with overcommitted memory, the OOM killer
+/// kills the process first, so allocation errors are never caught. In the strict
+/// memory mode, an allocation which would make the bytes allocated by the process
+/// exceed the ceiling fails, so `AllocError` is the deterministic outcome instead
+/// of `SIGKILL`. The ceiling applies to the bytes requested from the global
+/// allocator, so it should leave a margin below the real limit, e.g. of the
+/// cgroup, for the overhead of the allocator and the memory allocated otherwise.
+///
+/// The ceiling is enforced by [`TrackingAlloc`], which must be registered as the
+/// global allocator, otherwise it has no effect.
+#[inline]
+pub fn set_strict_memory_mode(ceiling: Option<usize>) -> Option<usize> {
+    let previous = STRICT_LIMIT.swap(ceiling.unwrap_or(usize::MAX), Ordering::Relaxed);
+    (previous != usize::MAX).then_some(previous)
+}
+
+/// Returns the ceiling of the strict memory mode, or `None` if the mode is
+/// disabled, see [`set_strict_memory_mode`].
+#[must_use]
+#[inline]
+pub fn strict_memory_mode() -> Option<usize> {
+    let ceiling = STRICT_LIMIT.load(Ordering::Relaxed);
+    (ceiling != usize::MAX).then_some(ceiling)
+}
+
+/// Returns the high-water mark of the innermost catching scope in current thread,
+/// i.e. the peak of the bytes allocated by the thread above the usage when the
+/// scope was entered, or `None` outside catching scopes.
+///
+/// This lets a long-running closure monitor itself, e.g. to spill to disk before
+/// an allocation error actually occurs. The bytes are counted by
+/// [`TrackingAlloc`], otherwise the mark is always 0.
+#[must_use]
+#[inline]
+pub fn scope_high_water_mark() -> Option<usize> {
+    let base = THREAD_SCOPE_BASE.with(Cell::get)?;
+    Some(thread_peak_usage().saturating_sub(base))
+}
+
+/// Tracks the high-water mark of a catching scope in current thread, restoring
+/// the enclosing scope on drop.
+pub(crate) struct ScopeUsage {
+    base: Option<usize>,
+    peak: usize,
+}
+
+impl ScopeUsage {
+    /// Checks if current thread is in a catching scope.
+    #[inline]
+    pub(crate) fn active() -> bool {
+        THREAD_SCOPE_BASE.with(Cell::get).is_some()
+    }
+
+    #[inline]
+    pub(crate) fn enter() -> Self {
+        let current = thread_current_usage();
+        ScopeUsage {
+            base: THREAD_SCOPE_BASE.with(|b| b.replace(Some(current))),
+            peak: ThreadUsage::set_peak(current),
+        }
+    }
+}
+
+impl Drop for ScopeUsage {
+    #[inline]
+    fn drop(&mut self) {
+        THREAD_SCOPE_BASE.with(|b| b.set(self.base));
+        THREAD_PEAK.with(|p| p.set(p.get().max(self.peak)));
+    }
+}
+
+/// A snapshot of the allocation counters of current thread.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ThreadUsage {
+    /// The bytes currently allocated.
+    pub(crate) current: usize,
+    /// The peak of the bytes allocated.
+    pub(crate) peak: usize,
+    /// The total bytes ever allocated.
+    pub(crate) total: usize,
+    /// The number of allocations.
+    pub(crate) count: usize,
+}
+
+impl ThreadUsage {
+    /// Takes a snapshot of current thread.
+    #[inline]
+    pub(crate) fn get() -> Self {
+        ThreadUsage {
+            current: THREAD_ALLOCATED.with(Cell::get),
+            peak: THREAD_PEAK.with(Cell::get),
+            total: THREAD_TOTAL.with(Cell::get),
+            count: THREAD_COUNT.with(Cell::get),
+        }
+    }
+
+    /// Sets the peak of current thread, returning the previous peak.
+    #[inline]
+    pub(crate) fn set_peak(peak: usize) -> usize {
+        THREAD_PEAK.with(|p| p.replace(peak))
+    }
+}
+
+/// A global allocator wrapper which counts the bytes allocated by the process and
+/// by each thread, see [`current_usage`] and [`thread_current_usage`], and enforces
+/// the limit set by [`set_thread_alloc_limit`], the maximum set by
+/// [`set_max_alloc_size`], the ceiling set by [`set_strict_memory_mode`], and the
+/// [`MemoryBudget`](crate::MemoryBudget) attached to the thread.
+///
+/// The limits are not enforced on an allocation while unwinding, e.g. in a
+/// destructor, where the failure cannot be raised without aborting the process.
+/// The exceeding allocation is recorded by
+/// [`AllocError::suppressed`](crate::AllocError::suppressed) instead.
+///
+/// With the `stable` feature, the wrapper should be wrapped by [`CatchAlloc`] so
+/// the failures caused by the limit are caught:
+///
+/// ```
+/// use panic_safe::{CatchAlloc, TrackingAlloc};
+/// use std::alloc::System;
+///
+/// #[global_allocator]
+/// static GLOBAL: CatchAlloc<TrackingAlloc<System>> = CatchAlloc::new(TrackingAlloc::new(System));
+/// ```
+///
+/// [`CatchAlloc`]: crate::CatchAlloc
+#[derive(Debug, Default)]
+pub struct TrackingAlloc<A = System>(A);
+
+impl<A> TrackingAlloc<A> {
+    /// Creates a new `TrackingAlloc` wrapping the given allocator.
+    #[must_use]
+    #[inline]
+    pub const fn new(alloc: A) -> Self {
+        TrackingAlloc(alloc)
+    }
+
+    /// Returns a reference to the wrapped allocator.
+    #[must_use]
+    #[inline]
+    pub const fn inner(&self) -> &A {
+        &self.0
+    }
+}
+
+impl<A> TrackingAlloc<A> {
+    /// Checks if current thread may allocate `size` more bytes, and counts them
+    /// for the process and debits them from the attached budget.
+    #[inline]
+    fn acquire(size: usize) -> bool {
+        if crate::no_alloc::deny() || crate::watchdog::deny() {
+            return false;
+        }
+        let allocated = THREAD_ALLOCATED.with(Cell::get);
+        let limit = THREAD_ALLOC_LIMIT.with(Cell::get);
+        if !allocated.checked_add(size).is_some_and(|total| total <= limit) || !Self::count_process(size) {
+            return false;
+        }
+        if !budget::debit(size) {
+            ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Counts `size` more bytes allocated by the process, unless they exceed the
+    /// ceiling of the strict memory mode.
+    #[inline]
+    fn count_process(size: usize) -> bool {
+        let ceiling = STRICT_LIMIT.load(Ordering::Relaxed);
+        if ceiling == usize::MAX {
+            ALLOCATED.fetch_add(size, Ordering::Relaxed);
+            return true;
+        }
+        let mut current = ALLOCATED.load(Ordering::Relaxed);
+        loop {
+            let Some(new) = current.checked_add(size).filter(|&new| new <= ceiling) else {
+                return false;
+            };
+            match ALLOCATED.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Checks if an allocation of `layout` denied by the limits is let exceed them,
+    /// as the failure cannot be raised while unwinding, counting `size` bytes for
+    /// the process and debiting them from the attached budget, as they are credited
+    /// when deallocated.
+    ///
+    /// The failure is recorded as suppressed by the allocation error being unwound.
+    /// The allocations capturing the backtrace of an allocation error are also let
+    /// exceed the limits, as failing them aborts the process.
+    #[inline]
+    fn exempt(layout: Layout, size: usize) -> bool {
+        #[cfg(feature = "backtrace")]
+        let capturing = crate::backtrace::capturing();
+        #[cfg(not(feature = "backtrace"))]
+        let capturing = false;
+        if !capturing {
+            if !ThreadPanic::cannot_raise() {
+                return false;
+            }
+            suppress_oom(layout);
+        }
+        ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        budget::force_debit(size);
+        true
+    }
+
+    /// Checks if a single allocation of `size` bytes exceeds the maximum.
+    #[inline]
+    fn oversized(size: usize) -> bool {
+        size > MAX_ALLOC_SIZE.load(Ordering::Relaxed)
+    }
+
+    /// Credits `size` bytes acquired but not allocated.
+    #[inline]
+    fn release(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+        budget::credit(size);
+    }
+
+    #[inline]
+    fn allocated(size: usize) {
+        PEAK.fetch_max(ALLOCATED.load(Ordering::Relaxed), Ordering::Relaxed);
+        let allocated = THREAD_ALLOCATED.with(|a| {
+            a.set(a.get().saturating_add(size));
+            a.get()
+        });
+        THREAD_PEAK.with(|p| p.set(p.get().max(allocated)));
+        THREAD_TOTAL.with(|t| t.set(t.get().wrapping_add(size)));
+        crate::tag::attribute(size);
+    }
+
+    #[inline]
+    fn counted() {
+        THREAD_COUNT.with(|c| c.set(c.get().wrapping_add(1)));
+    }
+
+    #[inline]
+    fn deallocated(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+        THREAD_ALLOCATED.with(|a| a.set(a.get().saturating_sub(size)));
+        budget::credit(size);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAlloc<A> {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if (Self::oversized(layout.size()) || !Self::acquire(layout.size())) && !Self::exempt(layout, layout.size()) {
+            return std::ptr::null_mut();
+        }
+        let ptr = self.0.alloc(layout);
+        if ptr.is_null() {
+            Self::release(layout.size());
+        } else {
+            Self::allocated(layout.size());
+            Self::counted();
+            crate::histogram::allocated(layout.size());
+            crate::large_alloc::trace(layout);
+        }
+        ptr
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout);
+        Self::deallocated(layout.size());
+        crate::histogram::deallocated(layout.size());
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if (Self::oversized(layout.size()) || !Self::acquire(layout.size())) && !Self::exempt(layout, layout.size()) {
+            return std::ptr::null_mut();
+        }
+        let ptr = self.0.alloc_zeroed(layout);
+        if ptr.is_null() {
+            Self::release(layout.size());
+        } else {
+            Self::allocated(layout.size());
+            Self::counted();
+            crate::histogram::allocated(layout.size());
+            crate::large_alloc::trace(layout);
+        }
+        ptr
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let old_size = layout.size();
+        let grow = new_size.saturating_sub(old_size);
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        if grow > 0 && (Self::oversized(new_size) || !Self::acquire(grow)) && !Self::exempt(new_layout, grow) {
+            return std::ptr::null_mut();
+        }
+        let new_ptr = self.0.realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            Self::release(grow);
+            return new_ptr;
+        }
+        if grow > 0 {
+            Self::allocated(grow);
+            crate::large_alloc::trace(new_layout);
+        } else {
+            Self::deallocated(old_size - new_size);
+        }
+        Self::counted();
+        crate::histogram::deallocated(old_size);
+        crate::histogram::allocated(new_size);
+        new_ptr
+    }
+}
@@ -0,0 +1,108 @@
+//! The watchdog failing allocations early when the resident set is too large.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::hook::ThreadPanic;
+use crate::tracking::ScopeUsage;
+
+static EXCEEDED: AtomicBool = AtomicBool::new(false);
+
+/// Checks if the allocation of current thread should fail as the resident set
+/// exceeds the threshold of the watchdog.
+///
+/// Only the allocations in catching scopes fail, except the ones handling the
+/// failure as an allocation error.
+#[inline]
+pub(crate) fn deny() -> bool {
+    EXCEEDED.load(Ordering::Relaxed)
+        && ScopeUsage::active()
+        && !ThreadPanic::handling_oom()
+        && !std::thread::panicking()
+}
+
+/// Returns the resident set size of the process in bytes.
+fn resident_set_size() -> io::Result<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        let status = std::fs::read_to_string("/proc/self/status")?;
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| value.trim().strip_suffix("kB"))
+            .and_then(|kb| kb.trim().parse::<usize>().ok())
+            .map(|kb| kb.saturating_mul(1024))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "VmRSS is not found"))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "resident set size is not supported",
+        ))
+    }
+}
+
+/// The watchdog started by [`start_rss_watchdog`], which stops when dropped.
+#[derive(Debug)]
+#[must_use = "the watchdog stops once it is dropped"]
+pub struct RssWatchdog {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for RssWatchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+        EXCEEDED.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Starts a watchdog sampling the resident set size of the process every
+/// `interval`, which makes the allocations in catching scopes fail while it
+/// exceeds `threshold` bytes.
+///
+/// This turns the process being killed by the OOM killer into allocation errors
+/// caught by the closures allocating the most, in the best case. The allocations
+/// outside catching scopes are not affected, and the allocations in them succeed
+/// again once the resident set shrinks below the threshold.
+///
+/// The allocations are checked by [`TrackingAlloc`](crate::TrackingAlloc), which
+/// must be registered as the global allocator. Only Linux is supported, on which
+/// the size is read from `/proc/self/status`, otherwise an error is returned.
+///
+/// ```
+/// use panic_safe::start_rss_watchdog;
+/// use std::time::Duration;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let _watchdog = start_rss_watchdog(6 << 30, Duration::from_millis(100))?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn start_rss_watchdog(threshold: usize, interval: Duration) -> io::Result<RssWatchdog> {
+    resident_set_size()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = thread::Builder::new().name("panic-safe-watchdog".into()).spawn({
+        let stop = Arc::clone(&stop);
+        move || {
+            while !stop.load(Ordering::Relaxed) {
+                if let Ok(size) = resident_set_size() {
+                    EXCEEDED.store(size > threshold, Ordering::Relaxed);
+                }
+                thread::park_timeout(interval);
+            }
+        }
+    })?;
+    Ok(RssWatchdog {
+        stop,
+        thread: Some(thread),
+    })
+}
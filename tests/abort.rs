@@ -0,0 +1,119 @@
+#![cfg(unix)]
+
+use std::alloc::{handle_alloc_error, Layout};
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::panic::PanicHookInfo;
+use std::process::Output;
+
+use panic_safe::{
+    add_abort_hook, catch_oom, set_abort_outside_scopes, set_crash_report, set_fatal_reporter, AbortGuard,
+};
+
+mod common;
+
+use common::run_child;
+
+const SIGABRT: i32 = 6;
+
+/// The crash report of the child process, named by the pid of the parent.
+fn crash_report_path() -> std::path::PathBuf {
+    let pid = match std::env::var_os("PANIC_SAFE_TEST_CHILD") {
+        Some(_) => std::os::unix::process::parent_id(),
+        None => std::process::id(),
+    };
+    std::env::temp_dir().join(format!("panic-safe-crash-{}.txt", pid))
+}
+
+fn assert_aborted(output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert_eq!(output.status.signal(), Some(SIGABRT), "stderr: {}", stderr);
+    stderr
+}
+
+fn report(_: &PanicHookInfo<'_>) {
+    let _ = std::io::stderr().write_all(b"fatal reporter\n");
+}
+
+#[test]
+fn panic_in_scope_runs_the_abort_hooks() {
+    let Some(output) = run_child("panic_in_scope_runs_the_abort_hooks", || {
+        add_abort_hook(|_| {
+            let _ = std::io::stderr().write_all(b"abort hook\n");
+        });
+        let _ = catch_oom(|| panic!("boom"));
+    }) else {
+        return;
+    };
+    let stderr = assert_aborted(&output);
+    assert!(stderr.contains("abort hook"), "stderr: {}", stderr);
+}
+
+#[test]
+fn panic_in_scope_writes_the_crash_report() {
+    let Some(output) = run_child("panic_in_scope_writes_the_crash_report", || {
+        set_crash_report(crash_report_path()).unwrap();
+        let _ = catch_oom(|| panic!("boom"));
+    }) else {
+        return;
+    };
+    assert_aborted(&output);
+    let crash_report = std::fs::read_to_string(crash_report_path()).unwrap();
+    std::fs::remove_file(crash_report_path()).unwrap();
+    assert!(crash_report.contains("boom"), "crash report: {}", crash_report);
+}
+
+#[test]
+fn fatal_reporter_runs_before_the_abort_hooks() {
+    let Some(output) = run_child("fatal_reporter_runs_before_the_abort_hooks", || {
+        set_fatal_reporter(Some(report));
+        add_abort_hook(|_| {
+            let _ = std::io::stderr().write_all(b"abort hook\n");
+        });
+        let _ = catch_oom(|| panic!("boom"));
+    }) else {
+        return;
+    };
+    let stderr = assert_aborted(&output);
+    let reporter = stderr.find("fatal reporter").unwrap();
+    assert!(stderr[reporter..].contains("abort hook"), "stderr: {}", stderr);
+}
+
+#[test]
+fn oom_in_critical_section_aborts() {
+    let Some(output) = run_child("oom_in_critical_section_aborts", || {
+        let _ = catch_oom(|| {
+            let _guard = AbortGuard::new();
+            handle_alloc_error(Layout::new::<u64>());
+        });
+    }) else {
+        return;
+    };
+    assert_aborted(&output);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn disarmed_critical_section_is_left() {
+    let guard = AbortGuard::new();
+    guard.disarm();
+    assert!(catch_oom(|| handle_alloc_error(Layout::new::<u64>())).is_err());
+}
+
+#[test]
+fn panic_outside_scopes_aborts_if_enabled() {
+    let Some(output) = run_child("panic_outside_scopes_aborts_if_enabled", || {
+        set_abort_outside_scopes(true);
+        panic_safe::init();
+        panic!("outside");
+    }) else {
+        return;
+    };
+    assert_aborted(&output);
+}
+
+#[test]
+fn panic_outside_scopes_unwinds_by_default() {
+    panic_safe::init();
+    assert!(std::panic::catch_unwind(|| panic!("outside")).is_err());
+}
@@ -0,0 +1,68 @@
+#![cfg(all(feature = "capi", not(feature = "stable")))]
+
+use std::alloc::{handle_alloc_error, Layout};
+use std::ffi::{c_char, c_void, CStr};
+
+use panic_safe::capi::{
+    panic_safe_call, panic_safe_init, panic_safe_last_error_align, panic_safe_last_error_message,
+    panic_safe_last_error_size, PANIC_SAFE_INVALID, PANIC_SAFE_OK, PANIC_SAFE_OOM, PANIC_SAFE_PANIC,
+};
+
+unsafe extern "C-unwind" fn add_one(ctx: *mut c_void) {
+    *ctx.cast::<u32>() += 1;
+}
+
+unsafe extern "C-unwind" fn fail(_: *mut c_void) {
+    handle_alloc_error(Layout::from_size_align(96, 16).unwrap());
+}
+
+unsafe extern "C-unwind" fn panic(_: *mut c_void) {
+    panic!("expected");
+}
+
+fn last_error_message(len: usize) -> (usize, String) {
+    let mut buf = vec![0 as c_char; len];
+    let total = unsafe { panic_safe_last_error_message(buf.as_mut_ptr(), buf.len()) };
+    let message = match len {
+        0 => String::new(),
+        _ => unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned(),
+    };
+    (total, message)
+}
+
+#[test]
+fn calls_report_their_errors() {
+    assert_eq!(panic_safe_init(), PANIC_SAFE_OK);
+    let mut n = 1u32;
+    assert_eq!(
+        unsafe { panic_safe_call(Some(add_one), (&raw mut n).cast()) },
+        PANIC_SAFE_OK
+    );
+    assert_eq!(n, 2);
+    assert_eq!(
+        unsafe { panic_safe_call(None, std::ptr::null_mut()) },
+        PANIC_SAFE_INVALID
+    );
+
+    assert_eq!(
+        unsafe { panic_safe_call(Some(fail), std::ptr::null_mut()) },
+        PANIC_SAFE_OOM
+    );
+    assert_eq!((panic_safe_last_error_size(), panic_safe_last_error_align()), (96, 16));
+    let (total, message) = last_error_message(256);
+    assert_eq!(total, message.len());
+    assert!(message.contains("96"), "message: {}", message);
+
+    // The message is truncated like `snprintf`.
+    let (truncated_total, truncated) = last_error_message(5);
+    assert_eq!(truncated_total, total);
+    assert_eq!(truncated, message[..4]);
+    assert_eq!(last_error_message(0).0, total);
+
+    assert_eq!(
+        unsafe { panic_safe_call(Some(panic), std::ptr::null_mut()) },
+        PANIC_SAFE_PANIC
+    );
+    assert_eq!(panic_safe_last_error_size(), 0);
+    assert!(last_error_message(256).1.contains("expected"));
+}
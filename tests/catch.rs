@@ -0,0 +1,470 @@
+use std::alloc::Layout;
+use std::any::Any;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use panic_safe::{
+    catch, catch_oom, catch_oom_retry_with_backoff, catch_oom_with_hook, catch_panic, payload_as_str, throw,
+    AllocError, AllocErrorKind, CatchMode, CaughtError,
+};
+
+#[cfg(not(feature = "stable"))]
+use std::alloc::handle_alloc_error;
+#[cfg(not(feature = "stable"))]
+use std::cell::RefCell;
+#[cfg(not(feature = "stable"))]
+use std::io;
+#[cfg(not(feature = "stable"))]
+use std::panic::AssertUnwindSafe;
+
+#[cfg(not(feature = "stable"))]
+use panic_safe::{
+    add_memory_releaser, catch_any, catch_ffi, catch_oom_assert, catch_oom_mut, catch_oom_named, catch_oom_result,
+    catch_oom_retry, catch_oom_rich, catch_oom_with_mode, defer, on_oom, peek_last_error, take_last_error, CatchError,
+    CatchOomExt, Catcher, ErrorScope, PanicKind,
+};
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn inner_error_leaves_outer_scope_clean() {
+    let result = catch_oom(|| {
+        let inner = catch_oom(|| handle_alloc_error(layout(16))).unwrap_err();
+        assert_eq!(inner.size(), 16);
+        7
+    });
+    assert_eq!(result.unwrap(), 7);
+}
+
+/// Runs a catching scope when dropped, e.g. while the outer scope unwinds.
+#[cfg(not(feature = "stable"))]
+struct CatchOnDrop<'a> {
+    size: Option<usize>,
+    result: &'a Cell<Option<Result<(), AllocError>>>,
+}
+
+#[cfg(not(feature = "stable"))]
+impl Drop for CatchOnDrop<'_> {
+    fn drop(&mut self) {
+        let size = self.size;
+        self.result.set(Some(catch_oom(|| {
+            if let Some(size) = size {
+                handle_alloc_error(layout(size));
+            }
+        })));
+    }
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn outer_error_is_kept_across_inner_scope() {
+    for inner_size in [None, Some(32)] {
+        let inner = Cell::new(None);
+        let outer = catch_oom(AssertUnwindSafe(|| {
+            let _guard = CatchOnDrop {
+                size: inner_size,
+                result: &inner,
+            };
+            handle_alloc_error(layout(64));
+        }))
+        .unwrap_err();
+        assert_eq!(outer.size(), 64);
+        match (inner_size, inner.take().unwrap()) {
+            (None, result) => assert!(result.is_ok()),
+            (Some(size), result) => assert_eq!(result.unwrap_err().size(), size),
+        }
+    }
+}
+
+#[test]
+fn panic_error_describes_the_panic() {
+    let e = catch_panic(|| panic!("expected {}", 1)).unwrap_err();
+    assert_eq!(e.message(), Some("expected 1"));
+    assert_eq!(e.location().unwrap().file(), file!());
+    assert!(e.to_string().starts_with("panicked at tests/catch.rs:"));
+
+    let e = catch_panic(|| std::panic::panic_any(7u8)).unwrap_err();
+    assert_eq!(e.message(), None);
+    assert_eq!(e.payload().downcast_ref::<u8>(), Some(&7));
+    assert_eq!(catch_panic(|| 1).unwrap(), 1);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn allocation_error_is_caught() {
+    assert_eq!(catch_oom(|| 1).unwrap(), 1);
+    let e = catch_oom(|| handle_alloc_error(layout(8))).unwrap_err();
+    assert_eq!(e.layout(), layout(8));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn oom_panic_has_a_static_message() {
+    let e = catch_panic(|| handle_alloc_error(layout(8))).unwrap_err();
+    assert_eq!(e.message(), Some("memory allocation failed"));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn panic_kinds_are_classified() {
+    let kind = |f: fn()| catch_panic(f).unwrap_err().kind();
+    assert_eq!(kind(|| panic!("expected")), PanicKind::Explicit);
+    assert_eq!(kind(|| std::panic::panic_any(7u8)), PanicKind::Unknown);
+    assert_eq!(kind(|| handle_alloc_error(layout(8))), PanicKind::OutOfMemory);
+    assert_eq!(kind(|| assert_eq!(1, 2)), PanicKind::Assertion);
+    assert_eq!(
+        kind(|| {
+            std::hint::black_box(std::hint::black_box([0u8; 1])[std::hint::black_box(3)]);
+        }),
+        PanicKind::IndexOutOfBounds
+    );
+    assert_eq!(
+        kind(|| {
+            std::hint::black_box(None::<u8>).unwrap();
+        }),
+        PanicKind::Unwrap
+    );
+    assert_eq!(
+        kind(|| {
+            std::hint::black_box(1u8 / std::hint::black_box(0));
+        }),
+        PanicKind::ArithmeticOverflow
+    );
+    assert_eq!(PanicKind::IndexOutOfBounds.to_string(), "index_out_of_bounds");
+}
+
+#[test]
+fn payload_is_downcast_by_type() {
+    let e = catch_panic(|| std::panic::panic_any(7u8)).unwrap_err();
+    assert_eq!(e.downcast_payload::<u8>(), Some(&7));
+    assert_eq!(e.downcast_payload::<u16>(), None);
+}
+
+#[test]
+fn payload_as_str_looks_into_boxes() {
+    let payload: Box<dyn Any + Send> = Box::new(String::from("boxed"));
+    assert_eq!(payload_as_str(&payload), Some("boxed"));
+    assert_eq!(payload_as_str(&*payload), Some("boxed"));
+    assert_eq!(payload_as_str(&1u8), None);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn any_scope_catches_both() {
+    assert!(matches!(
+        catch_any(|| handle_alloc_error(layout(8))),
+        Err(CaughtError::Oom(_))
+    ));
+    assert!(matches!(catch_any(|| panic!("expected")), Err(CaughtError::Panic(_))));
+    assert_eq!(catch_any(|| 1).unwrap(), 1);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn ffi_scope_catches_both() {
+    assert!(matches!(
+        catch_ffi(|| handle_alloc_error(layout(8))),
+        Err(CaughtError::Oom(_))
+    ));
+    assert!(matches!(catch_ffi(|| panic!("expected")), Err(CaughtError::Panic(_))));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn panics_are_caught_by_mode() {
+    let e = catch_oom_with_mode(CatchMode::ReturnError, || panic!("expected")).unwrap_err();
+    assert!(matches!(&e, CaughtError::Panic(p) if p.message() == Some("expected")));
+    let e = catch_oom_with_mode(CatchMode::ReturnError, || handle_alloc_error(layout(8))).unwrap_err();
+    assert!(matches!(e, CaughtError::Oom(_)));
+
+    let payload = std::panic::catch_unwind(|| {
+        let _ = catch_oom_with_mode(CatchMode::ResumeUnwind, || panic!("resumed"));
+    })
+    .unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"resumed"));
+}
+
+#[test]
+fn hook_scope_chooses_the_mode() {
+    let seen = Cell::new(false);
+    let e = catch_oom_with_hook(
+        |_| {
+            seen.set(true);
+            CatchMode::ReturnError
+        },
+        || panic!("expected"),
+    )
+    .unwrap_err();
+    assert!(seen.get());
+    assert!(matches!(e, CaughtError::Panic(_)));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn error_kind_tells_the_cause() {
+    let e = catch_oom(|| handle_alloc_error(layout(8))).unwrap_err();
+    assert_eq!(e.kind(), AllocErrorKind::Exhausted);
+    assert_eq!(AllocError::capacity_overflow().kind(), AllocErrorKind::CapacityOverflow);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn error_describes_the_layout() {
+    let e = catch_oom(|| handle_alloc_error(layout(48))).unwrap_err();
+    assert_eq!((e.size(), e.align()), (48, 8));
+    assert!(e
+        .to_string()
+        .starts_with("failed to allocate memory by required layout {size: 48, align: 8}"));
+    assert!(format!("{:#}", e).starts_with("failed to allocate 48"));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn error_records_the_caller_location() {
+    let line = line!() + 1;
+    let e = catch_oom(|| handle_alloc_error(layout(8))).unwrap_err();
+    let location = e.location().unwrap();
+    assert_eq!((location.file(), location.line()), (file!(), line));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn named_scope_keeps_inner_context() {
+    let e = catch_oom_named("outer", || handle_alloc_error(layout(8))).unwrap_err();
+    assert_eq!(e.context(), Some("outer"));
+
+    let e = catch_oom_named("outer", || {
+        std::panic::resume_unwind(Box::new(AllocError::new(layout(8)).with_context("inner")))
+    })
+    .unwrap_err();
+    assert_eq!(e.context(), Some("inner"));
+}
+
+#[test]
+fn capacity_overflow_is_caught() {
+    let e = catch_oom(|| std::hint::black_box(Vec::<u8>::with_capacity(usize::MAX)).len()).unwrap_err();
+    assert_eq!(e.kind(), AllocErrorKind::CapacityOverflow);
+}
+
+#[test]
+fn invalid_layout_is_impossible() {
+    let e = AllocError::invalid_layout(layout(8));
+    assert_eq!(e.kind(), AllocErrorKind::InvalidLayout);
+    assert!(e.kind().is_impossible());
+    assert!(AllocErrorKind::CapacityOverflow.is_impossible());
+    assert!(!AllocErrorKind::Exhausted.is_impossible());
+}
+
+#[test]
+fn unexpected_panic_is_returned() {
+    // A resumed panic bypasses the panic hook, so it is not aborted.
+    let e = catch_oom(|| std::panic::resume_unwind(Box::new(1u8))).unwrap_err();
+    assert_eq!(e.kind(), AllocErrorKind::UnexpectedPanic);
+    assert_eq!(e.take_panic().unwrap().payload().downcast_ref::<u8>(), Some(&1));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn result_scope_separates_errors() {
+    let result = catch_oom_result(|| "x".parse::<u32>());
+    assert!(matches!(result, Err(CatchError::User(_))));
+    let result = catch_oom_result(|| -> Result<u32, std::num::ParseIntError> { handle_alloc_error(layout(8)) });
+    assert!(matches!(result, Err(CatchError::Alloc(_))));
+    assert_eq!(catch_oom_result(|| "7".parse::<u32>()).unwrap(), 7);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn catch_error_is_inspected_and_converted() {
+    let e = catch_oom_result(|| "x".parse::<u32>()).unwrap_err();
+    assert!(e.alloc_error().is_none());
+    assert!(e.user_error().is_some());
+
+    let e = catch_oom_result(|| -> Result<u32, std::num::ParseIntError> { handle_alloc_error(layout(8)) }).unwrap_err();
+    assert_eq!(e.alloc_error().unwrap().size(), 8);
+    let e = e.map_user(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    assert_eq!(e.into_user().kind(), io::ErrorKind::OutOfMemory);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn mut_scopes_reuse_the_closure() {
+    let mut calls = 0;
+    let mut f = move || {
+        calls += 1;
+        if calls == 2 {
+            handle_alloc_error(layout(8));
+        }
+        calls
+    };
+    assert_eq!(catch_oom_mut(&mut f).unwrap(), 1);
+    assert!(catch_oom_mut(&mut f).is_err());
+    let catcher = Catcher::new();
+    assert_eq!(catcher.catch_oom_mut(&mut f).unwrap(), 3);
+    assert_eq!(catcher.catch_oom(|| 4).unwrap(), 4);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn assert_scope_takes_non_unwind_safe_closures() {
+    let cell = RefCell::new(Vec::new());
+    catch_oom_assert(|| cell.borrow_mut().push(1)).unwrap();
+    assert!(catch_oom_assert(|| {
+        cell.borrow_mut().push(2);
+        handle_alloc_error(layout(8))
+    })
+    .is_err());
+    assert_eq!(*cell.borrow(), [1, 2]);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn ext_methods_match_the_functions() {
+    assert_eq!((|| 1).catch_oom().unwrap(), 1);
+    let e = (|| handle_alloc_error(layout(8))).catch_oom_named("ext").unwrap_err();
+    assert_eq!(e.context(), Some("ext"));
+    assert!((|| panic!("expected")).catch_panic().is_err());
+    assert!(matches!(
+        (|| panic!("expected")).catch_any(),
+        Err(CaughtError::Panic(_))
+    ));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn macro_captures_the_block() {
+    let words = String::from("b a b");
+    let summary = panic_safe::catch_oom! {
+        let mut words: Vec<_> = words.split(' ').collect();
+        words.sort_unstable();
+        words.dedup();
+        words.join(" ")
+    };
+    assert_eq!(summary.unwrap(), "a b");
+    assert!(panic_safe::catch_oom! { move handle_alloc_error(layout(8)) }.is_err());
+}
+
+#[test]
+fn thrown_values_are_caught_by_type() {
+    assert_eq!(catch::<u32, _, _>(|| throw(3u32)), Err::<(), _>(3));
+    assert_eq!(catch::<u32, _, ()>(|| ()), Ok(()));
+    let result = catch::<&str, _, _>(|| catch::<u32, _, ()>(|| throw("other")));
+    assert_eq!(result, Err("other"));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn guards_run_on_exit() {
+    let rolled_back = Cell::new(0);
+    let deferred = Cell::new(0);
+    let result = catch_oom(AssertUnwindSafe(|| {
+        let _rollback = on_oom(|| rolled_back.set(rolled_back.get() + 1));
+        let _deferred = defer(|| deferred.set(deferred.get() + 1));
+        handle_alloc_error(layout(8));
+    }));
+    assert!(result.is_err());
+    assert_eq!((rolled_back.get(), deferred.get()), (1, 1));
+
+    catch_oom(AssertUnwindSafe(|| {
+        let _rollback = on_oom(|| rolled_back.set(rolled_back.get() + 1));
+        let _deferred = defer(|| deferred.set(deferred.get() + 1));
+    }))
+    .unwrap();
+    assert_eq!((rolled_back.get(), deferred.get()), (1, 2));
+
+    catch_oom(AssertUnwindSafe(|| on_oom(|| rolled_back.set(0)).dismiss())).unwrap();
+    assert_eq!(rolled_back.get(), 1);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn retry_runs_the_closure_again() {
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+    add_memory_releaser(|| {
+        RELEASED.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let mut runs = 0;
+    let result = catch_oom_retry(3, move || {
+        runs += 1;
+        if runs < 3 {
+            handle_alloc_error(layout(8));
+        }
+        runs
+    });
+    assert_eq!(result.unwrap(), 3);
+    assert!(RELEASED.load(Ordering::Relaxed) >= 2);
+
+    let runs = AtomicUsize::new(0);
+    let result = catch_oom_retry(2, || -> () {
+        runs.fetch_add(1, Ordering::Relaxed);
+        handle_alloc_error(layout(8))
+    });
+    assert!(result.is_err());
+    assert_eq!(runs.load(Ordering::Relaxed), 3);
+
+    let runs = AtomicUsize::new(0);
+    let result = catch_oom_retry_with_backoff(2, Duration::from_millis(1), || -> () {
+        runs.fetch_add(1, Ordering::Relaxed);
+        handle_alloc_error(layout(8))
+    });
+    assert!(result.is_err());
+    assert_eq!(runs.load(Ordering::Relaxed), 3);
+}
+
+#[test]
+fn retry_gives_up_on_impossible_allocations() {
+    let runs = AtomicUsize::new(0);
+    let result = catch_oom_retry_with_backoff(5, Duration::from_millis(1), || {
+        runs.fetch_add(1, Ordering::Relaxed);
+        std::hint::black_box(Vec::<u8>::with_capacity(usize::MAX));
+    });
+    assert_eq!(result.unwrap_err().kind(), AllocErrorKind::CapacityOverflow);
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn error_scope_keeps_uncaught_errors() {
+    let scope = ErrorScope::new();
+    let payload = std::panic::catch_unwind(|| scope.enter(|| handle_alloc_error(layout(24)))).unwrap_err();
+    drop(payload);
+    assert!(scope.has_error());
+    assert_eq!(scope.take_error().unwrap().layout(), layout(24));
+    assert!(!scope.has_error());
+
+    let result = scope.enter(|| catch_oom(|| handle_alloc_error(layout(8))));
+    assert!(result.is_err());
+    assert!(scope.take_error().is_none());
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn last_error_is_recorded_per_thread() {
+    let _ = take_last_error();
+    let _ = catch_oom(|| handle_alloc_error(layout(72)));
+    assert_eq!(peek_last_error().unwrap().size(), 72);
+    assert_eq!(take_last_error().unwrap().size(), 72);
+    assert!(take_last_error().is_none());
+    std::thread::spawn(|| assert!(peek_last_error().is_none()))
+        .join()
+        .unwrap();
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn rich_error_records_the_thread() {
+    let e = std::thread::Builder::new()
+        .name("rich".into())
+        .spawn(|| catch_oom_rich(|| handle_alloc_error(layout(8))).unwrap_err())
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(e.thread_name(), Some("rich"));
+    assert_eq!(e.error().layout(), layout(8));
+    assert!(e.timestamp() <= std::time::SystemTime::now());
+}
@@ -0,0 +1,24 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use std::process::{Command, Output};
+
+const CHILD: &str = "PANIC_SAFE_TEST_CHILD";
+
+/// Runs the test `name` in a child process, which runs `child` instead of the
+/// test body, e.g. as it aborts or its stderr is checked.
+///
+/// Returns the output of the child in the parent, or `None` in the child.
+pub fn run_child(name: &str, child: fn()) -> Option<Output> {
+    if std::env::var(CHILD).as_deref() == Ok(name) {
+        child();
+        return None;
+    }
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([name, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD, name)
+        .output()
+        .unwrap();
+    Some(output)
+}
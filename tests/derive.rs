@@ -0,0 +1,60 @@
+#![cfg(all(feature = "derive", not(feature = "stable")))]
+
+use std::alloc::{handle_alloc_error, Layout};
+
+use panic_safe::{catch_oom, catch_oom_fn, ffi_guard, AllocError, FromAllocError};
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[derive(Debug, FromAllocError)]
+enum Error {
+    #[allow(dead_code)]
+    Parse(std::num::ParseIntError),
+    OutOfMemory(AllocError),
+}
+
+#[derive(Debug, FromAllocError)]
+struct Wrapper(Box<AllocError>);
+
+#[test]
+fn derived_conversion_picks_the_variant() {
+    let e = Error::from(catch_oom(|| handle_alloc_error(layout(8))).unwrap_err());
+    assert!(matches!(e, Error::OutOfMemory(e) if e.layout() == layout(8)));
+    let Wrapper(e) = AllocError::new(layout(16)).into();
+    assert_eq!(e.layout(), layout(16));
+}
+
+#[catch_oom_fn]
+fn parse_or_fail(input: &str, fail: bool) -> Result<u32, std::num::ParseIntError> {
+    if fail {
+        handle_alloc_error(layout(24));
+    }
+    let n = input.parse()?;
+    Ok(n)
+}
+
+#[test]
+fn attributed_function_returns_the_error() {
+    assert_eq!(parse_or_fail("7", false).unwrap().unwrap(), 7);
+    assert!(parse_or_fail("x", false).unwrap().is_err());
+    let e: AllocError = parse_or_fail("7", true).unwrap_err();
+    assert_eq!(e.layout(), layout(24));
+}
+
+#[ffi_guard(oom = -2, panic = -1)]
+extern "C" fn guarded(mode: i32) -> i32 {
+    match mode {
+        0 => handle_alloc_error(layout(8)),
+        1 => panic!("expected"),
+        _ => mode,
+    }
+}
+
+#[test]
+fn guarded_function_returns_the_sentinels() {
+    assert_eq!(guarded(0), -2);
+    assert_eq!(guarded(1), -1);
+    assert_eq!(guarded(7), 7);
+}
@@ -0,0 +1,147 @@
+#![cfg_attr(not(feature = "stable"), feature(allocator_api))]
+#![cfg_attr(not(feature = "stable"), feature(error_generic_member_access))]
+
+use std::alloc::Layout;
+use std::hash::{BuildHasher, RandomState};
+
+use panic_safe::AllocError;
+
+#[cfg(not(feature = "stable"))]
+use std::alloc::handle_alloc_error;
+#[cfg(not(feature = "stable"))]
+use std::error::Error;
+#[cfg(not(feature = "stable"))]
+use std::io;
+
+#[cfg(not(feature = "stable"))]
+use panic_safe::{catch_any, catch_oom_result, try_reserve_or_catch, AllocErrorKind, CatchError, CaughtError};
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn caught_error_is_transparent() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let e = catch_any(|| handle_alloc_error(layout)).unwrap_err();
+    assert!(matches!(&e, CaughtError::Oom(e) if e.layout() == layout));
+    assert!(e.source().is_none());
+
+    let e = catch_any(|| panic!("expected")).unwrap_err();
+    let CaughtError::Panic(panic) = &e else {
+        panic!("unexpected error: {}", e);
+    };
+    assert_eq!(e.to_string(), panic.to_string());
+    assert!(e.source().is_none());
+}
+
+#[cfg(all(feature = "backtrace", not(feature = "stable")))]
+#[test]
+fn backtrace_is_captured_at_the_failure() {
+    let layout = std::alloc::Layout::new::<u64>();
+    let e = panic_safe::catch_oom(|| std::alloc::handle_alloc_error(layout)).unwrap_err();
+    let backtrace = e.backtrace().unwrap();
+    assert_eq!(backtrace.status(), std::backtrace::BacktraceStatus::Captured);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn error_provides_the_layout() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let e = panic_safe::catch_oom(|| handle_alloc_error(layout)).unwrap_err();
+    assert_eq!(std::error::request_value::<Layout>(&e), Some(layout));
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn converts_into_io_error() {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let e = panic_safe::catch_oom(|| handle_alloc_error(layout)).unwrap_err();
+    let e = io::Error::from(e);
+    assert_eq!(e.kind(), io::ErrorKind::OutOfMemory);
+    assert_eq!(
+        e.get_ref().unwrap().downcast_ref::<AllocError>().unwrap().layout(),
+        layout
+    );
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn try_reserve_error_is_converted() {
+    let e = Vec::<u8>::new().try_reserve(usize::MAX).unwrap_err();
+    assert_eq!(AllocError::from(e).kind(), AllocErrorKind::CapacityOverflow);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn reservation_and_closure_share_the_error() {
+    let mut v = vec![1u8; 4];
+    let len = try_reserve_or_catch(&mut v, 100, |v| {
+        v.extend_from_slice(&[2; 100]);
+        v.len()
+    })
+    .unwrap();
+    assert_eq!(len, 104);
+    let e = try_reserve_or_catch(&mut v, usize::MAX, |_| ()).unwrap_err();
+    assert_eq!(e.kind(), AllocErrorKind::CapacityOverflow);
+}
+
+#[test]
+fn errors_are_compared_by_layout() {
+    let small = AllocError::new(Layout::new::<u32>());
+    let large = AllocError::new(Layout::new::<u64>());
+    assert_eq!(small, AllocError::new(Layout::new::<u32>()));
+    assert_ne!(small, large);
+    assert!(small < large);
+    let state = RandomState::new();
+    assert_eq!(
+        state.hash_one(&small),
+        state.hash_one(AllocError::new(Layout::new::<u32>()))
+    );
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn allocator_api_error_conversions() {
+    use panic_safe::{AllocError, AllocErrorKind};
+
+    let layout = Layout::from_size_align(16, 4).unwrap();
+    let e = AllocError::from_allocator(std::alloc::AllocError, layout);
+    assert_eq!(e.layout(), layout);
+    assert_eq!(e.kind(), AllocErrorKind::Exhausted);
+    assert!(std::alloc::AllocError::try_from(e).is_ok());
+
+    // A resumed panic bypasses the panic hook, so it is not aborted.
+    let e = panic_safe::catch_oom(|| std::panic::resume_unwind(Box::new(1))).unwrap_err();
+    let e = std::alloc::AllocError::try_from(e).unwrap_err();
+    assert_eq!(e.kind(), AllocErrorKind::UnexpectedPanic);
+    assert!(e.take_panic().is_some());
+}
+
+#[derive(Debug)]
+#[cfg(not(feature = "stable"))]
+struct Wrapped(std::io::Error);
+
+#[cfg(not(feature = "stable"))]
+impl std::fmt::Display for Wrapped {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("wrapped")
+    }
+}
+
+#[cfg(not(feature = "stable"))]
+impl Error for Wrapped {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn catch_error_is_transparent() {
+    let e = CatchError::User(Wrapped(std::io::Error::other("inner")));
+    assert_eq!(e.to_string(), "wrapped");
+    assert_eq!(e.source().unwrap().to_string(), "inner");
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let e = catch_oom_result(|| -> Result<(), Wrapped> { handle_alloc_error(layout) }).unwrap_err();
+    assert!(matches!(&e, CatchError::Alloc(e) if e.layout() == layout));
+    assert!(e.source().is_none());
+}
@@ -0,0 +1,74 @@
+use std::alloc::{Layout, System};
+use std::hint::black_box;
+
+use panic_safe::{catch_oom, catch_panic, exhaustive_oom_check, replay_failure, FailureTrace, FaultInjector};
+
+#[cfg(not(feature = "stable"))]
+#[global_allocator]
+static GLOBAL: FaultInjector<System> = FaultInjector::new(System);
+#[cfg(feature = "stable")]
+#[global_allocator]
+static GLOBAL: panic_safe::CatchAlloc<FaultInjector<System>> = panic_safe::CatchAlloc::new(FaultInjector::new(System));
+
+/// Allocates the vector of `n` boxes, i.e., `n + 1` allocations.
+fn boxes(n: usize) -> usize {
+    let mut v = Vec::with_capacity(n);
+    v.extend((0..n).map(Box::new));
+    v.len()
+}
+
+#[test]
+fn countdown_fails_one_allocation() {
+    FaultInjector::fail_at(3);
+    let e = catch_oom(|| boxes(5)).unwrap_err();
+    assert_eq!(e.layout(), Layout::new::<usize>());
+    assert_eq!(FaultInjector::allocations(), 3);
+    assert_eq!(catch_oom(|| boxes(5)).unwrap(), 5);
+}
+
+#[test]
+fn predicate_fails_matching_allocations() {
+    FaultInjector::fail_when(|layout| layout.size() == 24);
+    assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(24))).is_err());
+    assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(16))).is_ok());
+    assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(24))).is_err());
+    FaultInjector::disarm();
+    assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(24))).is_ok());
+}
+
+#[test]
+fn failure_is_replayed() {
+    FaultInjector::fail_at(4);
+    assert!(catch_oom(|| boxes(6)).is_err());
+    let trace = FaultInjector::last_failure().unwrap();
+    assert_eq!(trace, FailureTrace::new(4, Layout::new::<usize>()));
+    assert_eq!(trace.to_string(), "allocation 4 of 8 bytes aligned to 8");
+    assert!(replay_failure(&trace, || boxes(6)).is_err());
+    // The closure completes before reaching the failed allocation.
+    assert!(replay_failure(&trace, || boxes(1)).is_ok());
+}
+
+#[test]
+fn replayed_failure_checks_the_layout() {
+    let trace = FailureTrace::new(1, Layout::new::<u128>());
+    let e = catch_panic(|| replay_failure(&trace, || boxes(2))).unwrap_err();
+    assert!(e.message().unwrap().contains("has another layout"));
+}
+
+#[test]
+fn exhaustive_check_fails_every_allocation() {
+    assert_eq!(exhaustive_oom_check(|| boxes(3)), 4);
+    assert_eq!(exhaustive_oom_check(|| 1), 0);
+}
+
+#[cfg(feature = "fuzz")]
+#[test]
+fn schedule_fails_the_allocations_of_the_input() {
+    use panic_safe::FailureSchedule;
+
+    let schedule = FailureSchedule::from_bytes(&[1, 0x80, 0x01]);
+    assert_eq!(schedule.failures().collect::<Vec<_>>(), [2, 131]);
+    assert!(FailureSchedule::from_bytes(&[1]).run(|| boxes(3)).is_err());
+    assert!(FailureSchedule::from_bytes(&[9]).run(|| boxes(3)).is_ok());
+    assert!(FailureSchedule::default().run(|| boxes(3)).is_ok());
+}
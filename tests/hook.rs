@@ -0,0 +1,170 @@
+// The allocation errors are raised by `handle_alloc_error`, which is not hooked
+// with the `stable` feature.
+#![cfg(not(feature = "stable"))]
+
+use std::alloc::{handle_alloc_error, Layout};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use panic_safe::{
+    catch_mode, catch_oom, catch_panic, emergency_reserve, install_scoped, quiet_oom, register_low_memory_listener,
+    register_oom_observer, set_catch_mode, set_emergency_reserve, set_oom_message, set_quiet_oom, verify_hook,
+    AllocErrorInfo, CatchMode, OomMessage,
+};
+
+mod common;
+
+use common::run_child;
+
+/// Serializes the tests changing the global state.
+static GLOBAL: Mutex<()> = Mutex::new(());
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn hooks_are_installed() {
+    assert!(panic_safe::can_unwind());
+    assert!(panic_safe::try_init().is_ok());
+}
+
+#[test]
+fn init_is_race_free() {
+    let threads: Vec<_> = (0..4).map(|_| std::thread::spawn(panic_safe::init)).collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert!(catch_oom(|| handle_alloc_error(layout(8))).is_err());
+}
+
+#[test]
+fn previous_hook_is_chained() {
+    let Some(output) = run_child("previous_hook_is_chained", || {
+        std::panic::set_hook(Box::new(|_| eprintln!("previous hook")));
+        let _ = catch_panic(|| panic!("expected"));
+    }) else {
+        return;
+    };
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("previous hook"), "stderr: {}", stderr);
+}
+
+#[test]
+fn catch_mode_propagates_other_panics() {
+    let _global = GLOBAL.lock().unwrap();
+    assert_eq!(catch_mode(), CatchMode::AbortOnPanic);
+    for mode in [CatchMode::ResumeUnwind, CatchMode::ReturnError] {
+        set_catch_mode(mode);
+        assert_eq!(catch_mode(), mode);
+        let payload = std::panic::catch_unwind(|| catch_oom(|| panic!("expected"))).unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"expected"));
+        assert!(catch_oom(|| handle_alloc_error(layout(8))).is_err());
+    }
+    set_catch_mode(CatchMode::AbortOnPanic);
+}
+
+#[test]
+fn replaced_hook_is_reinstalled() {
+    let _global = GLOBAL.lock().unwrap();
+    panic_safe::init();
+    assert!(verify_hook().is_ok());
+    std::panic::set_hook(Box::new(|_| {}));
+    assert!(verify_hook().is_err());
+    assert!(verify_hook().is_ok());
+    assert!(catch_oom(|| handle_alloc_error(layout(8))).is_err());
+}
+
+#[test]
+fn uninstalled_hooks_are_installed_again() {
+    let _global = GLOBAL.lock().unwrap();
+    panic_safe::uninstall();
+    assert!(catch_oom(|| handle_alloc_error(layout(8))).is_err());
+    drop(install_scoped());
+    assert!(catch_oom(|| handle_alloc_error(layout(8))).is_err());
+}
+
+#[test]
+fn emergency_reserve_is_kept() {
+    let _global = GLOBAL.lock().unwrap();
+    set_emergency_reserve(4096);
+    assert_eq!(emergency_reserve(), 4096);
+    assert!(catch_oom(|| handle_alloc_error(layout(8))).is_err());
+    assert_eq!(catch_oom(|| vec![1u8; 64].len()).unwrap(), 64);
+    set_emergency_reserve(0);
+}
+
+#[test]
+fn listeners_are_notified() {
+    static NOTIFIED: AtomicUsize = AtomicUsize::new(0);
+    register_low_memory_listener(|layout| {
+        if layout.size() == 4097 {
+            NOTIFIED.fetch_add(1, Ordering::Relaxed);
+        }
+        0
+    });
+    assert!(catch_oom(|| handle_alloc_error(layout(4097))).is_err());
+    assert_eq!(NOTIFIED.load(Ordering::Relaxed), 1);
+}
+
+static FREED: AtomicUsize = AtomicUsize::new(0);
+static OBSERVED: AtomicUsize = AtomicUsize::new(0);
+
+fn observe(info: &AllocErrorInfo<'_>) {
+    if info.error().size() == 4099 {
+        OBSERVED.fetch_add(1, Ordering::Relaxed);
+        FREED.fetch_add(info.freed(), Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn observers_are_notified() {
+    register_low_memory_listener(|layout| if layout.size() == 4099 { 100 } else { 0 });
+    register_oom_observer(observe);
+    assert!(catch_oom(|| handle_alloc_error(layout(4099))).is_err());
+    assert_eq!(OBSERVED.load(Ordering::Relaxed), 1);
+    assert_eq!(FREED.load(Ordering::Relaxed), 100);
+}
+
+fn format_message(w: &mut dyn Write, layout: Layout) -> std::fmt::Result {
+    write!(w, "E1001: out of memory ({} bytes)", layout.size())
+}
+
+#[test]
+fn oom_message_is_printed() {
+    let Some(output) = run_child("oom_message_is_printed", || {
+        set_oom_message(OomMessage::Static("E1000: out of memory"));
+        let _ = catch_oom(|| handle_alloc_error(layout(8)));
+        set_oom_message(OomMessage::Format(format_message));
+        let _ = catch_oom(|| handle_alloc_error(layout(16)));
+        set_oom_message(OomMessage::Default);
+        let _ = catch_oom(|| handle_alloc_error(layout(32)));
+    }) else {
+        return;
+    };
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("E1000: out of memory"), "stderr: {}", stderr);
+    assert!(stderr.contains("E1001: out of memory (16 bytes)"), "stderr: {}", stderr);
+    assert!(
+        stderr.contains("memory allocation of 32 bytes failed"),
+        "stderr: {}",
+        stderr
+    );
+}
+
+#[test]
+fn quiet_oom_is_not_printed() {
+    let Some(output) = run_child("quiet_oom_is_not_printed", || {
+        set_quiet_oom(true);
+        assert!(quiet_oom());
+        let _ = catch_oom(|| handle_alloc_error(layout(8)));
+    }) else {
+        return;
+    };
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("memory allocation"), "stderr: {}", stderr);
+}
@@ -0,0 +1,53 @@
+#![cfg(all(target_pointer_width = "64", any(target_os = "linux", target_os = "macos")))]
+
+use panic_safe::{catch_oom_isolated, IsolatedError};
+
+#[cfg(feature = "stable")]
+#[global_allocator]
+static GLOBAL: panic_safe::CatchAlloc<std::alloc::System> = panic_safe::CatchAlloc::new(std::alloc::System);
+
+#[test]
+fn result_is_returned_from_child() {
+    let output = catch_oom_isolated(None, || b"rendered".to_vec()).unwrap();
+    assert_eq!(output, b"rendered");
+}
+
+#[test]
+fn allocation_error_in_child_is_returned() {
+    let e = catch_oom_isolated(Some(1 << 34), || vec![1u8; 1 << 35]).unwrap_err();
+    let IsolatedError::Oom(e) = e else {
+        panic!("unexpected error: {}", e);
+    };
+    assert_eq!(e.size(), 1 << 35);
+}
+
+#[test]
+fn abort_in_child_is_crashed() {
+    let e = catch_oom_isolated(None, || std::process::abort()).unwrap_err();
+    let IsolatedError::Crashed(status) = e else {
+        panic!("unexpected error: {}", e);
+    };
+    assert!(!status.success());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pipe_is_closed_on_exec() {
+    let output = catch_oom_isolated(None, || {
+        let mut open = Vec::new();
+        for entry in std::fs::read_dir("/proc/self/fd").unwrap() {
+            let fd = entry.unwrap().file_name().into_string().unwrap();
+            let target = std::fs::read_link(format!("/proc/self/fd/{}", fd)).unwrap_or_default();
+            if fd.parse::<u32>().unwrap() > 2 && target.to_string_lossy().starts_with("pipe:") {
+                let info = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", fd)).unwrap();
+                let flags = info.lines().find_map(|line| line.strip_prefix("flags:")).unwrap();
+                let flags = u32::from_str_radix(flags.trim(), 8).unwrap();
+                open.push(flags & 0o2000000 != 0);
+            }
+        }
+        open.into_iter().map(u8::from).collect()
+    })
+    .unwrap();
+    assert!(!output.is_empty());
+    assert!(output.iter().all(|&cloexec| cloexec == 1), "{:?}", output);
+}
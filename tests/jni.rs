@@ -0,0 +1,72 @@
+#![cfg(all(feature = "jni", not(feature = "stable")))]
+
+use std::alloc::{handle_alloc_error, Layout};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr};
+
+use panic_safe::catch_oom_jni;
+
+const FIND_CLASS: usize = 6;
+const THROW_NEW: usize = 14;
+const DELETE_LOCAL_REF: usize = 23;
+const EXCEPTION_CHECK: usize = 228;
+
+thread_local! {
+    static THROWN: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+    static PENDING: RefCell<bool> = const { RefCell::new(false) };
+}
+
+static CLASS: u8 = 0;
+
+unsafe extern "system" fn find_class(_: *mut c_void, name: *const c_char) -> *mut c_void {
+    assert_eq!(CStr::from_ptr(name).to_str().unwrap(), "java/lang/OutOfMemoryError");
+    (&raw const CLASS).cast_mut().cast()
+}
+
+unsafe extern "system" fn throw_new(_: *mut c_void, class: *mut c_void, message: *const c_char) -> i32 {
+    assert_eq!(class.cast_const(), (&raw const CLASS).cast());
+    let message = CStr::from_ptr(message).to_string_lossy().into_owned();
+    THROWN.with(|thrown| thrown.borrow_mut().push(("OutOfMemoryError".to_owned(), message)));
+    0
+}
+
+unsafe extern "system" fn delete_local_ref(_: *mut c_void, _: *mut c_void) {}
+
+unsafe extern "system" fn exception_check(_: *mut c_void) -> u8 {
+    PENDING.with(|pending| u8::from(*pending.borrow()))
+}
+
+/// A `JNIEnv` whose function table records the thrown errors.
+struct FakeEnv {
+    functions: Box<[*const c_void; 229]>,
+}
+
+impl FakeEnv {
+    fn new() -> Self {
+        let mut functions = Box::new([std::ptr::null(); 229]);
+        functions[FIND_CLASS] = find_class as *const c_void;
+        functions[THROW_NEW] = throw_new as *const c_void;
+        functions[DELETE_LOCAL_REF] = delete_local_ref as *const c_void;
+        functions[EXCEPTION_CHECK] = exception_check as *const c_void;
+        FakeEnv { functions }
+    }
+}
+
+#[test]
+fn allocation_error_is_thrown_to_java() {
+    let env = FakeEnv::new();
+    let mut table = env.functions.as_ptr();
+    let raw: *mut c_void = (&raw mut table).cast();
+
+    assert_eq!(unsafe { catch_oom_jni(raw, || 1) }.unwrap(), 1);
+    let e = unsafe { catch_oom_jni::<_, ()>(raw, || handle_alloc_error(Layout::new::<[u64; 5]>())) }.unwrap_err();
+    assert_eq!(e.size(), 40);
+    let thrown = THROWN.with(|thrown| thrown.take());
+    assert_eq!(thrown.len(), 1);
+    assert!(thrown[0].1.contains("40"), "message: {}", thrown[0].1);
+
+    // A pending exception is kept.
+    PENDING.with(|pending| *pending.borrow_mut() = true);
+    assert!(unsafe { catch_oom_jni::<_, ()>(raw, || handle_alloc_error(Layout::new::<u64>())) }.is_err());
+    assert!(THROWN.with(|thrown| thrown.take()).is_empty());
+}
@@ -0,0 +1,65 @@
+use std::alloc::{Layout, System};
+use std::hint::black_box;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use panic_safe::{
+    catch_oom, current_usage, max_alloc_size, set_large_alloc_tracer, set_max_alloc_size, set_strict_memory_mode,
+    strict_memory_mode, TrackingAlloc,
+};
+
+#[cfg(not(feature = "stable"))]
+#[global_allocator]
+static GLOBAL: TrackingAlloc<System> = TrackingAlloc::new(System);
+#[cfg(feature = "stable")]
+#[global_allocator]
+static GLOBAL: panic_safe::CatchAlloc<TrackingAlloc<System>> = panic_safe::CatchAlloc::new(TrackingAlloc::new(System));
+
+/// Serializes the tests, as the limits apply to the whole process.
+static LIMITS: Mutex<()> = Mutex::new(());
+
+#[test]
+fn max_alloc_size_fails_larger_allocations() {
+    let _limits = LIMITS.lock().unwrap();
+    assert_eq!(set_max_alloc_size(Some(1 << 20)), None);
+    assert_eq!(max_alloc_size(), Some(1 << 20));
+    let e = catch_oom(|| black_box(Vec::<u8>::with_capacity(2 << 20))).unwrap_err();
+    assert_eq!(e.size(), 2 << 20);
+    assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(1 << 19))).is_ok());
+    set_max_alloc_size(None);
+}
+
+#[test]
+fn strict_mode_fails_allocations_over_the_ceiling() {
+    let _limits = LIMITS.lock().unwrap();
+    assert_eq!(set_strict_memory_mode(Some(current_usage() + (1 << 20))), None);
+    assert!(strict_memory_mode().is_some());
+    let e = catch_oom(|| black_box(Vec::<u8>::with_capacity(4 << 20))).unwrap_err();
+    assert_eq!(e.size(), 4 << 20);
+    set_strict_memory_mode(None);
+    assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(4 << 20))).is_ok());
+}
+
+static TRACED: AtomicUsize = AtomicUsize::new(0);
+static TRACED_LINE: AtomicUsize = AtomicUsize::new(0);
+
+fn trace(layout: Layout, location: &'static Location<'static>) {
+    if layout.size() == 3 << 20 {
+        TRACED.fetch_add(1, Ordering::Relaxed);
+        TRACED_LINE.store(location.line() as usize, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn large_allocations_in_scopes_are_traced() {
+    let _limits = LIMITS.lock().unwrap();
+    assert!(set_large_alloc_tracer(1 << 20, Some(trace)).is_none());
+    black_box(Vec::<u8>::with_capacity(3 << 20));
+    assert_eq!(TRACED.load(Ordering::Relaxed), 0);
+    let line = line!() + 1;
+    catch_oom(|| black_box(Vec::<u8>::with_capacity(3 << 20))).unwrap();
+    assert_eq!(TRACED.load(Ordering::Relaxed), 1);
+    assert_eq!(TRACED_LINE.load(Ordering::Relaxed), line as usize);
+    assert!(set_large_alloc_tracer(usize::MAX, None).is_some());
+}
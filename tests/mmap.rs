@@ -0,0 +1,45 @@
+#![cfg(all(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+
+use std::ffi::{c_int, c_void};
+use std::fs::File;
+use std::hint::black_box;
+use std::os::fd::AsRawFd;
+
+use panic_safe::catch_mmap_fault;
+
+const PROT_READ: c_int = 1;
+const MAP_SHARED: c_int = 1;
+const LEN: usize = 64 * 1024;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+#[test]
+fn truncated_file_faults_are_caught() {
+    let path = std::env::temp_dir().join(format!("panic-safe-mmap-{}", std::process::id()));
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    file.set_len(LEN as u64).unwrap();
+    let ptr = unsafe { mmap(std::ptr::null_mut(), LEN, PROT_READ, MAP_SHARED, file.as_raw_fd(), 0) };
+    assert_ne!(ptr as isize, -1);
+    let region = unsafe { std::slice::from_raw_parts(ptr.cast::<u8>(), LEN) };
+    assert_eq!(catch_mmap_fault(region, || black_box(region[0])).unwrap(), 0);
+
+    file.set_len(0).unwrap();
+    let e = catch_mmap_fault(region, || black_box(region[LEN / 2])).unwrap_err();
+    assert_eq!(e.address(), ptr as usize + LEN / 2);
+    assert_eq!(e.location().file(), file!());
+
+    unsafe { munmap(ptr, LEN) };
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,25 @@
+#![cfg(target_os = "linux")]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use panic_safe::{low_memory, register_memory_pressure_listener, watch_memory_pressure};
+
+#[test]
+fn listeners_are_notified_on_both_transitions() {
+    let transitions = Arc::new(Mutex::new(Vec::new()));
+    register_memory_pressure_listener({
+        let transitions = Arc::clone(&transitions);
+        move |low| transitions.lock().unwrap().push(low)
+    });
+    // Any pressure crosses the threshold of 0%.
+    let Ok(watcher) = watch_memory_pressure(0.0, Duration::from_millis(10), |_| {}) else {
+        return;
+    };
+    while !low_memory() {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    drop(watcher);
+    assert!(!low_memory());
+    assert_eq!(*transitions.lock().unwrap(), [true, false]);
+}
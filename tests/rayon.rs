@@ -0,0 +1,40 @@
+#![cfg(all(feature = "rayon", not(feature = "stable")))]
+
+use std::alloc::{handle_alloc_error, Layout};
+use std::panic::AssertUnwindSafe;
+
+use panic_safe::{catch_oom, install_catch_oom, AllocError};
+use rayon::ThreadPoolBuilder;
+
+#[test]
+fn catches_in_pool() {
+    let pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+    let sum = install_catch_oom(&pool, || rayon::join(|| 1, || 2)).unwrap();
+    assert_eq!(sum, (1, 2));
+
+    // The error raised on another worker is transported to the scope.
+    let e = install_catch_oom(&pool, || {
+        rayon::join(|| (), || handle_alloc_error(Layout::new::<[u8; 24]>()))
+    })
+    .unwrap_err();
+    assert_eq!(e.layout(), Layout::new::<[u8; 24]>());
+}
+
+#[test]
+fn payload_transport_is_restored() {
+    let pool = ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+    // Checks if an out-of-memory panic on a worker carries the allocation error.
+    let transported = || {
+        pool.broadcast(|_| {
+            catch_oom(|| {
+                std::panic::catch_unwind(|| handle_alloc_error(Layout::new::<[u8; 24]>()))
+                    .unwrap_err()
+                    .is::<AllocError>()
+            })
+            .unwrap()
+        })
+    };
+    assert_eq!(transported(), [false, false]);
+    install_catch_oom(&pool, AssertUnwindSafe(|| assert_eq!(transported(), [true, true]))).unwrap();
+    assert_eq!(transported(), [false, false]);
+}
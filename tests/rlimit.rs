@@ -0,0 +1,16 @@
+#![cfg(all(target_pointer_width = "64", any(target_os = "linux", target_os = "macos")))]
+
+use std::hint::black_box;
+
+use panic_safe::{catch_oom, with_address_space_limit};
+
+#[cfg(feature = "stable")]
+#[global_allocator]
+static GLOBAL: panic_safe::CatchAlloc<std::alloc::System> = panic_safe::CatchAlloc::new(std::alloc::System);
+
+// The limit applies to the whole process, so it is checked in one test.
+#[test]
+fn allocations_over_the_limit_fail() {
+    let result = with_address_space_limit(4 << 30, || catch_oom(|| black_box(vec![0u8; 8 << 30]).len())).unwrap();
+    assert_eq!(result.unwrap_err().size(), 8 << 30);
+}
@@ -0,0 +1,83 @@
+#![cfg(feature = "serde")]
+
+use std::alloc::Layout;
+use std::panic::resume_unwind;
+
+use panic_safe::{catch_any, AllocError, AllocErrorKind, CaughtError, FailureTrace, PanicLocation};
+use serde_json::json;
+
+#[test]
+fn alloc_error_restores_the_layout() {
+    let e = AllocError::new(Layout::from_size_align(48, 16).unwrap());
+    let value = serde_json::to_value(&e).unwrap();
+    assert_eq!(value["size"], 48);
+    assert_eq!(value["align"], 16);
+
+    let restored: AllocError = serde_json::from_value(value).unwrap();
+    assert_eq!(restored.layout(), e.layout());
+    assert!(serde_json::from_value::<AllocError>(json!({ "size": 8, "align": 3 })).is_err());
+}
+
+#[test]
+fn alloc_error_restores_the_kind() {
+    let value = serde_json::to_value(AllocError::capacity_overflow()).unwrap();
+    assert_eq!(value["kind"], "CapacityOverflow");
+    let restored: AllocError = serde_json::from_value(value).unwrap();
+    assert_eq!(restored.kind(), AllocErrorKind::CapacityOverflow);
+
+    // The kind defaults to exhausted memory.
+    let restored: AllocError = serde_json::from_value(json!({ "size": 8, "align": 8 })).unwrap();
+    assert_eq!(restored.kind(), AllocErrorKind::Exhausted);
+}
+
+#[test]
+fn alloc_error_keeps_the_context() {
+    let e = AllocError::new(Layout::new::<u64>()).with_context("parse");
+    assert_eq!(serde_json::to_value(&e).unwrap()["context"], "parse");
+}
+
+#[test]
+fn invalid_layout_kind_round_trips() {
+    let e = AllocError::invalid_layout(Layout::from_size_align(48, 16).unwrap());
+    let value = serde_json::to_value(&e).unwrap();
+    assert_eq!(value["kind"], "InvalidLayout");
+    let restored: AllocError = serde_json::from_value(value).unwrap();
+    assert_eq!((restored.size(), restored.align()), (48, 16));
+    assert_eq!(restored.kind(), AllocErrorKind::InvalidLayout);
+}
+
+#[test]
+fn traces_round_trip() {
+    let trace = FailureTrace::new(3, Layout::new::<u64>());
+    let json = serde_json::to_string(&trace).unwrap();
+    assert_eq!(serde_json::from_str::<FailureTrace>(&json).unwrap(), trace);
+}
+
+#[test]
+fn locations_round_trip() {
+    let value = json!({ "file": "src/lib.rs", "line": 7, "column": 9 });
+    let location: PanicLocation = serde_json::from_value(value.clone()).unwrap();
+    assert_eq!(
+        (location.file(), location.line(), location.column()),
+        ("src/lib.rs", 7, 9)
+    );
+    assert_eq!(serde_json::to_value(&location).unwrap(), value);
+}
+
+#[test]
+fn caught_errors_round_trip() {
+    let e = catch_any(|| -> () { resume_unwind(Box::new(String::from("expected"))) }).unwrap_err();
+    let value = serde_json::to_value(&e).unwrap();
+    assert_eq!(value["Panic"]["message"], "expected");
+    match serde_json::from_value::<CaughtError>(value).unwrap() {
+        CaughtError::Panic(e) => assert_eq!(e.message(), Some("expected")),
+        CaughtError::Oom(e) => panic!("unexpected {}", e),
+    }
+
+    let e = CaughtError::Oom(AllocError::new(Layout::new::<u32>()));
+    let value = serde_json::to_value(&e).unwrap();
+    match serde_json::from_value::<CaughtError>(value).unwrap() {
+        CaughtError::Oom(e) => assert_eq!(e.layout(), Layout::new::<u32>()),
+        CaughtError::Panic(e) => panic!("unexpected {:?}", e.message()),
+    }
+}
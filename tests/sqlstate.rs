@@ -0,0 +1,23 @@
+#![cfg(all(feature = "sqlstate", not(feature = "stable")))]
+
+use std::alloc::{handle_alloc_error, Layout};
+
+use panic_safe::{catch_oom, set_vendor_code, AllocError, AllocErrorKind};
+
+#[test]
+fn errors_map_to_sqlstates() {
+    assert_eq!(AllocErrorKind::Exhausted.sqlstate(), "53200");
+    assert_eq!(AllocErrorKind::CapacityOverflow.sqlstate(), "54000");
+    assert_eq!(AllocErrorKind::InvalidLayout.sqlstate(), "54000");
+    assert_eq!(AllocErrorKind::UnexpectedPanic.sqlstate(), "XX000");
+
+    let e = catch_oom(|| handle_alloc_error(Layout::new::<u64>())).unwrap_err();
+    assert_eq!(e.sqlstate(), "53200");
+    assert_eq!(AllocError::capacity_overflow().sqlstate(), "54000");
+
+    assert_eq!(e.vendor_code(), None);
+    set_vendor_code(1037);
+    assert_eq!(e.vendor_code(), Some(1037));
+    set_vendor_code(0);
+    assert_eq!(e.vendor_code(), None);
+}
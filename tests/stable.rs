@@ -0,0 +1,16 @@
+#![cfg(feature = "stable")]
+
+use std::alloc::{Layout, System};
+use std::hint::black_box;
+
+use panic_safe::{catch_oom, CatchAlloc};
+
+#[global_allocator]
+static GLOBAL: CatchAlloc<System> = CatchAlloc::new(System);
+
+#[test]
+fn failed_allocation_is_caught() {
+    let e = catch_oom(|| black_box(Vec::<u8>::with_capacity(1 << 62))).unwrap_err();
+    assert_eq!(e.layout(), Layout::array::<u8>(1 << 62).unwrap());
+    assert_eq!(catch_oom(|| black_box(vec![1u8; 64]).len()).unwrap(), 64);
+}
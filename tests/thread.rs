@@ -0,0 +1,175 @@
+// The allocation errors are raised by `handle_alloc_error`, which is not hooked
+// with the `stable` feature.
+#![cfg(not(feature = "stable"))]
+
+use std::alloc::{handle_alloc_error, Layout};
+use std::cell::Cell;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::pin;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc};
+use std::task::{Context, Poll, Wake, Waker};
+
+use panic_safe::{
+    catch_oom, catch_oom_future, catch_oom_scoped, catch_oom_with_stack, remaining_stack, spawn, spawn_propagating,
+    spawn_with_stack, ErrorScope, PanicSafeThreadPool,
+};
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+fn oom_unless(ok: bool, value: usize) -> usize {
+    if !ok {
+        handle_alloc_error(layout(value));
+    }
+    value
+}
+
+#[test]
+fn spawned_thread_returns_the_error() {
+    assert_eq!(spawn(|| 1).join().unwrap().unwrap(), 1);
+    let e = spawn(|| oom_unless(false, 40)).join().unwrap().unwrap_err();
+    assert_eq!(e.layout(), layout(40));
+}
+
+#[test]
+fn scoped_jobs_return_their_results_in_order() {
+    let base = [10, 20, 30];
+    let results = catch_oom_scoped(base.iter().map(|&n| move || oom_unless(n != 20, n)));
+    assert_eq!(results.len(), 3);
+    assert_eq!(*results[0].as_ref().unwrap(), 10);
+    assert_eq!(results[1].as_ref().unwrap_err().layout(), layout(20));
+    assert_eq!(*results[2].as_ref().unwrap(), 30);
+}
+
+#[test]
+fn propagating_thread_raises_in_the_joining_scope() {
+    let e = catch_oom(|| spawn_propagating(|| oom_unless(false, 56)).join()).unwrap_err();
+    assert_eq!(e.layout(), layout(56));
+    let handle = spawn_propagating(|| 2);
+    assert_eq!(catch_oom(std::panic::AssertUnwindSafe(|| handle.join())).unwrap(), 2);
+}
+
+#[test]
+fn pool_jobs_return_their_results() {
+    let pool = PanicSafeThreadPool::new(2).unwrap();
+    assert_eq!(pool.size(), 2);
+    let ok = pool.submit(|| 3);
+    let oom = pool.submit(|| oom_unless(false, 64));
+    assert_eq!(ok.join().unwrap(), 3);
+    assert_eq!(oom.join().unwrap_err().layout(), layout(64));
+
+    let (sender, receiver) = mpsc::channel();
+    for n in 1..=4 {
+        pool.execute(move || n, sender.clone());
+    }
+    drop(sender);
+    let mut results: Vec<_> = receiver.iter().map(Result::unwrap).collect();
+    results.sort_unstable();
+    assert_eq!(results, [1, 2, 3, 4]);
+}
+
+fn depth(n: usize) -> usize {
+    if n == 0 {
+        0
+    } else {
+        // Keep a frame of stack alive across the recursion.
+        let frame = std::hint::black_box([0u8; 512]);
+        catch_oom_with_stack(32 * 1024, 1024 * 1024, || depth(n - 1)).unwrap() + 1 + usize::from(frame[0])
+    }
+}
+
+#[test]
+fn deep_recursion_grows_the_stack() {
+    assert!(remaining_stack().is_some_and(|remaining| remaining > 0));
+    let result = std::thread::Builder::new()
+        .stack_size(128 * 1024)
+        .spawn(|| depth(2000))
+        .unwrap()
+        .join()
+        .unwrap();
+    assert_eq!(result, 2000);
+}
+
+#[test]
+fn new_stack_returns_the_error() {
+    assert_eq!(spawn_with_stack(1 << 20, || 5).unwrap(), 5);
+    assert_eq!(
+        spawn_with_stack(1 << 20, || oom_unless(false, 80)).unwrap_err().size(),
+        80
+    );
+}
+
+#[cfg(all(
+    any(target_arch = "x86_64", target_arch = "aarch64"),
+    any(target_os = "linux", target_os = "macos")
+))]
+#[test]
+fn grown_stack_stays_on_the_thread() {
+    let thread = std::thread::current().id();
+    let calls = Rc::new(Cell::new(0));
+    let result = catch_oom_with_stack(
+        usize::MAX,
+        1 << 20,
+        AssertUnwindSafe(|| {
+            calls.set(calls.get() + 1);
+            assert!(remaining_stack().is_some_and(|remaining| remaining <= 1 << 20));
+            std::thread::current().id()
+        }),
+    );
+    assert_eq!(result.unwrap(), thread);
+    assert_eq!(calls.get(), 1);
+}
+
+struct NoopWaker;
+
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Polls a future which does not wait on anything to completion.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// A future which is pending once and then runs `f`.
+struct YieldThen<F>(Option<F>, bool);
+
+impl<F: FnOnce() -> R + Unpin, R> Future for YieldThen<F> {
+    type Output = R;
+
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<R> {
+        if !self.1 {
+            self.1 = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Poll::Ready(self.0.take().unwrap()())
+    }
+}
+
+#[test]
+fn future_returns_the_error_of_any_poll() {
+    assert_eq!(block_on(catch_oom_future(YieldThen(Some(|| 6), false))).unwrap(), 6);
+    let e = block_on(catch_oom_future(YieldThen(Some(|| oom_unless(false, 88)), false))).unwrap_err();
+    assert_eq!(e.layout(), layout(88));
+}
+
+#[test]
+fn error_scope_travels_with_the_future() {
+    panic_safe::init();
+    let scope = ErrorScope::new();
+    let future = scope.wrap(YieldThen(Some(|| oom_unless(false, 96)), false));
+    let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| block_on(future))).unwrap_err();
+    drop(payload);
+    assert_eq!(scope.take_error().unwrap().layout(), layout(96));
+}
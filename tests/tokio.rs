@@ -0,0 +1,19 @@
+#![cfg(all(feature = "tokio", not(feature = "stable")))]
+
+use std::alloc::{handle_alloc_error, Layout};
+
+use panic_safe::catch_oom_blocking;
+use tokio::runtime::Builder;
+
+#[test]
+fn catches_on_blocking_pool() {
+    let runtime = Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        assert_eq!(catch_oom_blocking(|| 1).await.unwrap().unwrap(), 1);
+        let e = catch_oom_blocking(|| handle_alloc_error(Layout::new::<[u8; 24]>()))
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(e.layout(), Layout::new::<[u8; 24]>());
+    });
+}
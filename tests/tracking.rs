@@ -0,0 +1,286 @@
+#![cfg_attr(not(feature = "stable"), feature(allocator_api))]
+
+use std::alloc::System;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use panic_safe::{
+    assert_no_alloc, catch_oom, catch_oom_report, catch_oom_tagged, catch_panic, current_usage, heap_histogram,
+    peak_usage, reset_thread_peak_usage, scope_high_water_mark, set_heap_histogram_reporter, set_thread_alloc_limit,
+    spawn_with_stack, tagged_usage, thread_alloc_limit, thread_current_usage, thread_peak_usage, AllocError,
+    HeapHistogram, MemoryBudget, TrackingAlloc,
+};
+
+#[cfg(not(feature = "stable"))]
+use panic_safe::MemoryContext;
+
+#[cfg(not(feature = "stable"))]
+#[global_allocator]
+static GLOBAL: TrackingAlloc<System> = TrackingAlloc::new(System);
+#[cfg(feature = "stable")]
+#[global_allocator]
+static GLOBAL: panic_safe::CatchAlloc<TrackingAlloc<System>> = panic_safe::CatchAlloc::new(TrackingAlloc::new(System));
+
+/// Allocates on drop, e.g. while unwinding from an allocation error.
+struct AllocOnDrop(usize);
+
+impl Drop for AllocOnDrop {
+    fn drop(&mut self) {
+        black_box(Vec::<u8>::with_capacity(self.0));
+    }
+}
+
+#[test]
+fn budget_is_kept_across_exempt_allocations() {
+    let budget = MemoryBudget::new(16 * 1024);
+    // The first caught error initializes the thread state kept in the budget.
+    // The errors are dropped in the budget, as their backtraces are debited from it.
+    let e = budget
+        .catch_oom(|| black_box(Vec::<u8>::with_capacity(32 * 1024)))
+        .unwrap_err();
+    assert_eq!(e.suppressed(), None);
+    budget.enter(|| drop(e));
+    let kept = budget.enter(|| Vec::<u8>::with_capacity(2048));
+    let used = budget.used();
+
+    let size = budget.remaining() + 1;
+    let e = budget
+        .catch_oom(|| {
+            let _guard = AllocOnDrop(size);
+            black_box(Vec::<u8>::with_capacity(size));
+        })
+        .unwrap_err();
+    assert_eq!(e.size(), size);
+    assert_eq!(e.suppressed(), Some(size));
+    budget.enter(|| drop(e));
+    assert_eq!(budget.used(), used);
+
+    budget.enter(|| drop(kept));
+    assert_eq!(budget.used(), used - 2048);
+}
+
+#[cfg(feature = "backtrace")]
+#[test]
+fn backtrace_capture_exceeds_budget() {
+    // Capturing the backtrace exceeds the budget rather than aborting.
+    let budget = MemoryBudget::new(512);
+    let e = budget
+        .catch_oom(|| black_box(Vec::<u8>::with_capacity(1024)))
+        .unwrap_err();
+    assert_eq!(e.size(), 1024);
+    budget.enter(|| drop(e));
+}
+
+#[test]
+fn new_stack_carries_thread_state() {
+    let budget = MemoryBudget::new(4096);
+    let e = budget
+        .catch_oom(|| spawn_with_stack(1 << 20, || black_box(Vec::<u8>::with_capacity(8192))))
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(e.size(), 8192);
+
+    catch_oom_tagged("new_stack", || {
+        spawn_with_stack(1 << 20, || black_box(Vec::<u8>::with_capacity(8192))).unwrap();
+    })
+    .unwrap();
+    let usage = tagged_usage();
+    assert!(usage.iter().any(|&(tag, bytes)| tag == "new_stack" && bytes >= 8192));
+
+    let previous = set_thread_alloc_limit(Some(thread_current_usage() + 4096));
+    let kept = Vec::<u8>::with_capacity(2048);
+    let e = spawn_with_stack(1 << 20, || black_box(Vec::<u8>::with_capacity(3072))).unwrap_err();
+    set_thread_alloc_limit(previous);
+    assert_eq!(e.size(), 3072);
+    drop(kept);
+}
+
+/// Runs `f` in a new thread, so its usage counters start from 0.
+fn in_new_thread<F: FnOnce() + Send + 'static>(f: F) {
+    std::thread::spawn(f).join().unwrap();
+}
+
+#[test]
+fn usage_is_counted() {
+    in_new_thread(|| {
+        let before = thread_current_usage();
+        let kept = black_box(Vec::<u8>::with_capacity(1000));
+        assert_eq!(thread_current_usage(), before + 1000);
+        assert!(thread_peak_usage() >= before + 1000);
+        assert!(current_usage() >= 1000);
+        assert!(peak_usage() >= current_usage());
+        drop(kept);
+        assert_eq!(thread_current_usage(), before);
+        reset_thread_peak_usage();
+        assert_eq!(thread_peak_usage(), before);
+    });
+}
+
+#[test]
+fn thread_limit_fails_allocations() {
+    in_new_thread(|| {
+        assert_eq!(set_thread_alloc_limit(Some(thread_current_usage() + 4096)), None);
+        assert!(thread_alloc_limit().is_some());
+        let e = catch_oom(|| black_box(Vec::<u8>::with_capacity(8192))).unwrap_err();
+        assert_eq!(e.size(), 8192);
+        assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(1024))).is_ok());
+        set_thread_alloc_limit(None);
+        assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(8192))).is_ok());
+    });
+}
+
+#[test]
+fn failure_while_unwinding_is_suppressed() {
+    in_new_thread(|| {
+        set_thread_alloc_limit(Some(thread_current_usage() + 4096));
+        let e = catch_oom(|| {
+            let _guard = AllocOnDrop(8192);
+            black_box(Vec::<u8>::with_capacity(8192));
+        })
+        .unwrap_err();
+        set_thread_alloc_limit(None);
+        assert_eq!(e.size(), 8192);
+        assert_eq!(e.suppressed(), Some(8192));
+    });
+}
+
+#[test]
+fn budget_is_shared_by_threads() {
+    let budget = MemoryBudget::new(8192);
+    let kept = budget.enter(|| black_box(Vec::<u8>::with_capacity(6000)));
+    assert_eq!(budget.used(), 6000);
+    assert_eq!(budget.remaining(), 2192);
+    let other = budget.clone();
+    let e = std::thread::spawn(move || other.catch_oom(|| black_box(Vec::<u8>::with_capacity(4000))))
+        .join()
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(e.size(), 4000);
+    budget.enter(|| drop(kept));
+    assert!(budget.catch_oom(|| black_box(Vec::<u8>::with_capacity(4000))).is_ok());
+}
+
+#[test]
+fn report_counts_the_closure() {
+    in_new_thread(|| {
+        let (result, report) = catch_oom_report(|| {
+            let a = black_box(Vec::<u8>::with_capacity(1000));
+            drop(a);
+            black_box(Vec::<u8>::with_capacity(500)).capacity()
+        });
+        assert_eq!(result.unwrap(), 500);
+        assert!(report.allocated_bytes() >= 1500);
+        assert!(report.peak_bytes() >= 1000);
+        // The scope may allocate the memory reserved to capture backtraces.
+        #[cfg(not(feature = "backtrace"))]
+        assert!(report.peak_bytes() < 1500);
+        assert!(report.allocation_count() >= 2);
+
+        let (result, report) = catch_oom_report(|| {
+            let _kept = black_box(Vec::<u8>::with_capacity(700));
+            black_box(Vec::<u8>::with_capacity(isize::MAX as usize / 2))
+        });
+        assert!(result.is_err());
+        assert!(report.allocated_bytes() >= 700);
+    });
+}
+
+#[test]
+fn high_water_mark_is_per_scope() {
+    in_new_thread(|| {
+        assert_eq!(scope_high_water_mark(), None);
+        catch_oom(|| {
+            drop(black_box(Vec::<u8>::with_capacity(3000)));
+            assert!(scope_high_water_mark().unwrap() >= 3000);
+            catch_oom(|| assert!(scope_high_water_mark().unwrap() < 3000)).unwrap();
+        })
+        .unwrap();
+    });
+}
+
+#[test]
+fn tags_attribute_to_the_innermost_scope() {
+    in_new_thread(|| {
+        // The first scope may allocate the memory reserved to capture backtraces.
+        catch_oom(|| ()).unwrap();
+        catch_oom_tagged("outer", || {
+            black_box(Vec::<u8>::with_capacity(100));
+            catch_oom_tagged("inner", || black_box(Vec::<u8>::with_capacity(10000))).unwrap();
+        })
+        .unwrap();
+        let usage = tagged_usage();
+        let bytes = |name| usage.iter().find(|&&(tag, _)| tag == name).unwrap().1;
+        assert!(bytes("inner") >= 10000);
+        assert!(bytes("outer") >= 100 && bytes("outer") < 10000);
+    });
+}
+
+#[test]
+fn no_alloc_scope_denies_allocations() {
+    assert_eq!(assert_no_alloc(|| 1 + 1).unwrap(), 2);
+    let samples = [1u32; 4];
+    assert_eq!(assert_no_alloc(|| samples.iter().sum::<u32>()).unwrap(), 4);
+    if cfg!(debug_assertions) {
+        let e = catch_panic(|| assert_no_alloc(|| black_box(vec![1u8; 8]))).unwrap_err();
+        assert!(e.message().unwrap().contains("no-alloc scope"));
+    }
+}
+
+#[test]
+fn histogram_counts_live_allocations() {
+    let kept: Vec<_> = (0..10).map(|_| black_box(Vec::<u8>::with_capacity(3000))).collect();
+    let histogram = heap_histogram();
+    assert!(histogram.count() >= 11);
+    assert!(histogram.bytes() >= 30000);
+    assert!(histogram.classes().any(|(max, count, _)| max >= 3000 && count >= 10));
+    drop(kept);
+}
+
+#[cfg(not(feature = "stable"))]
+#[test]
+fn memory_context_is_reset_on_failure() {
+    let mut context = MemoryContext::new("query", Some(64 * 1024));
+    let child = context.child("sort", None);
+    assert_eq!(
+        (context.name(), context.limit(), child.limit()),
+        ("query", Some(64 * 1024), None)
+    );
+
+    let len = context
+        .catch_oom(|context| {
+            let mut v = Vec::new_in(context);
+            v.extend_from_slice(&[1u8; 1000]);
+            v.len()
+        })
+        .unwrap();
+    assert_eq!(len, 1000);
+    assert!(context.used() > 0);
+
+    let e = context
+        .catch_oom(|context| {
+            let mut v = Vec::new_in(context);
+            v.extend_from_slice(&[1u8; 128 * 1024]);
+        })
+        .unwrap_err();
+    assert!(e.size() >= 128 * 1024);
+    assert_eq!(context.used(), 0);
+    drop(child);
+}
+
+#[test]
+fn heap_histogram_is_reported_where_the_allocation_fails() {
+    static REPORTED: AtomicUsize = AtomicUsize::new(0);
+    fn report(e: &AllocError, histogram: &HeapHistogram) {
+        if e.size() == isize::MAX as usize / 2 {
+            let (_, count, _) = histogram.classes().find(|&(max, _, _)| max >= 3000).unwrap();
+            REPORTED.store(count, Ordering::Relaxed);
+        }
+    }
+
+    let kept: Vec<_> = (0..10).map(|_| black_box(Vec::<u8>::with_capacity(3000))).collect();
+    assert!(set_heap_histogram_reporter(Some(report)).is_none());
+    assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(isize::MAX as usize / 2))).is_err());
+    set_heap_histogram_reporter(None);
+    assert!(REPORTED.load(Ordering::Relaxed) >= 10);
+    drop(kept);
+}
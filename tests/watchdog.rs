@@ -0,0 +1,30 @@
+#![cfg(target_os = "linux")]
+
+use std::alloc::System;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use panic_safe::{catch_oom, start_rss_watchdog, TrackingAlloc};
+
+#[cfg(not(feature = "stable"))]
+#[global_allocator]
+static GLOBAL: TrackingAlloc<System> = TrackingAlloc::new(System);
+#[cfg(feature = "stable")]
+#[global_allocator]
+static GLOBAL: panic_safe::CatchAlloc<TrackingAlloc<System>> = panic_safe::CatchAlloc::new(TrackingAlloc::new(System));
+
+#[test]
+fn allocations_fail_while_the_threshold_is_exceeded() {
+    let watchdog = start_rss_watchdog(1, Duration::from_millis(1)).unwrap();
+    let start = Instant::now();
+    let e = loop {
+        match catch_oom(|| black_box(Vec::<u8>::with_capacity(1024)).capacity()) {
+            Err(e) => break e,
+            Ok(_) if start.elapsed() < Duration::from_secs(10) => std::thread::sleep(Duration::from_millis(1)),
+            Ok(_) => panic!("the watchdog did not sample the resident set"),
+        }
+    };
+    assert_eq!(e.size(), 1024);
+    drop(watchdog);
+    assert!(catch_oom(|| black_box(Vec::<u8>::with_capacity(1024)).capacity()).is_ok());
+}